@@ -0,0 +1,152 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::config::AdaptiveConfig;
+use crate::metrics::Metrics;
+
+/// AIMD controller gating page fetches: the effective concurrency grows additively while
+/// the recent failure rate stays below the threshold and is cut multiplicatively otherwise.
+pub struct AdaptiveConcurrency {
+    semaphore: Semaphore,
+    limit: AtomicUsize,
+    // permits still to be removed once they are released by in-flight requests
+    debt: AtomicUsize,
+    successes: AtomicUsize,
+    failures: AtomicUsize,
+    config: AdaptiveConfig,
+    max: usize,
+}
+
+pub struct AdaptivePermit<'a> {
+    permit: Option<SemaphorePermit<'a>>,
+    limiter: &'a AdaptiveConcurrency,
+}
+
+impl Drop for AdaptivePermit<'_> {
+    fn drop(&mut self) {
+        if let Some(permit) = self.permit.take() {
+            let paid = self
+                .limiter
+                .debt
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |debt| {
+                    debt.checked_sub(1)
+                })
+                .is_ok();
+            if paid {
+                permit.forget();
+            }
+        }
+    }
+}
+
+impl AdaptiveConcurrency {
+    pub fn new(max: usize, config: AdaptiveConfig) -> Self {
+        AdaptiveConcurrency {
+            semaphore: Semaphore::new(max),
+            limit: AtomicUsize::new(max),
+            debt: AtomicUsize::new(0),
+            successes: AtomicUsize::new(0),
+            failures: AtomicUsize::new(0),
+            config,
+            max,
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    pub async fn acquire(&self) -> AdaptivePermit<'_> {
+        AdaptivePermit {
+            permit: self.semaphore.acquire().await.ok(),
+            limiter: self,
+        }
+    }
+
+    pub fn record(&self, success: bool) {
+        if success {
+            self.successes.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Evaluates the outcomes recorded since the last call and resizes the limit.
+    pub fn adjust(&self) -> usize {
+        let successes = self.successes.load(Ordering::Relaxed);
+        let failures = self.failures.load(Ordering::Relaxed);
+        let samples = successes + failures;
+        if samples < self.config.min_samples {
+            return self.limit();
+        }
+        self.successes.fetch_sub(successes, Ordering::Relaxed);
+        self.failures.fetch_sub(failures, Ordering::Relaxed);
+
+        let current = self.limit();
+        let failure_rate = failures as f64 / samples as f64;
+        let target = if failure_rate > self.config.failure_threshold {
+            // a min_concurrency above crawl.concurrency never raises the limit past it
+            ((current as f64 * self.config.decrease_factor) as usize)
+                .clamp(self.config.min_concurrency.min(self.max), self.max)
+        } else {
+            (current + self.config.increase_step).min(self.max)
+        };
+
+        if target > current {
+            self.grow(target - current);
+        } else if target < current {
+            self.shrink(current - target);
+        }
+        self.limit.store(target, Ordering::Relaxed);
+        target
+    }
+
+    fn grow(&self, mut amount: usize) {
+        // cancel outstanding debt before handing out new permits
+        while amount > 0 {
+            let debt = self.debt.load(Ordering::Acquire);
+            if debt == 0 {
+                break;
+            }
+            let cancelled = debt.min(amount);
+            if self
+                .debt
+                .compare_exchange(debt, debt - cancelled, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                amount -= cancelled;
+            }
+        }
+        if amount > 0 {
+            self.semaphore.add_permits(amount);
+        }
+    }
+
+    fn shrink(&self, amount: usize) {
+        let forgotten = self.semaphore.forget_permits(amount);
+        if forgotten < amount {
+            self.debt.fetch_add(amount - forgotten, Ordering::AcqRel);
+        }
+    }
+
+    pub fn spawn_controller(self: &Arc<Self>, metrics: Arc<Metrics>) {
+        let limiter = self.clone();
+        metrics
+            .effective_concurrency
+            .store(limiter.limit(), Ordering::Relaxed);
+
+        tokio::spawn(async move {
+            let mut interval =
+                tokio::time::interval(Duration::from_secs(limiter.config.window_secs.max(1)));
+            loop {
+                interval.tick().await;
+                let limit = limiter.adjust();
+                metrics
+                    .effective_concurrency
+                    .store(limit, Ordering::Relaxed);
+            }
+        });
+    }
+}
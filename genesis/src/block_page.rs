@@ -0,0 +1,115 @@
+//! Block pages: challenges, captchas and WAF interstitials served in place of the page.
+//!
+//! These are answered with 200 as often as with 403 or 503, so the body is checked too.
+//! Markers are markup that only interstitials carry, never words an article might use, and
+//! generic captcha widgets only count alongside a blocking status or a "prove you're human"
+//! line, since plenty of contact forms embed one.
+
+use std::fmt;
+
+/// Only the start of the body is searched, block pages are small
+const SCAN_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockKind {
+    /// Cloudflare error page, e.g. "Worker threw exception" or a 5xx with a Ray ID
+    CloudflareError,
+    /// Cloudflare JS or managed challenge, "Just a moment..."
+    CloudflareChallenge,
+    /// reCAPTCHA, hCaptcha, Turnstile, DataDome or PerimeterX human check
+    Captcha,
+    /// Block page of another firewall: Imperva, Akamai, Sucuri, F5, Wordfence
+    Waf,
+}
+
+impl fmt::Display for BlockKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            BlockKind::CloudflareError => "Cloudflare error",
+            BlockKind::CloudflareChallenge => "Cloudflare challenge",
+            BlockKind::Captcha => "captcha",
+            BlockKind::Waf => "firewall block page",
+        };
+        f.write_str(kind)
+    }
+}
+
+/// Returned when [`detect_block_page`] recognises a response, so it is retried elsewhere
+/// rather than analysed.
+#[derive(Debug)]
+pub struct Blocked(pub BlockKind);
+
+impl fmt::Display for Blocked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Blocked by a {}", self.0)
+    }
+}
+
+impl std::error::Error for Blocked {}
+
+const CLOUDFLARE_CHALLENGE_MARKERS: &[&str] = &[
+    "__cf_chl_",
+    "cf-chl-",
+    "cf_chl_opt",
+    "/cdn-cgi/challenge-platform/",
+    "<title>just a moment...</title>",
+];
+
+// these services only ever show up on interstitials
+const CAPTCHA_SERVICE_MARKERS: &[&str] = &["captcha-delivery.com", "px-captcha"];
+
+const CAPTCHA_WIDGET_MARKERS: &[&str] = &["g-recaptcha", "h-captcha", "cf-turnstile"];
+
+const HUMAN_CHECK_PHRASES: &[&str] = &[
+    "verify you are human",
+    "verify you are a human",
+    "are you a robot",
+    "unusual traffic from your",
+];
+
+const WAF_MARKERS: &[&str] = &[
+    "_incapsula_resource",
+    "incapsula incident id",
+    "errors.edgesuite.net",
+    "sucuri website firewall",
+    "the requested url was rejected. please consult with your administrator",
+    "generated by wordfence",
+];
+
+/// What kind of block page `text` is, given the response `status`, if any.
+pub fn detect_block_page(text: &str, status: u16) -> Option<BlockKind> {
+    let text = head(text, SCAN_BYTES).to_lowercase();
+    let contains_any = |markers: &[&str]| markers.iter().any(|marker| text.contains(marker));
+
+    let cloudflare = text.contains("cloudflare");
+    // challenges come first, they are usually 503s with a Ray ID too
+    if contains_any(CLOUDFLARE_CHALLENGE_MARKERS)
+        || (cloudflare && text.contains("checking your browser"))
+    {
+        return Some(BlockKind::CloudflareChallenge);
+    }
+    if cloudflare
+        && (text.contains("worker threw exception") || (status >= 500 && text.contains("ray id")))
+    {
+        return Some(BlockKind::CloudflareError);
+    }
+    let blocking_status = matches!(status, 403 | 429 | 503);
+    if contains_any(CAPTCHA_SERVICE_MARKERS)
+        || (contains_any(CAPTCHA_WIDGET_MARKERS)
+            && (blocking_status || contains_any(HUMAN_CHECK_PHRASES)))
+    {
+        return Some(BlockKind::Captcha);
+    }
+    if contains_any(WAF_MARKERS) {
+        return Some(BlockKind::Waf);
+    }
+    None
+}
+
+fn head(text: &str, max_bytes: usize) -> &str {
+    let mut end = max_bytes.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
@@ -0,0 +1,56 @@
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+
+/// How far into the body a `<meta charset>` is looked for, as in the HTML prescan
+const PRESCAN_LEN: usize = 1024;
+
+/// Picks the encoding of an HTML body: a BOM wins, then the `charset` of the
+/// `Content-Type` header, then a `<meta charset>` or `<meta http-equiv>` near the
+/// top of the document, and finally UTF-8.
+pub fn detect_encoding(body: &[u8], content_type: Option<&str>) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(body) {
+        return encoding;
+    }
+    content_type
+        .and_then(content_type_charset)
+        .or_else(|| meta_charset(body))
+        .unwrap_or(UTF_8)
+}
+
+pub fn content_type_charset(content_type: &str) -> Option<&'static Encoding> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (name, value) = param.split_once('=')?;
+        if !name.trim().eq_ignore_ascii_case("charset") {
+            return None;
+        }
+        Encoding::for_label(
+            value
+                .trim()
+                .trim_matches(|c| c == '"' || c == '\'')
+                .as_bytes(),
+        )
+    })
+}
+
+/// Finds the charset declared by a `<meta>` tag within the first bytes of `body`.
+pub fn meta_charset(body: &[u8]) -> Option<&'static Encoding> {
+    let head = &body[..body.len().min(PRESCAN_LEN)];
+    let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+
+    head.match_indices("<meta").find_map(|(start, _)| {
+        let tag = &head[start..];
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+        let value = &tag[tag.find("charset")? + "charset".len()..];
+        let value = value.trim_start().strip_prefix('=')?.trim_start();
+        let value = value.trim_start_matches(['"', '\'']);
+        let end = value
+            .find(|c: char| c == '"' || c == '\'' || c == ';' || c == '/' || c.is_whitespace())
+            .unwrap_or(value.len());
+        let encoding = Encoding::for_label(&value.as_bytes()[..end])?;
+        // a document that could declare itself UTF-16 in ASCII is not UTF-16
+        Some(if encoding == UTF_16LE || encoding == UTF_16BE {
+            UTF_8
+        } else {
+            encoding
+        })
+    })
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::CircuitBreakerConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    Closed,
+    Open { until: Instant },
+    /// A probe was released and is given until `probe_expires` to report back
+    HalfOpen { probe_expires: Instant },
+}
+
+struct DomainCircuit {
+    consecutive_failures: usize,
+    state: CircuitState,
+}
+
+/// Tracks consecutive failures per domain. A domain whose circuit is open has its queued
+/// URLs deferred until the cooldown passes, after which a single probe decides whether
+/// the circuit closes again or reopens. A probe that never reports back, because it was
+/// dropped before being fetched, is replaced once another cooldown has passed.
+pub struct CircuitBreakers {
+    circuits: Mutex<HashMap<String, DomainCircuit>>,
+    failure_threshold: usize,
+    cooldown: Duration,
+}
+
+impl CircuitBreakers {
+    pub fn new(config: &CircuitBreakerConfig) -> Self {
+        CircuitBreakers {
+            circuits: Mutex::new(HashMap::new()),
+            failure_threshold: config.failure_threshold.max(1),
+            cooldown: Duration::from_secs(config.cooldown_secs),
+        }
+    }
+
+    pub fn state(&self, domain: &str) -> CircuitState {
+        self.circuits
+            .lock()
+            .unwrap()
+            .get(domain)
+            .map(|circuit| circuit.state)
+            .unwrap_or(CircuitState::Closed)
+    }
+
+    /// How many URLs of `domain` may be released right now, out of `max`.
+    pub fn allowance(&self, domain: &str, max: usize) -> usize {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = match circuits.get_mut(domain) {
+            Some(circuit) => circuit,
+            None => return max,
+        };
+
+        let now = Instant::now();
+        match circuit.state {
+            CircuitState::Closed => max,
+            CircuitState::Open { until } | CircuitState::HalfOpen { probe_expires: until }
+                if now >= until =>
+            {
+                circuit.state = CircuitState::HalfOpen {
+                    probe_expires: now + self.cooldown,
+                };
+                max.min(1)
+            }
+            CircuitState::Open { .. } | CircuitState::HalfOpen { .. } => 0,
+        }
+    }
+
+    pub fn record_success(&self, domain: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        if let Some(circuit) = circuits.get_mut(domain) {
+            circuit.consecutive_failures = 0;
+            circuit.state = CircuitState::Closed;
+        }
+    }
+
+    pub fn record_failure(&self, domain: &str) {
+        let mut circuits = self.circuits.lock().unwrap();
+        let circuit = circuits
+            .entry(domain.to_string())
+            .or_insert(DomainCircuit {
+                consecutive_failures: 0,
+                state: CircuitState::Closed,
+            });
+        circuit.consecutive_failures += 1;
+
+        let reopen = match circuit.state {
+            CircuitState::HalfOpen { .. } => true,
+            CircuitState::Closed => circuit.consecutive_failures >= self.failure_threshold,
+            CircuitState::Open { .. } => false,
+        };
+        if reopen {
+            circuit.state = CircuitState::Open {
+                until: Instant::now() + self.cooldown,
+            };
+        }
+    }
+}
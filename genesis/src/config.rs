@@ -0,0 +1,620 @@
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::db::BatchFormat;
+use crate::logger::{LogFormat, Rotation};
+use crate::utils::DEFAULT_TRACKING_PARAMS;
+
+const DEFAULT_CONFIG_PATH: &str = "config.toml";
+
+/// Printed in place of secrets by [`Config::to_toml`]
+const REDACTED: &str = "<redacted>";
+
+#[derive(Debug, Parser)]
+#[command(name = "genesis", about = "The crawler behind Vyntr")]
+pub struct Cli {
+    /// Path to a TOML config file (defaults to ./config.toml when present)
+    #[arg(long, env = "GENESIS_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    #[arg(long, env = "CONCURRENCY")]
+    pub concurrency: Option<usize>,
+
+    #[arg(long, env = "MAX_PAGES")]
+    pub max_pages: Option<usize>,
+
+    #[arg(long, env = "BATCH_SIZE")]
+    pub batch_size: Option<usize>,
+
+    #[arg(long, env = "MAX_TUNNEL_RETRIES")]
+    pub max_tunnel_retries: Option<usize>,
+
+    #[arg(long, env = "MAX_FORBIDDEN_RETRIES")]
+    pub max_forbidden_retries: Option<usize>,
+
+    #[arg(long, env = "MAX_DEPTH")]
+    pub max_depth: Option<u32>,
+
+    #[arg(long, env = "MAX_PAGES_PER_DOMAIN")]
+    pub max_pages_per_domain: Option<usize>,
+
+    #[arg(long, env = "MAX_PAGE_BYTES")]
+    pub max_page_bytes: Option<usize>,
+
+    /// Seconds without a successful fetch before `--exit-when-idle` stops the crawl
+    #[arg(long, env = "INACTIVITY_TIMEOUT")]
+    pub inactivity_timeout: Option<u64>,
+
+    /// Finish the crawl once nothing was fetched for the inactivity timeout
+    #[arg(long, env = "EXIT_WHEN_IDLE")]
+    pub exit_when_idle: bool,
+
+    /// Output sink: s3, postgres, jsonl or stdout
+    #[arg(long, env = "SINK")]
+    pub sink: Option<String>,
+
+    /// Fetch and parse pages without writing anything to the sink
+    #[arg(long, env = "DRY_RUN")]
+    pub dry_run: bool,
+
+    /// Fetch and analyse only this URL, print the analysis as JSON and exit
+    #[arg(long)]
+    pub url: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub crawl: CrawlConfig,
+    pub timeouts: TimeoutConfig,
+    pub rate_limit: RateLimitConfig,
+    pub adaptive: AdaptiveConfig,
+    pub circuit_breaker: CircuitBreakerConfig,
+    pub dedup: DedupConfig,
+    pub soft_404: Soft404Config,
+    pub sink: SinkConfig,
+    pub proxy: ProxyConfig,
+    pub tunnel_pool: TunnelPoolConfig,
+    pub domains: DomainConfig,
+    pub logging: LoggingConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CrawlConfig {
+    pub seeds_file: PathBuf,
+    pub concurrency: usize,
+    pub max_pages: usize,
+    pub batch_size: usize,
+    pub max_per_domain: usize,
+    /// Pages crawled from a single host before its remaining URLs are dropped, 0 disables the cap
+    pub max_pages_per_domain: usize,
+    /// Requests allowed in flight to a single host at once, 0 disables the cap
+    pub max_concurrent_per_domain: usize,
+    /// Minimum gap between two requests to the same host, 0 disables the spacing
+    pub politeness_delay_ms: u64,
+    /// Up to this much is randomly added to each gap
+    pub politeness_jitter_ms: u64,
+    pub max_tunnel_retries: usize,
+    /// Times a 403 or block page from a proxy is retried through another proxy, and so another
+    /// fingerprint
+    pub max_forbidden_retries: usize,
+    /// Responses with a larger body are dropped without being parsed, 0 disables the limit
+    pub max_page_bytes: usize,
+    /// Times a rate limited (429) URL is requeued before it is dropped
+    pub max_retry_attempts: u32,
+    /// Base delay for requeued URLs when the site sends no `Retry-After`, doubled per attempt
+    pub retry_backoff_secs: u64,
+    /// Links found more than this many hops away from a seed are not followed, unlimited when unset
+    pub max_depth: Option<u32>,
+    pub inactivity_timeout_secs: u64,
+    /// Shut down gracefully once nothing was fetched for `inactivity_timeout_secs`
+    pub exit_when_idle: bool,
+    /// Where the visited URLs are written when the crawl stops and read back on the next
+    /// start so they are not crawled again, skipped when unset
+    pub visited_file: Option<PathBuf>,
+    /// Where the binary writes its end-of-run summary as JSON, skipped when unset
+    pub report_file: Option<PathBuf>,
+    /// URLs found but not crawled yet are checkpointed here and reloaded on the next start,
+    /// skipped when unset
+    pub frontier_file: Option<PathBuf>,
+    pub frontier_checkpoint_secs: u64,
+    /// Fetch `/sitemap.xml` and `/sitemap_index.xml` the first time a domain is seen
+    pub discover_sitemaps: bool,
+    /// Treat `www.example.com` and `example.com` as one domain for queues, per-domain limits
+    /// and caps, the fetched URL is left as found
+    pub collapse_www: bool,
+    /// Group hosts by registrable domain (eTLD+1) instead, so `a.blogspot.com` and
+    /// `b.blogspot.com` share queues, per-domain limits and caps
+    pub group_by_registrable_domain: bool,
+    /// Log the analyses that would be saved instead of writing them to the sink
+    pub dry_run: bool,
+    /// Also run [`crate::readability`] over each page to fill `main_content`
+    pub extract_main_content: bool,
+    /// Pages with fewer words of extracted text are crawled for links but not saved,
+    /// 0 saves every page
+    pub min_content_words: usize,
+    /// Query parameters stripped from every URL, a trailing `*` matches by prefix
+    pub tracking_params: Vec<String>,
+}
+
+impl CrawlConfig {
+    pub fn page_size_limit(&self) -> Option<usize> {
+        (self.max_page_bytes > 0).then_some(self.max_page_bytes)
+    }
+
+    pub fn retry_backoff(&self) -> Duration {
+        Duration::from_secs(self.retry_backoff_secs)
+    }
+}
+
+impl Default for CrawlConfig {
+    fn default() -> Self {
+        CrawlConfig {
+            seeds_file: PathBuf::from("data/sites.txt"),
+            concurrency: 5_000,
+            max_pages: 50_000,
+            batch_size: 2_000,
+            max_per_domain: 5,
+            max_pages_per_domain: 0,
+            max_concurrent_per_domain: 8,
+            politeness_delay_ms: 500,
+            politeness_jitter_ms: 250,
+            max_tunnel_retries: 2,
+            max_forbidden_retries: 2,
+            max_page_bytes: 10 * 1024 * 1024,
+            max_retry_attempts: 3,
+            retry_backoff_secs: 5,
+            max_depth: None,
+            inactivity_timeout_secs: 60,
+            exit_when_idle: false,
+            visited_file: None,
+            report_file: Some(PathBuf::from("report.json")),
+            frontier_file: None,
+            frontier_checkpoint_secs: 30,
+            discover_sitemaps: true,
+            collapse_www: false,
+            group_by_registrable_domain: false,
+            dry_run: false,
+            extract_main_content: false,
+            min_content_words: 0,
+            tracking_params: DEFAULT_TRACKING_PARAMS
+                .iter()
+                .map(|param| param.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Timeouts of the tunnel and proxy clients. A dead host fails after `connect_secs`,
+/// while a large page that keeps streaming may take up to `request_secs`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeoutConfig {
+    /// Whole request, from connecting to the last byte of the body
+    pub request_secs: u64,
+    /// Establishing the connection, 0 leaves it to `request_secs`
+    pub connect_secs: u64,
+    /// Longest wait for the next chunk of a response, 0 leaves it to `request_secs`
+    pub read_secs: u64,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        TimeoutConfig {
+            request_secs: 60,
+            connect_secs: 5,
+            read_secs: 20,
+        }
+    }
+}
+
+impl TimeoutConfig {
+    pub fn request(&self) -> Duration {
+        Duration::from_secs(self.request_secs)
+    }
+
+    pub fn connect(&self) -> Option<Duration> {
+        (self.connect_secs > 0).then(|| Duration::from_secs(self.connect_secs))
+    }
+
+    pub fn read(&self) -> Option<Duration> {
+        (self.read_secs > 0).then(|| Duration::from_secs(self.read_secs))
+    }
+
+    /// Sets the timeouts on a client being built.
+    pub fn apply(&self, builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        let builder = builder.timeout(self.request());
+        let builder = match self.connect() {
+            Some(connect) => builder.connect_timeout(connect),
+            None => builder,
+        };
+        match self.read() {
+            Some(read) => builder.read_timeout(read),
+            None => builder,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Global cap on page fetches per second, 0 disables it
+    pub requests_per_second: u32,
+}
+
+/// AIMD tuning for the effective concurrency, which starts at and never exceeds `crawl.concurrency`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AdaptiveConfig {
+    pub enabled: bool,
+    pub min_concurrency: usize,
+    pub increase_step: usize,
+    pub decrease_factor: f64,
+    /// Failure rate over a window above which concurrency is cut
+    pub failure_threshold: f64,
+    pub window_secs: u64,
+    /// Windows with fewer outcomes than this are carried over to the next one
+    pub min_samples: usize,
+}
+
+impl Default for AdaptiveConfig {
+    fn default() -> Self {
+        AdaptiveConfig {
+            enabled: true,
+            min_concurrency: 50,
+            increase_step: 100,
+            decrease_factor: 0.5,
+            failure_threshold: 0.5,
+            window_secs: 5,
+            min_samples: 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CircuitBreakerConfig {
+    pub enabled: bool,
+    /// Consecutive failures after which a domain's circuit opens
+    pub failure_threshold: usize,
+    pub cooldown_secs: u64,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            enabled: true,
+            failure_threshold: 5,
+            cooldown_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DedupConfig {
+    pub enabled: bool,
+    /// Number of recently saved content hashes remembered
+    pub capacity: usize,
+    /// Pages whose SimHash differs from a remembered one by at most this many bits are skipped
+    pub max_distance: u32,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        DedupConfig {
+            enabled: true,
+            capacity: 10_000,
+            max_distance: 6,
+        }
+    }
+}
+
+/// Patterns for [`crate::soft404::is_soft_404`], matched case-insensitively
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Soft404Config {
+    pub enabled: bool,
+    /// A page of at most `max_content_chars` whose title contains one of these is an error page
+    pub title_patterns: Vec<String>,
+    /// So is one whose text contains one of these
+    pub content_patterns: Vec<String>,
+    pub max_content_chars: usize,
+}
+
+impl Default for Soft404Config {
+    fn default() -> Self {
+        let strings = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect();
+        Soft404Config {
+            enabled: true,
+            title_patterns: strings(&["404", "page not found", "page does not exist"]),
+            content_patterns: strings(&[
+                "page not found",
+                "could not be found",
+                "couldn't be found",
+                "page does not exist",
+                "page doesn't exist",
+                "no longer available",
+                "error 404",
+            ]),
+            max_content_chars: 1_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SinkConfig {
+    pub kind: String,
+    pub jsonl_dir: PathBuf,
+    /// `jsonl` (default) or `parquet` for batches written by the S3 and JSONL sinks
+    pub format: BatchFormat,
+    /// Write S3 and JSONL batches zstd compressed, as `.jsonl.zst`. Parquet batches are
+    /// compressed inside the file instead.
+    pub compress: bool,
+    /// Also store each page's raw HTML, zstd compressed, under `raw/`. Off by default since it
+    /// takes several times the space of the analyses.
+    pub archive_raw_html: bool,
+}
+
+impl Default for SinkConfig {
+    fn default() -> Self {
+        SinkConfig {
+            kind: "s3".to_string(),
+            jsonl_dir: PathBuf::from("analyses"),
+            format: BatchFormat::Jsonl,
+            compress: true,
+            archive_raw_html: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProxyConfig {
+    pub file: PathBuf,
+    /// Falls back to the `PROXY_TUNNEL_URL` environment variable when unset
+    pub tunnel_url: Option<String>,
+    /// Give every proxy client its own cookie store, so cookies a site sets are sent back
+    /// on later requests through the same proxy
+    pub cookies: bool,
+    /// User-Agent the tunnel client sends to the tunnel endpoint itself
+    pub tunnel_user_agent: String,
+    /// Extra headers sent with every tunnel request
+    pub tunnel_headers: BTreeMap<String, String>,
+    /// Username sent to the proxy, built from the proxy file's `{username}` and a
+    /// `{session}` token, e.g. `{username}-session-{session}` for gateways that keep the exit
+    /// IP for as long as the session token stays the same
+    pub username_template: Option<String>,
+    /// Same as `username_template`, for providers that take the session in the password
+    pub password_template: Option<String>,
+    /// Picks of a proxy before it gets a new session token, 0 keeps one session per run
+    pub session_requests: usize,
+}
+
+/// A current desktop Chrome, so the tunnel hop does not announce itself as reqwest
+pub const DEFAULT_TUNNEL_USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) \
+    AppleWebKit/537.36 (KHTML, like Gecko) Chrome/132.0.0.0 Safari/537.36";
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        ProxyConfig {
+            file: PathBuf::from("data/proxies.txt"),
+            tunnel_url: None,
+            cookies: false,
+            tunnel_user_agent: DEFAULT_TUNNEL_USER_AGENT.to_string(),
+            tunnel_headers: BTreeMap::new(),
+            username_template: None,
+            password_template: None,
+            session_requests: 10,
+        }
+    }
+}
+
+/// Connection pool tuning for the tunnel client
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TunnelPoolConfig {
+    /// Idle keep-alive connections kept per host, should be close to `crawl.concurrency`
+    pub max_idle_per_host: usize,
+    pub idle_timeout_secs: u64,
+    /// TCP keepalive interval, disabled when 0
+    pub tcp_keepalive_secs: u64,
+    /// Let HTTP/2 connections grow their flow control window with the measured bandwidth
+    pub http2_adaptive_window: bool,
+}
+
+impl Default for TunnelPoolConfig {
+    fn default() -> Self {
+        TunnelPoolConfig {
+            max_idle_per_host: 512,
+            idle_timeout_secs: 90,
+            tcp_keepalive_secs: 60,
+            http2_adaptive_window: false,
+        }
+    }
+}
+
+/// Hosts may be given exactly or as `*.example.com` wildcards, see [`crate::domain_filter`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DomainConfig {
+    /// When non-empty, only these hosts are crawled
+    pub allow: Vec<String>,
+    /// Never crawled, even when also allowed
+    pub block: Vec<String>,
+    /// Extra allow patterns, one per line, ignored when the file is missing
+    pub allow_file: Option<PathBuf>,
+    /// Extra block patterns, one per line, ignored when the file is missing
+    pub block_file: Option<PathBuf>,
+}
+
+impl Default for DomainConfig {
+    fn default() -> Self {
+        DomainConfig {
+            allow: Vec::new(),
+            block: Vec::new(),
+            allow_file: Some(PathBuf::from("data/allow.txt")),
+            block_file: Some(PathBuf::from("data/block.txt")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// `text` for timestamped lines or `json` for one object per line
+    pub format: LogFormat,
+    /// Size at which the log file is rotated, 0 disables rotation
+    pub max_file_mb: u64,
+    /// Rotated files kept next to the current one, the oldest is deleted first
+    pub max_files: usize,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            format: LogFormat::Text,
+            max_file_mb: 100,
+            max_files: 5,
+        }
+    }
+}
+
+impl LoggingConfig {
+    pub fn rotation(&self) -> Option<Rotation> {
+        (self.max_file_mb > 0).then(|| Rotation {
+            max_bytes: self.max_file_mb * 1024 * 1024,
+            max_files: self.max_files,
+        })
+    }
+}
+
+impl Config {
+    pub fn from_file(path: &Path) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// Resolves the config from, in increasing precedence: defaults, the TOML file,
+    /// environment variables and CLI flags.
+    pub fn load(cli: &Cli) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let mut config = match &cli.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_PATH).exists() => {
+                Self::from_file(Path::new(DEFAULT_CONFIG_PATH))?
+            }
+            None => Self::default(),
+        };
+
+        if let Ok(params) = std::env::var("TRACKING_PARAMS") {
+            config.crawl.tracking_params = params
+                .split(',')
+                .map(|param| param.trim().to_string())
+                .filter(|param| !param.is_empty())
+                .collect();
+        }
+        if let Ok(tunnel_url) = std::env::var("PROXY_TUNNEL_URL") {
+            config.proxy.tunnel_url = Some(tunnel_url);
+        }
+        if let Ok(cookies) = std::env::var("PROXY_COOKIES") {
+            config.proxy.cookies = cookies.parse()?;
+        }
+        if let Ok(user_agent) = std::env::var("PROXY_TUNNEL_USER_AGENT") {
+            config.proxy.tunnel_user_agent = user_agent;
+        }
+        if let Ok(template) = std::env::var("PROXY_USERNAME_TEMPLATE") {
+            config.proxy.username_template = Some(template);
+        }
+        if let Ok(request_timeout) = std::env::var("REQUEST_TIMEOUT") {
+            config.timeouts.request_secs = request_timeout.parse()?;
+        }
+        if let Ok(connect_timeout) = std::env::var("CONNECT_TIMEOUT") {
+            config.timeouts.connect_secs = connect_timeout.parse()?;
+        }
+        if let Ok(read_timeout) = std::env::var("READ_TIMEOUT") {
+            config.timeouts.read_secs = read_timeout.parse()?;
+        }
+        if let Ok(jsonl_dir) = std::env::var("JSONL_DIR") {
+            config.sink.jsonl_dir = PathBuf::from(jsonl_dir);
+        }
+        if let Ok(format) = std::env::var("SINK_FORMAT") {
+            config.sink.format = format.parse()?;
+        }
+        if let Ok(archive) = std::env::var("ARCHIVE_RAW_HTML") {
+            config.sink.archive_raw_html = archive.parse()?;
+        }
+        if let Ok(max_idle) = std::env::var("TUNNEL_POOL_MAX_IDLE_PER_HOST") {
+            config.tunnel_pool.max_idle_per_host = max_idle.parse()?;
+        }
+        if let Ok(idle_timeout) = std::env::var("TUNNEL_POOL_IDLE_TIMEOUT") {
+            config.tunnel_pool.idle_timeout_secs = idle_timeout.parse()?;
+        }
+        if let Ok(keepalive) = std::env::var("TUNNEL_TCP_KEEPALIVE") {
+            config.tunnel_pool.tcp_keepalive_secs = keepalive.parse()?;
+        }
+        if let Ok(adaptive_window) = std::env::var("TUNNEL_HTTP2_ADAPTIVE_WINDOW") {
+            config.tunnel_pool.http2_adaptive_window = adaptive_window.parse()?;
+        }
+
+        if let Some(concurrency) = cli.concurrency {
+            config.crawl.concurrency = concurrency;
+        }
+        if let Some(max_pages) = cli.max_pages {
+            config.crawl.max_pages = max_pages;
+        }
+        if let Some(batch_size) = cli.batch_size {
+            config.crawl.batch_size = batch_size;
+        }
+        if let Some(max_tunnel_retries) = cli.max_tunnel_retries {
+            config.crawl.max_tunnel_retries = max_tunnel_retries;
+        }
+        if let Some(max_forbidden_retries) = cli.max_forbidden_retries {
+            config.crawl.max_forbidden_retries = max_forbidden_retries;
+        }
+        if let Some(max_depth) = cli.max_depth {
+            config.crawl.max_depth = Some(max_depth);
+        }
+        if let Some(max_pages_per_domain) = cli.max_pages_per_domain {
+            config.crawl.max_pages_per_domain = max_pages_per_domain;
+        }
+        if let Some(max_page_bytes) = cli.max_page_bytes {
+            config.crawl.max_page_bytes = max_page_bytes;
+        }
+        if let Some(inactivity_timeout) = cli.inactivity_timeout {
+            config.crawl.inactivity_timeout_secs = inactivity_timeout;
+        }
+        if let Some(sink) = &cli.sink {
+            config.sink.kind = sink.clone();
+        }
+        if cli.exit_when_idle {
+            config.crawl.exit_when_idle = true;
+        }
+        if cli.dry_run {
+            config.crawl.dry_run = true;
+        }
+
+        Ok(config)
+    }
+
+    /// The config as TOML for printing at startup, with the tunnel URL and the proxy
+    /// credential templates masked since they carry credentials.
+    pub fn to_toml(&self) -> String {
+        let mut redacted = self.clone();
+        let proxy = &mut redacted.proxy;
+        for secret in [
+            &mut proxy.tunnel_url,
+            &mut proxy.username_template,
+            &mut proxy.password_template,
+        ] {
+            if secret.is_some() {
+                *secret = Some(REDACTED.to_string());
+            }
+        }
+        toml::to_string_pretty(&redacted).unwrap_or_default()
+    }
+}
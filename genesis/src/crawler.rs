@@ -1,12 +1,110 @@
-use std::collections::{HashMap, VecDeque};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::StreamExt;
+use publicsuffix::{IcannList, Psl};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, Mutex, Semaphore};
+use tokio_stream::wrappers::UnboundedReceiverStream;
 use url::Url;
 
+use crate::adaptive::AdaptiveConcurrency;
+use crate::block_page::{detect_block_page, Blocked};
+use crate::circuit::CircuitBreakers;
+use crate::config::{Config, CrawlConfig};
+use crate::db::SeoAnalysis;
+use crate::{debug_only, status};
+use crate::dedup::{simhash, ContentHasher};
+use crate::domain_filter::DomainFilter;
+use crate::error::CrawlError;
+use crate::fingerprint::RequestFingerprint;
+use crate::frontier::{self, Frontier};
+use crate::html_parser::{self, RobotsMeta};
+use crate::lang_detect::detect_language;
+use crate::limiter::{DomainLimiter, DomainPageCap, DomainSpacing, PageSlot};
+use crate::logger::{AsyncLogger, LogEvent};
+use crate::metrics::Metrics;
+use crate::network::{try_tunnel_request, FetchedPage, RateLimited};
+use crate::proxy::ProxyManager;
+use crate::readability;
+use crate::sink::Sink;
+use crate::sitemap::fetch_sitemap;
+use crate::soft404::{self, SoftNotFound};
+use crate::utils::{normalize_url, print_request_status};
+
+const DB_CONCURRENCY: usize = 20;
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(600);
+/// Priority of a seed. Each hop away from it lowers the priority by one, so seeds and their
+/// direct links are fetched before anything deeper.
+pub const SEED_PRIORITY: u32 = 2;
+
+lazy_static::lazy_static! {
+    // ICANN rules only, so hosted subdomains like `a.blogspot.com` group under `blogspot.com`
+    static ref PUBLIC_SUFFIXES: IcannList = include_str!("public_suffix_list.dat")
+        .parse()
+        .expect("bundled public suffix list should parse");
+}
+
+/// A URL in the crawl frontier along with how many links away from a seed it was found.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrawlItem {
+    pub url: String,
+    pub depth: u32,
+    /// Number of times this URL has been requeued after being rate limited
+    pub attempt: u32,
+    /// Items with a higher priority leave their domain's queue first, see [`SEED_PRIORITY`]
+    #[serde(default)]
+    pub priority: u32,
+}
+
+impl CrawlItem {
+    pub fn seed(url: impl Into<String>) -> Self {
+        CrawlItem {
+            url: url.into(),
+            depth: 0,
+            attempt: 0,
+            priority: SEED_PRIORITY,
+        }
+    }
+
+    pub fn child(&self, url: impl Into<String>) -> Self {
+        CrawlItem {
+            url: url.into(),
+            depth: self.depth + 1,
+            attempt: 0,
+            priority: self.priority.saturating_sub(1),
+        }
+    }
+
+    pub fn retry(&self) -> Self {
+        CrawlItem {
+            url: self.url.clone(),
+            depth: self.depth,
+            attempt: self.attempt + 1,
+            priority: self.priority,
+        }
+    }
+}
+
 pub struct DomainQueues {
-    queues: HashMap<String, VecDeque<String>>,
+    queues: HashMap<String, VecDeque<CrawlItem>>,
     order: Vec<String>,
     pub total: usize,
 }
 
+impl Default for DomainQueues {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DomainQueues {
     pub fn new() -> Self {
         Self {
@@ -16,24 +114,43 @@ impl DomainQueues {
         }
     }
 
-    pub fn add(&mut self, domain: String, url: String) {
+    /// Queues `url` under `domain`, behind every queued item of the same or a higher
+    /// priority. Returns `true` if the domain had not been seen before.
+    pub fn add(&mut self, domain: String, item: CrawlItem) -> bool {
+        let mut is_new = false;
         let queue = self.queues.entry(domain.clone()).or_insert_with(|| {
             self.order.push(domain);
+            is_new = true;
             VecDeque::new()
         });
-        queue.push_back(url);
+        let position = queue.partition_point(|queued| queued.priority >= item.priority);
+        queue.insert(position, item);
         self.total += 1;
+        is_new
     }
 
-    pub fn collect_batch(&mut self, max_per_domain: usize) -> Vec<String> {
+    pub fn collect_batch(&mut self, max_per_domain: usize) -> Vec<CrawlItem> {
+        self.collect_batch_with(|_| max_per_domain)
+    }
+
+    /// Like `collect_batch`, but asks `allowance` how many URLs each domain may release.
+    /// URLs that are not released stay queued for a later batch. Each domain releases its
+    /// highest priority URLs first and the batch is ordered by priority.
+    pub fn collect_batch_with<F>(&mut self, mut allowance: F) -> Vec<CrawlItem>
+    where
+        F: FnMut(&str) -> usize,
+    {
         let mut batch = Vec::new();
 
         for domain in &self.order {
             if let Some(queue) = self.queues.get_mut(domain) {
-                let take = std::cmp::min(queue.len(), max_per_domain);
+                if queue.is_empty() {
+                    continue;
+                }
+                let take = std::cmp::min(queue.len(), allowance(domain));
                 for _ in 0..take {
-                    if let Some(url) = queue.pop_front() {
-                        batch.push(url);
+                    if let Some(item) = queue.pop_front() {
+                        batch.push(item);
                         self.total -= 1;
                     }
                 }
@@ -44,12 +161,999 @@ impl DomainQueues {
             self.order.rotate_left(1);
         }
 
+        batch.sort_by_key(|item| Reverse(item.priority));
         batch
     }
 }
 
-pub fn extract_domain(url: &str) -> Result<String, Box<dyn std::error::Error>> {
+pub fn extract_domain(url: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
     let parsed = Url::parse(url)?;
     let domain = parsed.host_str().ok_or("URL has no host")?.to_string();
     Ok(domain)
 }
+
+/// Registrable domain (eTLD+1) of a URL's host, so `a.example.co.uk` and `b.example.co.uk`
+/// both give `example.co.uk`. IP addresses and hosts that are themselves a public suffix are
+/// returned unchanged.
+pub fn registrable_domain(url: &str) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let parsed = Url::parse(url)?;
+    let host = match parsed.host().ok_or("URL has no host")? {
+        url::Host::Domain(domain) => domain,
+        ip => return Ok(ip.to_string()),
+    };
+    Ok(match PUBLIC_SUFFIXES.domain(host.as_bytes()) {
+        Some(domain) => String::from_utf8_lossy(domain.as_bytes()).into_owned(),
+        None => host.to_string(),
+    })
+}
+
+/// Key a URL is queued, limited and capped under: its host, the host without a leading
+/// `www.` with `collapse_www`, or its registrable domain with `group_by_registrable_domain`.
+/// The URL that gets fetched keeps its host either way.
+pub fn domain_key(
+    url: &str,
+    config: &CrawlConfig,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    if config.group_by_registrable_domain {
+        return registrable_domain(url);
+    }
+    let domain = extract_domain(url)?;
+    match domain.strip_prefix("www.") {
+        Some(bare) if config.collapse_www => Ok(bare.to_string()),
+        _ => Ok(domain),
+    }
+}
+
+pub struct CrawlerBuilder {
+    seeds: Vec<String>,
+    config: Config,
+    proxy_manager: Option<ProxyManager>,
+    sink: Option<Arc<dyn Sink>>,
+    logger: Option<Arc<Mutex<AsyncLogger>>>,
+    metrics: Option<Arc<Metrics>>,
+    shutdown: Option<Arc<AtomicBool>>,
+}
+
+impl CrawlerBuilder {
+    pub fn seeds<I, S>(mut self, seeds: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.seeds.extend(seeds.into_iter().map(Into::into));
+        self
+    }
+
+    /// Replaces the whole configuration; call before the individual setters below.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.config.crawl.concurrency = concurrency;
+        self
+    }
+
+    pub fn max_pages(mut self, max_pages: usize) -> Self {
+        self.config.crawl.max_pages = max_pages;
+        self
+    }
+
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.config.crawl.batch_size = batch_size;
+        self
+    }
+
+    pub fn proxy_manager(mut self, proxy_manager: ProxyManager) -> Self {
+        self.proxy_manager = Some(proxy_manager);
+        self
+    }
+
+    /// Analyses are saved here in batches of `batch_size`. Without a sink they are
+    /// only emitted on the stream returned by [`Crawler::crawl`].
+    pub fn sink(mut self, sink: Arc<dyn Sink>) -> Self {
+        self.sink = Some(sink);
+        self
+    }
+
+    pub fn logger(mut self, logger: Arc<Mutex<AsyncLogger>>) -> Self {
+        self.logger = Some(logger);
+        self
+    }
+
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Setting this flag stops the crawl from taking new URLs. Pages already being
+    /// fetched are finished and saved before [`Crawler::crawl`]'s stream ends.
+    pub fn shutdown(mut self, shutdown: Arc<AtomicBool>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    pub fn build(self) -> Crawler {
+        let proxy_manager = self
+            .proxy_manager
+            .unwrap_or_else(|| ProxyManager::with_proxies(Vec::new(), &self.config));
+
+        Crawler {
+            seeds: self.seeds,
+            config: Arc::new(self.config),
+            proxy_manager,
+            sink: self.sink,
+            logger: self.logger,
+            metrics: self.metrics.unwrap_or_default(),
+            shutdown: self.shutdown.unwrap_or_default(),
+        }
+    }
+}
+
+pub struct Crawler {
+    seeds: Vec<String>,
+    config: Arc<Config>,
+    proxy_manager: ProxyManager,
+    sink: Option<Arc<dyn Sink>>,
+    logger: Option<Arc<Mutex<AsyncLogger>>>,
+    metrics: Arc<Metrics>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl Crawler {
+    pub fn builder() -> CrawlerBuilder {
+        CrawlerBuilder {
+            seeds: Vec::new(),
+            config: Config::default(),
+            proxy_manager: None,
+            sink: None,
+            logger: None,
+            metrics: None,
+            shutdown: None,
+        }
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    pub fn shutdown_handle(&self) -> Arc<AtomicBool> {
+        self.shutdown.clone()
+    }
+
+    /// Spawns the crawl onto the current runtime and returns every successfully
+    /// parsed page as it is produced. The raw body is left to the sink, so streamed
+    /// pages never carry `raw_html` when one is set.
+    pub fn crawl(self) -> UnboundedReceiverStream<SeoAnalysis> {
+        let (analysis_tx, analysis_rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            if let Err(e) = self.run(analysis_tx).await {
+                eprintln!("Crawl error: {:?}", e);
+            }
+        });
+        UnboundedReceiverStream::new(analysis_rx)
+    }
+
+    async fn run(
+        self,
+        analysis_tx: mpsc::UnboundedSender<SeoAnalysis>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let Crawler {
+            seeds,
+            config,
+            proxy_manager,
+            sink,
+            logger,
+            metrics,
+            shutdown,
+        } = self;
+        let concurrency = config.crawl.concurrency;
+        let max_pages = config.crawl.max_pages;
+        let batch_size = config.crawl.batch_size;
+
+        let rate_limiter = match config.rate_limit.requests_per_second {
+            0 => None,
+            rps => {
+                let mut interval =
+                    tokio::time::interval(Duration::from_secs_f64(1.0 / rps as f64));
+                interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                Some(Arc::new(Mutex::new(interval)))
+            }
+        };
+
+        let breakers = config
+            .circuit_breaker
+            .enabled
+            .then(|| Arc::new(CircuitBreakers::new(&config.circuit_breaker)));
+
+        let content_hasher = config
+            .dedup
+            .enabled
+            .then(|| Arc::new(ContentHasher::from_config(&config.dedup)));
+
+        let domain_limiter = match config.crawl.max_concurrent_per_domain {
+            0 => None,
+            max => Some(Arc::new(DomainLimiter::new(max))),
+        };
+
+        let domain_spacing = match config.crawl.politeness_delay_ms {
+            0 => None,
+            delay => Some(Arc::new(DomainSpacing::new(
+                Duration::from_millis(delay),
+                Duration::from_millis(config.crawl.politeness_jitter_ms),
+            ))),
+        };
+
+        let domain_filter = DomainFilter::from_config(&config.domains)?;
+        let page_cap = match config.crawl.max_pages_per_domain {
+            0 => None,
+            max => Some(Arc::new(DomainPageCap::new(max))),
+        };
+
+        let visited = Arc::new(Mutex::new(HashSet::new()));
+        let pages_count = Arc::new(AtomicUsize::new(0));
+        let db_semaphore = Arc::new(Semaphore::new(DB_CONCURRENCY));
+        let pending_analyses = Arc::new(Mutex::new(Vec::new()));
+        let frontier = Arc::new(Frontier::new());
+
+        let (discovered_tx, mut discovered_rx) = mpsc::unbounded_channel::<CrawlItem>();
+        let (processing_tx, processing_rx) = mpsc::unbounded_channel::<CrawlItem>();
+        let discovered_tx = Arc::new(discovered_tx);
+
+        let batch_task = tokio::spawn({
+            let config = config.clone();
+            let breakers = breakers.clone();
+            let proxy_manager = proxy_manager.clone();
+            let visited = visited.clone();
+            let metrics = metrics.clone();
+            let discovered_tx = discovered_tx.clone();
+            let page_cap = page_cap.clone();
+            let frontier = frontier.clone();
+            async move {
+                let mut domain_queues = DomainQueues::new();
+                let mut rng = StdRng::from_os_rng();
+                let mut interval = tokio::time::interval(Duration::from_secs(1));
+
+                loop {
+                    tokio::select! {
+                        Some(item) = discovered_rx.recv() => {
+                            // added back under its normalized url if it gets queued
+                            frontier.remove(&item.url);
+                            let normalized_url = match normalize_url(&item.url, &config.crawl.tracking_params) {
+                                Ok(url) => url,
+                                Err(_) => continue,
+                            };
+                            let host = match extract_domain(&normalized_url) {
+                                Ok(d) => d,
+                                Err(_) => continue,
+                            };
+                            if !domain_filter.allows(&host) {
+                                continue;
+                            }
+                            let domain = match domain_key(&normalized_url, &config.crawl) {
+                                Ok(d) => d,
+                                Err(_) => continue,
+                            };
+                            if page_cap.as_ref().is_some_and(|cap| cap.is_capped(&domain)) {
+                                continue;
+                            }
+                            let depth = item.depth;
+                            let item = CrawlItem { url: normalized_url, ..item };
+                            frontier.insert(&item);
+                            let is_new_domain = domain_queues.add(domain, item);
+                            if is_new_domain
+                                && config.crawl.discover_sitemaps
+                                && config.crawl.max_depth.is_none_or(|max_depth| depth < max_depth)
+                            {
+                                spawn_sitemap_discovery(
+                                    host,
+                                    depth + 1,
+                                    proxy_manager.clone(),
+                                    visited.clone(),
+                                    metrics.clone(),
+                                    frontier.clone(),
+                                    discovered_tx.clone(),
+                                );
+                            }
+
+                            if domain_queues.total >= batch_size {
+                                let batch = collect_batch(&mut domain_queues, &config, breakers.as_deref(), &mut rng);
+                                for item in batch {
+                                    if processing_tx.send(item).is_err() {
+                                        return;
+                                    }
+                                }
+                            }
+                        },
+                        _ = interval.tick() => {
+                            if domain_queues.total > 0 {
+                                let batch = collect_batch(&mut domain_queues, &config, breakers.as_deref(), &mut rng);
+                                for item in batch {
+                                    let _ = processing_tx.send(item);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        });
+
+        {
+            let mut visited_lock = visited.lock().await;
+            if let Some(path) = &config.crawl.frontier_file {
+                let restored = frontier::load(path)?;
+                if !restored.is_empty() {
+                    status!(
+                        "Restored {} pending URLs from {}",
+                        restored.len(),
+                        path.display()
+                    );
+                }
+                for item in restored {
+                    // counted once here, a seed or link repeating it is already visited
+                    if visited_lock.insert(item.url.clone()) {
+                        metrics.total_left.fetch_add(1, Ordering::Relaxed);
+                        frontier.insert(&item);
+                        discovered_tx
+                            .send(item)
+                            .expect("Failed to enqueue restored URL");
+                    }
+                }
+            }
+            // loaded after the frontier so URLs that were still pending get requeued
+            if let Some(path) = &config.crawl.visited_file {
+                let previous = load_visited(path)?;
+                if !previous.is_empty() {
+                    status!(
+                        "Loaded {} visited URLs from {}",
+                        previous.len(),
+                        path.display()
+                    );
+                }
+                visited_lock.extend(previous);
+            }
+            for seed in seeds {
+                if visited_lock.insert(seed.clone()) {
+                    let item = CrawlItem::seed(seed);
+                    frontier.insert(&item);
+                    discovered_tx
+                        .send(item)
+                        .expect("Failed to enqueue seed URL");
+                }
+            }
+        }
+
+        let checkpoint_task = config.crawl.frontier_file.clone().map(|path| {
+            let frontier = frontier.clone();
+            let period = Duration::from_secs(config.crawl.frontier_checkpoint_secs.max(1));
+            tokio::spawn(async move {
+                let start = tokio::time::Instant::now() + period;
+                let mut interval = tokio::time::interval_at(start, period);
+                loop {
+                    interval.tick().await;
+                    if let Err(e) = frontier.save(&path) {
+                        eprintln!("Frontier checkpoint error: {:?}", e);
+                    }
+                }
+            })
+        });
+
+        let adaptive = config.adaptive.enabled.then(|| {
+            let limiter = Arc::new(AdaptiveConcurrency::new(
+                concurrency,
+                config.adaptive.clone(),
+            ));
+            limiter.spawn_controller(metrics.clone());
+            limiter
+        });
+
+        status!("Starting crawl with limit of {} pages...", max_pages);
+
+        UnboundedReceiverStream::new(processing_rx)
+            .take_until(wait_for_shutdown(shutdown.clone()))
+            .for_each_concurrent(concurrency, |item| {
+                let sink = sink.clone();
+                let proxy_manager = proxy_manager.clone();
+                let visited = visited.clone();
+                let pages_count = pages_count.clone();
+                let discovered_tx = discovered_tx.clone();
+                let pending_analyses = pending_analyses.clone();
+                let logger = logger.clone();
+                let metrics = metrics.clone();
+                let analysis_tx = analysis_tx.clone();
+                let db_semaphore = db_semaphore.clone();
+                let rate_limiter = rate_limiter.clone();
+                let adaptive = adaptive.clone();
+                let breakers = breakers.clone();
+                let content_hasher = content_hasher.clone();
+                let domain_limiter = domain_limiter.clone();
+                let domain_spacing = domain_spacing.clone();
+                let page_cap = page_cap.clone();
+                let frontier = frontier.clone();
+                let config = config.clone();
+
+                async move {
+                    // URLs queued before their domain was capped are dropped here
+                    if let (Some(cap), Ok(domain)) = (&page_cap, domain_key(&item.url, &config.crawl)) {
+                        match cap.take(&domain) {
+                            PageSlot::Granted => {}
+                            PageSlot::Last => {
+                                metrics.capped_domains.fetch_add(1, Ordering::Relaxed);
+                            }
+                            PageSlot::Capped => {
+                                frontier.remove(&item.url);
+                                return;
+                            }
+                        }
+                    }
+                    let current_count = pages_count.fetch_add(1, Ordering::Relaxed) + 1;
+                    if current_count > max_pages {
+                        return;
+                    }
+                    let url = item.url.clone();
+                    metrics.max_depth.fetch_max(item.depth, Ordering::Relaxed);
+
+                    if let Some(rate_limiter) = rate_limiter {
+                        rate_limiter.lock().await.tick().await;
+                    }
+
+                    let permit = match &adaptive {
+                        Some(limiter) => Some(limiter.acquire().await),
+                        None => None,
+                    };
+                    let result = process_page(
+                        &url,
+                        &config,
+                        &proxy_manager,
+                        domain_limiter.as_deref(),
+                        domain_spacing.as_deref(),
+                        &metrics,
+                    )
+                    .await;
+                    // timeouts, dropped connections, 429s, 403s, block pages and 5xx pages slow us down
+                    let served = match &result {
+                        Ok((_, analysis)) => analysis.status_code < 500,
+                        Err(e) => !e.is_host_failure(),
+                    };
+                    if let Some(limiter) = &adaptive {
+                        limiter.record(served);
+                    }
+                    if let (Some(breakers), Ok(domain)) = (&breakers, domain_key(&url, &config.crawl)) {
+                        if served {
+                            breakers.record_success(&domain);
+                        } else {
+                            breakers.record_failure(&domain);
+                        }
+                    }
+                    drop(permit);
+                    // rate limited URLs are added back when they are requeued
+                    frontier.remove(&item.url);
+
+                    match result {
+                        Ok((child_links, mut analysis)) => {
+                            // decrease total_left since we processed one
+                            metrics.total_left.fetch_sub(1, Ordering::Relaxed);
+
+                            debug_only! { status!("[DEBUG] Extracted {} links from {}", child_links.len(), url) }
+
+                            // a redirect target that is already known gets indexed on its own
+                            let redirected_to_visited = analysis.url != item.url
+                                && !visited.lock().await.insert(analysis.url.clone());
+                            let noindex = analysis.robots.noindex;
+                            // nav-only pages are not worth saving but still pass their links on
+                            let thin = !noindex
+                                && analysis.content_text.split_whitespace().count()
+                                    < config.crawl.min_content_words;
+                            let duplicate = !noindex
+                                && !thin
+                                && (redirected_to_visited
+                                    || match &content_hasher {
+                                        Some(hasher) if !analysis.content_text.is_empty() => {
+                                            hasher.is_duplicate(analysis.content_hash)
+                                        }
+                                        _ => false,
+                                    });
+                            if noindex {
+                                debug_only! { status!("[DEBUG] Not saving noindex page {}", url) }
+                            } else if thin {
+                                metrics.thin_pages.fetch_add(1, Ordering::Relaxed);
+                                debug_only! { status!("[DEBUG] Not saving thin page {}", url) }
+                            } else if duplicate {
+                                debug_only! { status!("[DEBUG] Skipping near-duplicate page {}", url) }
+                            }
+
+                            let saved = !noindex && !thin && !duplicate;
+                            if (sink.is_some() || config.crawl.dry_run) && saved {
+                                // only the sink archives the raw body, so the streamed copy goes without it
+                                let raw_html = analysis.raw_html.take();
+                                let _ = analysis_tx.send(analysis.clone());
+                                analysis.raw_html = raw_html;
+
+                                let mut analyses = pending_analyses.lock().await;
+                                analyses.push(analysis);
+
+                                if analyses.len() >= batch_size {
+                                    let analyses_to_save: Vec<SeoAnalysis> =
+                                        analyses.drain(..batch_size).collect();
+                                    drop(analyses);
+                                    if config.crawl.dry_run {
+                                        log_dry_run(logger.as_ref(), &analyses_to_save).await;
+                                    } else if let Some(sink) = sink {
+                                        let permit = db_semaphore.clone().acquire_owned().await;
+                                        tokio::spawn(async move {
+                                            if let Err(e) = sink.write_batch(&analyses_to_save).await {
+                                                eprintln!("Batch save error: {:?}", e);
+                                            }
+                                            drop(permit);
+                                        });
+                                    }
+                                }
+                            } else if saved {
+                                let _ = analysis_tx.send(analysis);
+                            }
+
+                            let within_depth = config
+                                .crawl
+                                .max_depth
+                                .is_none_or(|max_depth| item.depth < max_depth);
+                            if within_depth {
+                                for link in child_links {
+                                    let mut visited_lock = visited.lock().await;
+                                    if visited_lock.insert(link.clone()) {
+                                        // increase total_left for each new URL discovered
+                                        metrics.total_left.fetch_add(1, Ordering::Relaxed);
+                                        let child = item.child(link);
+                                        frontier.insert(&child);
+                                        let _ = discovered_tx.send(child);
+                                    }
+                                }
+                            }
+                        }
+                        Err(e) => {
+                            if let CrawlError::RateLimited(rate_limited) = &e {
+                                requeue_rate_limited(
+                                    &item,
+                                    rate_limited,
+                                    &config,
+                                    &frontier,
+                                    discovered_tx.clone(),
+                                );
+                            }
+                            debug_only! { eprintln!("Error processing {}: {:?}", url, e) }
+                        }
+                    }
+
+                    if current_count.is_multiple_of(batch_size) {
+                        if let Some(logger) = logger {
+                            let mut logger = logger.lock().await;
+                            let _ = logger.add_entry(format!(
+                                "======== Batch {} complete ========",
+                                current_count
+                            ));
+                            let _ = logger.flush();
+                        }
+                    }
+                }
+            })
+            .await;
+
+        if shutdown.load(Ordering::Relaxed) {
+            status!("Shutdown requested, saving pending analyses...");
+            batch_task.abort();
+        } else {
+            batch_task.await?;
+        }
+
+        if config.crawl.dry_run {
+            let final_analyses = pending_analyses.lock().await.drain(..).collect::<Vec<_>>();
+            log_dry_run(logger.as_ref(), &final_analyses).await;
+        } else if let Some(sink) = &sink {
+            // wait for batches that are still being written
+            let _ = db_semaphore.acquire_many(DB_CONCURRENCY as u32).await;
+
+            let final_analyses = pending_analyses.lock().await.drain(..).collect::<Vec<_>>();
+            if !final_analyses.is_empty() {
+                sink.write_batch(&final_analyses).await?;
+            }
+        }
+
+        if let Some(path) = &config.crawl.visited_file {
+            save_visited(path, &visited.lock().await.iter().cloned().collect::<Vec<_>>())?;
+        }
+
+        if let Some(task) = checkpoint_task {
+            task.abort();
+        }
+        if let Some(path) = &config.crawl.frontier_file {
+            let saved = frontier.save(path)?;
+            status!("Saved {} pending URLs to {}", saved, path.display());
+        }
+
+        if let Some(logger) = &logger {
+            logger.lock().await.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+pub async fn process_page(
+    url: &str,
+    config: &Config,
+    proxy_manager: &ProxyManager,
+    domain_limiter: Option<&DomainLimiter>,
+    domain_spacing: Option<&DomainSpacing>,
+    metrics: &Arc<Metrics>,
+) -> Result<(Vec<String>, SeoAnalysis), CrawlError> {
+    let result = fetch_and_analyze(
+        url,
+        config,
+        proxy_manager,
+        domain_limiter,
+        domain_spacing,
+        metrics,
+    )
+    .await;
+    if let Err(e) = &result {
+        metrics.record_failure(e);
+    }
+    if let Ok(domain) = domain_key(url, &config.crawl) {
+        metrics.record_domain(&domain, result.is_ok());
+    }
+    result
+}
+
+async fn fetch_and_analyze(
+    url: &str,
+    config: &Config,
+    proxy_manager: &ProxyManager,
+    domain_limiter: Option<&DomainLimiter>,
+    domain_spacing: Option<&DomainSpacing>,
+    metrics: &Arc<Metrics>,
+) -> Result<(Vec<String>, SeoAnalysis), CrawlError> {
+    *metrics.last_activity.lock().await = Instant::now();
+
+    let base_url =
+        normalize_url(url, &config.crawl.tracking_params).map_err(CrawlError::Parse)?;
+
+    let domain = || domain_key(&base_url, &config.crawl).map_err(CrawlError::Parse);
+    let domain_permit = match domain_limiter {
+        Some(limiter) => Some(limiter.acquire(&domain()?).await),
+        None => None,
+    };
+    if let Some(spacing) = domain_spacing {
+        spacing.wait(&domain()?).await;
+    }
+
+    let max_tunnel_retries = config.crawl.max_tunnel_retries;
+    let max_page_bytes = config.crawl.page_size_limit();
+    let mut tunnel_retries = 0;
+    let page = loop {
+        match try_tunnel_request(url, proxy_manager, metrics, max_page_bytes).await {
+            Ok(page) => {
+                *metrics.last_activity.lock().await = Instant::now();
+                break page;
+            }
+            // retrying would get the same answer
+            Err(e @ (CrawlError::RateLimited(_) | CrawlError::TooLarge(_))) => return Err(e),
+            Err(e) => {
+                // the tunnel would be shown the same block page again, so go straight to proxies
+                tunnel_retries = if matches!(e, CrawlError::Blocked(_)) {
+                    max_tunnel_retries
+                } else {
+                    tunnel_retries + 1
+                };
+                if tunnel_retries < max_tunnel_retries {
+                    print_request_status(
+                        url,
+                        "TUNNEL",
+                        "RETRY",
+                        Some(&format!(
+                            "attempt {}/{}",
+                            tunnel_retries, max_tunnel_retries
+                        )),
+                    );
+                    continue;
+                }
+                // without proxies the tunnel's answer is the most useful error to report
+                if proxy_manager.proxies.is_empty() {
+                    return Err(e);
+                }
+
+                metrics.proxy.fetch_add(1, Ordering::Relaxed);
+
+                // proxies pick their fingerprint from their IP, so a 403 is worth another
+                // proxy before the page is given up on
+                let mut forbidden_retries = 0;
+                break loop {
+                    let (proxy_index, proxy) =
+                        proxy_manager.get_next_proxy().ok_or(CrawlError::NoProxy)?;
+                    let fp = match &proxy.ip {
+                        Some(ip) => RequestFingerprint::new(ip, url),
+                        None => RequestFingerprint::new(proxy.addr.as_str(), url),
+                    };
+                    let started = Instant::now();
+
+                    let request = fp
+                        .headers
+                        .into_iter()
+                        .fold(proxy.client.get(&base_url), |request, (name, value)| {
+                            request.header(name, value)
+                        });
+
+                    match request.send().await {
+                        Ok(response) => {
+                            if let Some(rate_limited) = RateLimited::from_response(&response) {
+                                print_request_status(url, "PROXY", "FAILED", Some("429 Too Many Requests"));
+                                return Err(rate_limited.into());
+                            }
+                            let page = match FetchedPage::read(response, max_page_bytes).await {
+                                Ok(page) => page,
+                                Err(e) => {
+                                    print_request_status(url, "PROXY", "FAILED", Some(&e.to_string()));
+                                    return Err(e);
+                                }
+                            };
+                            let text = page.text();
+                            let blocked = detect_block_page(&text, page.status_code);
+                            if blocked.is_some()
+                                || page.status_code == 403
+                                || text.contains("403 Forbidden")
+                            {
+                                let reason = match blocked {
+                                    Some(kind) => kind.to_string(),
+                                    None => "403 Forbidden".to_string(),
+                                };
+                                proxy_manager.record_failure(proxy_index);
+                                if forbidden_retries < config.crawl.max_forbidden_retries {
+                                    forbidden_retries += 1;
+                                    metrics.forbidden_retries.fetch_add(1, Ordering::Relaxed);
+                                    print_request_status(
+                                        url,
+                                        "PROXY",
+                                        "RETRY",
+                                        Some(&format!(
+                                            "{}, attempt {}/{}",
+                                            reason,
+                                            forbidden_retries,
+                                            config.crawl.max_forbidden_retries
+                                        )),
+                                    );
+                                    continue;
+                                }
+                                print_request_status(url, "PROXY", "FAILED", Some(&reason));
+                                return Err(match blocked {
+                                    Some(kind) => Blocked(kind).into(),
+                                    None => CrawlError::Forbidden,
+                                });
+                            }
+                            proxy_manager.record_latency(proxy_index, started.elapsed());
+                            print_request_status(url, "PROXY", "SUCCESS", None);
+                            break page;
+                        }
+                        Err(e) => {
+                            proxy_manager.record_failure(proxy_index);
+                            print_request_status(url, "PROXY", "FAILED", Some(&e.to_string()));
+                            return Err(e.into());
+                        }
+                    }
+                }
+            }
+        }
+    };
+
+    drop(domain_permit);
+
+    // index the page under the URL it redirected to, if any
+    let base_url = match normalize_url(&page.final_url, &config.crawl.tracking_params) {
+        Ok(final_url) if final_url != base_url => final_url,
+        _ => base_url,
+    };
+
+    if !html_parser::looks_like_html(&page.body, page.content_type.as_deref()) {
+        return Err(CrawlError::NotHtml);
+    }
+
+    let parsed = html_parser::parse_html_with_encoding(
+        &page.body,
+        &html_parser::parse_base_url(&base_url)?,
+        page.encoding,
+    );
+
+    if config.soft_404.enabled
+        && soft404::is_soft_404(&parsed.title, &parsed.content_text, &config.soft_404)
+    {
+        metrics.soft_404s.fetch_add(1, Ordering::Relaxed);
+        debug_only! { status!("[DEBUG] Skipping soft 404 {}", url) }
+        return Err(SoftNotFound.into());
+    }
+
+    let (language, language_confidence) = match parsed.language.trim() {
+        "" => match detect_language(&parsed.content_text) {
+            Some(detected) => (detected.code, Some(detected.confidence)),
+            None => (String::new(), None),
+        },
+        _ => (parsed.language, None),
+    };
+
+    let robots = page
+        .robots_tags
+        .iter()
+        .map(|value| RobotsMeta::from_header(value))
+        .fold(parsed.robots_directives, RobotsMeta::merge);
+    // the page is still analysed so a noindex,follow page passes its links on
+    let links = if robots.nofollow {
+        Vec::new()
+    } else {
+        // translations are crawled like any other link
+        let mut links = parsed.links;
+        for (_, url) in &parsed.alternates {
+            let is_self = normalize_url(url, &config.crawl.tracking_params)
+                .is_ok_and(|url| url == base_url);
+            if !is_self && !links.contains(url) {
+                links.push(url.clone());
+            }
+        }
+        links
+    };
+
+    let main_content = if config.crawl.extract_main_content {
+        readability::extract_main_content(&page.text())
+    } else {
+        String::new()
+    };
+
+    let analysis = SeoAnalysis {
+        url: base_url,
+        language,
+        language_confidence,
+        title: parsed.title,
+        meta_tags: parsed.meta_tags,
+        canonical_url: parsed.canonical_url,
+        content_hash: simhash(&parsed.content_text),
+        content_text: parsed.content_text,
+        main_content,
+        status_code: page.status_code,
+        content_type: page.content_type,
+        charset: Some(page.encoding.name().to_string()),
+        last_modified: page.last_modified,
+        crawled_at: chrono::Utc::now().timestamp(),
+        headings: parsed.headings,
+        structured_data: parsed.structured_data,
+        favicon_url: parsed.favicon_url,
+        image_url: parsed.image_url,
+        robots,
+        alternates: parsed.alternates,
+        raw_html: config.sink.archive_raw_html.then(|| page.body.to_vec()),
+    };
+
+    metrics.success.fetch_add(1, Ordering::Relaxed);
+    metrics
+        .content_bytes
+        .fetch_add(analysis.content_text.len(), Ordering::Relaxed);
+    Ok((links, analysis))
+}
+
+/// Records the analyses a dry run would have written to the sink.
+async fn log_dry_run(logger: Option<&Arc<Mutex<AsyncLogger>>>, analyses: &[SeoAnalysis]) {
+    status!("[dry-run] Would save a batch of {} analyses", analyses.len());
+    if let Some(logger) = logger {
+        let mut logger = logger.lock().await;
+        for analysis in analyses {
+            let _ = logger.add_event(LogEvent::info("dry_run_save").url(&analysis.url));
+        }
+        let _ = logger.flush();
+    }
+}
+
+async fn wait_for_shutdown(shutdown: Arc<AtomicBool>) {
+    let mut interval = tokio::time::interval(Duration::from_millis(100));
+    while !shutdown.load(Ordering::Relaxed) {
+        interval.tick().await;
+    }
+}
+
+/// Reads the URLs written by [`save_visited`], empty when there is no file yet.
+fn load_visited(path: &Path) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(contents
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(String::from)
+            .collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn save_visited(
+    path: &Path,
+    visited: &[String],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, visited.join("\n"))?;
+    status!("Saved {} visited URLs to {}", visited.len(), path.display());
+    Ok(())
+}
+
+/// Sends a rate limited URL back to the frontier once its `Retry-After` delay (or an
+/// exponential backoff when the site gave none) has passed, up to `max_retry_attempts` times.
+fn requeue_rate_limited(
+    item: &CrawlItem,
+    rate_limited: &RateLimited,
+    config: &Config,
+    frontier: &Frontier,
+    discovered_tx: Arc<mpsc::UnboundedSender<CrawlItem>>,
+) {
+    if item.attempt >= config.crawl.max_retry_attempts {
+        debug_only! { status!("[DEBUG] Giving up on rate limited {}", item.url) }
+        return;
+    }
+
+    let backoff = config.crawl.retry_backoff() * 2u32.saturating_pow(item.attempt);
+    let delay = rate_limited
+        .retry_after
+        .unwrap_or(backoff)
+        .min(MAX_RETRY_DELAY);
+    let retry = item.retry();
+    frontier.insert(&retry);
+
+    tokio::spawn(async move {
+        tokio::time::sleep(delay).await;
+        let _ = discovered_tx.send(retry);
+    });
+}
+
+fn spawn_sitemap_discovery(
+    domain: String,
+    depth: u32,
+    proxy_manager: ProxyManager,
+    visited: Arc<Mutex<HashSet<String>>>,
+    metrics: Arc<Metrics>,
+    frontier: Arc<Frontier>,
+    discovered_tx: Arc<mpsc::UnboundedSender<CrawlItem>>,
+) {
+    tokio::spawn(async move {
+        let urls = fetch_sitemap(&domain, &proxy_manager).await;
+        debug_only! { status!("[DEBUG] Found {} sitemap URLs for {}", urls.len(), domain) }
+
+        let mut visited_lock = visited.lock().await;
+        for url in urls {
+            if visited_lock.insert(url.clone()) {
+                metrics.total_left.fetch_add(1, Ordering::Relaxed);
+                let item = CrawlItem {
+                    url,
+                    depth,
+                    attempt: 0,
+                    priority: 0,
+                };
+                frontier.insert(&item);
+                let _ = discovered_tx.send(item);
+            }
+        }
+    });
+}
+
+// shuffled so one domain's URLs are not fetched back to back, but only among URLs of the
+// same priority
+fn collect_batch(
+    domain_queues: &mut DomainQueues,
+    config: &Config,
+    breakers: Option<&CircuitBreakers>,
+    rng: &mut StdRng,
+) -> Vec<CrawlItem> {
+    let max_per_domain = config.crawl.max_per_domain;
+    let mut batch = match breakers {
+        Some(breakers) => {
+            domain_queues.collect_batch_with(|domain| breakers.allowance(domain, max_per_domain))
+        }
+        None => domain_queues.collect_batch(max_per_domain),
+    };
+    batch.shuffle(rng);
+    batch.sort_by_key(|item| Reverse(item.priority));
+    batch
+}
@@ -1,39 +1,104 @@
+use arrow_schema::{DataType, Field, Fields, Schema};
 use aws_sdk_s3::{
     config::{http::HttpRequest, retry, timeout, Region, RequestChecksumCalculation},
     primitives::ByteStream,
     Client,
 };
+use bytes::Bytes;
+use futures::StreamExt;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::{Compression, ZstdLevel};
+use parquet::file::properties::WriterProperties;
 use serde::{Deserialize, Serialize};
 use std::env;
+use std::str::FromStr;
+use std::sync::Arc;
 use uuid::Uuid;
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::html_parser::RobotsMeta;
+use crate::status;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SeoAnalysis {
     pub url: String,
     pub language: String,
+    /// Set when `language` was detected from the content rather than declared by the page
+    #[serde(default)]
+    pub language_confidence: Option<f64>,
     pub title: String,
     pub meta_tags: Vec<MetaTag>,
     pub canonical_url: Option<String>,
     pub content_text: String,
+    /// Article body without navigation, sidebars and footers, empty unless
+    /// `crawl.extract_main_content` is set
+    #[serde(default)]
+    pub main_content: String,
+    /// SimHash of `content_text`, see [`crate::dedup::simhash`]
+    #[serde(default)]
+    pub content_hash: u64,
+    #[serde(default)]
+    pub status_code: u16,
+    #[serde(default)]
+    pub content_type: Option<String>,
+    /// Encoding the body was decoded with, e.g. `Shift_JIS`
+    #[serde(default)]
+    pub charset: Option<String>,
+    /// Raw `Last-Modified` header, when the server sent one
+    #[serde(default)]
+    pub last_modified: Option<String>,
+    /// Unix seconds at which the page was fetched
+    #[serde(default)]
+    pub crawled_at: i64,
+    /// Page outline in document order
+    #[serde(default)]
+    pub headings: Vec<Heading>,
+    /// schema.org JSON-LD blocks found on the page
+    #[serde(default)]
+    pub structured_data: Vec<serde_json::Value>,
+    /// Absolute URL of the page's icon
+    #[serde(default)]
+    pub favicon_url: Option<String>,
+    /// Absolute URL of a thumbnail, `og:image` when the page has one
+    #[serde(default)]
+    pub image_url: Option<String>,
+    /// Robots directives from the page and its `X-Robots-Tag` headers. `noindex` pages are
+    /// never saved, so only `nofollow` is ever set in stored records.
+    #[serde(default)]
+    pub robots: RobotsMeta,
+    /// Translations of the page as `(hreflang, url)` pairs, e.g. `("fr", "https://example.com/fr/")`
+    #[serde(default)]
+    pub alternates: Vec<(String, String)>,
+    /// Undecoded response body, only kept when `sink.archive_raw_html` is set. Sinks archive it
+    /// under [`raw_html_key`] instead of writing it into the record.
+    #[serde(skip)]
+    pub raw_html: Option<Vec<u8>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MetaTag {
     pub name: String,
     pub content: String,
 }
 
-pub async fn create_db_pool() -> Result<Client, Box<dyn std::error::Error>> {
-    println!("Creating S3 client");
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Heading {
+    /// 1 for `<h1>` through 6 for `<h6>`
+    pub level: u8,
+    pub text: String,
+}
+
+pub async fn create_db_pool() -> Result<Client, Box<dyn std::error::Error + Send + Sync>> {
+    status!("Creating S3 client");
     dotenvy::dotenv().ok();
 
     let bucket = env::var("S3_BUCKET")?;
     let region_env = env::var("S3_REGION").unwrap_or_else(|_| "us-east-1".to_string());
     let endpoint_url = env::var("S3_ENDPOINT")?;
 
-    println!("[S3] Using region: {}", region_env);
-    println!("[S3] Using endpoint: {}", endpoint_url);
-    println!("[S3] Using bucket: {}", bucket);
+    status!("[S3] Using region: {}", region_env);
+    status!("[S3] Using endpoint: {}", endpoint_url);
+    status!("[S3] Using bucket: {}", bucket);
 
     let shared_config = aws_config::from_env()
         .region(Region::new(region_env))
@@ -66,10 +131,11 @@ fn sanitize_text(text: &str) -> String {
         .collect()
 }
 
-fn sanitize_analysis(analysis: &SeoAnalysis) -> SeoAnalysis {
+pub fn sanitize_analysis(analysis: &SeoAnalysis) -> SeoAnalysis {
     SeoAnalysis {
         url: sanitize_text(&analysis.url),
         language: sanitize_text(&analysis.language),
+        language_confidence: analysis.language_confidence,
         title: sanitize_text(&analysis.title),
         meta_tags: analysis
             .meta_tags
@@ -84,55 +150,324 @@ fn sanitize_analysis(analysis: &SeoAnalysis) -> SeoAnalysis {
             .as_ref()
             .map(|url| sanitize_text(url)),
         content_text: sanitize_text(&analysis.content_text),
+        main_content: sanitize_text(&analysis.main_content),
+        content_hash: analysis.content_hash,
+        status_code: analysis.status_code,
+        content_type: analysis.content_type.as_deref().map(sanitize_text),
+        charset: analysis.charset.clone(),
+        last_modified: analysis.last_modified.as_deref().map(sanitize_text),
+        crawled_at: analysis.crawled_at,
+        headings: analysis
+            .headings
+            .iter()
+            .map(|heading| Heading {
+                level: heading.level,
+                text: sanitize_text(&heading.text),
+            })
+            .collect(),
+        structured_data: analysis.structured_data.clone(),
+        favicon_url: analysis.favicon_url.as_deref().map(sanitize_text),
+        image_url: analysis.image_url.as_deref().map(sanitize_text),
+        robots: analysis.robots,
+        alternates: analysis
+            .alternates
+            .iter()
+            .map(|(lang, url)| (sanitize_text(lang), sanitize_text(url)))
+            .collect(),
+        // never serialized, and too large to copy for nothing
+        raw_html: None,
+    }
+}
+
+pub fn to_jsonl(analyses: &[SeoAnalysis]) -> serde_json::Result<String> {
+    let mut jsonl = Vec::new();
+    for analysis in analyses {
+        let sanitized = sanitize_analysis(analysis);
+        let json = serde_json::to_string(&sanitized)?;
+        jsonl.push(json);
+    }
+    Ok(jsonl.join("\n"))
+}
+
+pub fn url_partition(url: &str) -> String {
+    format!("{:02x}", md5::compute(url).0[0])
+}
+
+/// UTC day the page was crawled, as `YYYY-MM-DD`.
+pub fn crawl_date(analysis: &SeoAnalysis) -> String {
+    chrono::DateTime::from_timestamp(analysis.crawled_at, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+/// Splits a batch into runs crawled on the same day, each keyed by its
+/// `date=YYYY-MM-DD/partition=XX` prefix. The partition comes from the first URL of the run.
+pub fn partitioned_runs(analyses: &[SeoAnalysis]) -> Vec<(String, &[SeoAnalysis])> {
+    analyses
+        .chunk_by(|a, b| crawl_date(a) == crawl_date(b))
+        .map(|run| {
+            let prefix = format!(
+                "date={}/partition={}",
+                crawl_date(&run[0]),
+                url_partition(&run[0].url)
+            );
+            (prefix, run)
+        })
+        .collect()
+}
+
+const ZSTD_LEVEL: i32 = 3;
+
+pub fn compress_jsonl(body: &str) -> std::io::Result<Vec<u8>> {
+    zstd::encode_all(body.as_bytes(), ZSTD_LEVEL)
+}
+
+pub fn compress_raw_html(body: &[u8]) -> std::io::Result<Vec<u8>> {
+    zstd::encode_all(body, ZSTD_LEVEL)
+}
+
+/// Where a page's archived HTML is stored, `raw/date=YYYY-MM-DD/partition=XX/<url md5>.html.zst`.
+/// Unlike batches, the partition comes from the page's own URL, so the object can be found
+/// from the URL and crawl day alone.
+pub fn raw_html_key(analysis: &SeoAnalysis) -> String {
+    format!(
+        "raw/date={}/partition={}/{:x}.html.zst",
+        crawl_date(analysis),
+        url_partition(&analysis.url),
+        md5::compute(&analysis.url)
+    )
+}
+
+/// How batches of analyses are encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BatchFormat {
+    /// One JSON object per line
+    #[default]
+    Jsonl,
+    /// Columnar, see [`to_parquet`]
+    Parquet,
+}
+
+impl FromStr for BatchFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "jsonl" => Ok(BatchFormat::Jsonl),
+            "parquet" => Ok(BatchFormat::Parquet),
+            other => Err(format!("Unknown batch format: {}", other)),
+        }
+    }
+}
+
+impl BatchFormat {
+    /// Top-level S3 prefix of batches in this format.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            BatchFormat::Jsonl => "analyses",
+            BatchFormat::Parquet => "parquet",
+        }
+    }
+
+    /// File extension of a batch: `jsonl`, `jsonl.zst` when compressed, or `parquet`, whose
+    /// compression is internal.
+    pub fn extension(self, compress: bool) -> &'static str {
+        match self {
+            BatchFormat::Jsonl if compress => "jsonl.zst",
+            BatchFormat::Jsonl => "jsonl",
+            BatchFormat::Parquet => "parquet",
+        }
     }
 }
 
+/// Encodes a batch in `format`, see [`BatchFormat::extension`].
+pub fn encode_batch(
+    analyses: &[SeoAnalysis],
+    format: BatchFormat,
+    compress: bool,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    match format {
+        BatchFormat::Jsonl if compress => Ok(compress_jsonl(&to_jsonl(analyses)?)?),
+        BatchFormat::Jsonl => Ok(to_jsonl(analyses)?.into_bytes()),
+        BatchFormat::Parquet => to_parquet(analyses, compress),
+    }
+}
+
+/// Column layout of Parquet batches, one column per [`SeoAnalysis`] field with meta tags,
+/// headings and alternates as nested lists. JSON-LD blocks vary too much in shape for a
+/// column type, so `structured_data` is a list of JSON strings.
+pub fn parquet_schema() -> Schema {
+    let text = |name: &str, nullable| Field::new(name, DataType::Utf8, nullable);
+    let list = |name: &str, item| Field::new_list(name, Field::new_list_field(item, true), false);
+    Schema::new(vec![
+        text("url", false),
+        text("language", false),
+        Field::new("language_confidence", DataType::Float64, true),
+        text("title", false),
+        list(
+            "meta_tags",
+            DataType::Struct(Fields::from(vec![
+                text("name", false),
+                text("content", false),
+            ])),
+        ),
+        text("canonical_url", true),
+        text("content_text", false),
+        text("main_content", false),
+        Field::new("content_hash", DataType::UInt64, false),
+        Field::new("status_code", DataType::UInt16, false),
+        text("content_type", true),
+        text("charset", true),
+        text("last_modified", true),
+        Field::new("crawled_at", DataType::Int64, false),
+        list(
+            "headings",
+            DataType::Struct(Fields::from(vec![
+                Field::new("level", DataType::UInt8, false),
+                text("text", false),
+            ])),
+        ),
+        list("structured_data", DataType::Utf8),
+        text("favicon_url", true),
+        text("image_url", true),
+        Field::new(
+            "robots",
+            DataType::Struct(Fields::from(vec![
+                Field::new("noindex", DataType::Boolean, false),
+                Field::new("nofollow", DataType::Boolean, false),
+            ])),
+            false,
+        ),
+        list(
+            "alternates",
+            DataType::List(Arc::new(Field::new_list_field(DataType::Utf8, true))),
+        ),
+    ])
+}
+
+/// Encodes sanitized analyses as a Parquet file of [`parquet_schema`], zstd compressed
+/// with `compress`.
+pub fn to_parquet(
+    analyses: &[SeoAnalysis],
+    compress: bool,
+) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
+    let schema = Arc::new(parquet_schema());
+    let mut rows = Vec::with_capacity(analyses.len());
+    for analysis in analyses {
+        let mut row = serde_json::to_value(sanitize_analysis(analysis))?;
+        if let Some(blocks) = row
+            .get_mut("structured_data")
+            .and_then(serde_json::Value::as_array_mut)
+        {
+            for block in blocks {
+                *block = serde_json::Value::String(block.to_string());
+            }
+        }
+        rows.push(row);
+    }
+
+    let mut decoder = arrow_json::ReaderBuilder::new(schema.clone()).build_decoder()?;
+    decoder.serialize(&rows)?;
+    let compression = if compress {
+        Compression::ZSTD(ZstdLevel::try_new(ZSTD_LEVEL)?)
+    } else {
+        Compression::UNCOMPRESSED
+    };
+    let properties = WriterProperties::builder()
+        .set_compression(compression)
+        .build();
+
+    let mut body = Vec::new();
+    let mut writer = ArrowWriter::try_new(&mut body, schema, Some(properties))?;
+    if let Some(batch) = decoder.flush()? {
+        writer.write(&batch)?;
+    }
+    writer.close()?;
+    Ok(body)
+}
+
+/// Reads back a file written by [`to_parquet`].
+pub fn from_parquet(
+    body: Bytes,
+) -> Result<Vec<SeoAnalysis>, Box<dyn std::error::Error + Send + Sync>> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(body)?.build()?;
+    let mut analyses = Vec::new();
+    for batch in reader {
+        let mut writer = arrow_json::ArrayWriter::new(Vec::new());
+        writer.write(&batch?)?;
+        writer.finish()?;
+        let rows: Vec<serde_json::Value> = serde_json::from_slice(&writer.into_inner())?;
+        for mut row in rows {
+            if let Some(blocks) = row
+                .get_mut("structured_data")
+                .and_then(serde_json::Value::as_array_mut)
+            {
+                for block in blocks {
+                    if let Some(json) = block.as_str() {
+                        *block = serde_json::from_str(json)?;
+                    }
+                }
+            }
+            analyses.push(serde_json::from_value(row)?);
+        }
+    }
+    Ok(analyses)
+}
+
 pub async fn save_analyses_batch(
     client: &Client,
     analyses: &[SeoAnalysis],
-) -> Result<(), Box<dyn std::error::Error>> {
+    format: BatchFormat,
+    compress: bool,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let bucket = env::var("S3_BUCKET")?;
-    println!("[S3] Using bucket: {}", bucket);
-
-    for (chunk_idx, chunk) in analyses.chunks(10_000).enumerate() {
-        let mut jsonl = Vec::new();
-        for analysis in chunk {
-            let sanitized = sanitize_analysis(analysis);
-            let json = serde_json::to_string(&sanitized)?;
-            jsonl.push(json);
-        }
+    status!("[S3] Using bucket: {}", bucket);
 
-        let body = jsonl.join("\n");
-        if body.is_empty() {
-            continue;
+    let mut chunks = Vec::new();
+    for (prefix, run) in partitioned_runs(analyses) {
+        for chunk in run.chunks(10_000) {
+            chunks.push((prefix.clone(), chunk));
         }
+    }
 
-        let partition = if let Some(first) = chunk.first() {
-            format!("{:02x}", md5::compute(&first.url).0[0])
-        } else {
+    for (chunk_idx, (prefix, chunk)) in chunks.into_iter().enumerate() {
+        if chunk.is_empty() {
             continue;
-        };
+        }
 
         let key = format!(
-            "analyses/partition={}/batch_{}.jsonl",
-            partition,
-            Uuid::new_v4()
+            "{}/{}/batch_{}.{}",
+            format.prefix(),
+            prefix,
+            Uuid::new_v4(),
+            format.extension(compress)
         );
+        let body = encode_batch(chunk, format, compress)?;
 
-        println!(
+        status!(
             "[S3] Uploading chunk {} to {}/{}",
             chunk_idx + 1,
             bucket,
             key
         );
 
-        client
+        let mut request = client
             .put_object()
             .bucket(&bucket)
             .key(&key)
-            .content_type("application/jsonlines")
-            .content_length(body.len() as i64)
-            .body(ByteStream::from(body.into_bytes()))
+            .content_length(body.len() as i64);
+        request = match format {
+            BatchFormat::Jsonl => request.content_type("application/jsonlines"),
+            BatchFormat::Parquet => request.content_type("application/vnd.apache.parquet"),
+        };
+        if compress && format == BatchFormat::Jsonl {
+            request = request.content_encoding("zstd");
+        }
+
+        request
+            .body(ByteStream::from(body))
             .customize()
             .mutate_request(|req: &mut HttpRequest| {
                 req.headers_mut().remove("x-amz-checksum-crc32");
@@ -140,8 +475,58 @@ pub async fn save_analyses_batch(
             .send()
             .await?;
 
-        println!("[S3] Successfully uploaded chunk {}", chunk_idx + 1);
+        status!("[S3] Successfully uploaded chunk {}", chunk_idx + 1);
+    }
+
+    Ok(())
+}
+
+const RAW_UPLOAD_CONCURRENCY: usize = 16;
+
+/// Uploads the raw HTML kept on `analyses`, one zstd compressed object per page at
+/// [`raw_html_key`]. Pages without `raw_html` are skipped.
+pub async fn save_raw_html_batch(
+    client: &Client,
+    analyses: &[SeoAnalysis],
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let bucket = env::var("S3_BUCKET")?;
+
+    let mut pages = Vec::new();
+    for analysis in analyses {
+        if let Some(raw_html) = &analysis.raw_html {
+            pages.push((raw_html_key(analysis), compress_raw_html(raw_html)?));
+        }
+    }
+
+    let uploads = pages.into_iter().map(|(key, body)| {
+        let request = client
+            .put_object()
+            .bucket(&bucket)
+            .key(key)
+            .content_type("text/html")
+            .content_encoding("zstd")
+            .content_length(body.len() as i64)
+            .body(ByteStream::from(body));
+        async move {
+            request
+                .customize()
+                .mutate_request(|req: &mut HttpRequest| {
+                    req.headers_mut().remove("x-amz-checksum-crc32");
+                })
+                .send()
+                .await
+        }
+    });
+
+    let results: Vec<_> = futures::stream::iter(uploads)
+        .buffer_unordered(RAW_UPLOAD_CONCURRENCY)
+        .collect()
+        .await;
+    let uploaded = results.len();
+    for result in results {
+        result?;
     }
 
+    status!("[S3] Archived raw HTML of {} pages", uploaded);
     Ok(())
 }
@@ -0,0 +1,76 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use crate::config::DedupConfig;
+
+/// 64-bit SimHash of the words in `text`. Texts that share most of their words end up
+/// with hashes a few bits apart, regardless of whitespace and word order.
+pub fn simhash(text: &str) -> u64 {
+    let mut weights = [0i32; 64];
+
+    for word in text.split_whitespace() {
+        let word = word.to_lowercase();
+        let digest = md5::compute(word.as_bytes());
+        let hash = u64::from_le_bytes(digest.0[..8].try_into().unwrap());
+        for (bit, weight) in weights.iter_mut().enumerate() {
+            if hash & (1 << bit) != 0 {
+                *weight += 1;
+            } else {
+                *weight -= 1;
+            }
+        }
+    }
+
+    weights
+        .iter()
+        .enumerate()
+        .filter(|(_, weight)| **weight > 0)
+        .fold(0, |hash, (bit, _)| hash | (1 << bit))
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Remembers the content hashes of the most recently saved pages in a bounded LRU and flags
+/// pages whose hash lies within `max_distance` bits of one of them.
+pub struct ContentHasher {
+    recent: Mutex<VecDeque<u64>>,
+    capacity: usize,
+    max_distance: u32,
+}
+
+impl ContentHasher {
+    pub fn new(capacity: usize, max_distance: u32) -> Self {
+        ContentHasher {
+            recent: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity: capacity.max(1),
+            max_distance,
+        }
+    }
+
+    pub fn from_config(config: &DedupConfig) -> Self {
+        Self::new(config.capacity, config.max_distance)
+    }
+
+    /// Returns `true` if `hash` is a near-duplicate of a recently seen page, otherwise
+    /// remembers it. Either way the matching entry becomes the most recently used one.
+    pub fn is_duplicate(&self, hash: u64) -> bool {
+        let mut recent = self.recent.lock().unwrap();
+
+        let found = recent
+            .iter()
+            .position(|seen| hamming_distance(*seen, hash) <= self.max_distance);
+        let entry = match found {
+            Some(index) => recent.remove(index).unwrap_or(hash),
+            None => hash,
+        };
+
+        if recent.len() >= self.capacity {
+            recent.pop_front();
+        }
+        recent.push_back(entry);
+
+        found.is_some()
+    }
+}
@@ -0,0 +1,98 @@
+use std::io::ErrorKind;
+use std::path::Path;
+
+use crate::config::DomainConfig;
+
+/// A host pattern, either an exact host or `*.example.com`, which matches any
+/// subdomain of `example.com` but not `example.com` itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum HostPattern {
+    Exact(String),
+    /// Stored with its leading dot, e.g. `.example.com`
+    Suffix(String),
+}
+
+impl HostPattern {
+    fn parse(pattern: &str) -> Option<Self> {
+        let pattern = pattern.trim().trim_end_matches('.').to_lowercase();
+        if pattern.is_empty() {
+            return None;
+        }
+        Some(match pattern.strip_prefix('*') {
+            Some(suffix) if suffix.starts_with('.') => HostPattern::Suffix(suffix.to_string()),
+            _ => HostPattern::Exact(pattern),
+        })
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            HostPattern::Exact(exact) => host == exact,
+            HostPattern::Suffix(suffix) => host.ends_with(suffix.as_str()),
+        }
+    }
+}
+
+/// Decides which hosts may be crawled. Blocked hosts are never crawled, and when
+/// any allow patterns exist only hosts matching one of them are.
+#[derive(Debug, Clone, Default)]
+pub struct DomainFilter {
+    allow: Vec<HostPattern>,
+    block: Vec<HostPattern>,
+}
+
+impl DomainFilter {
+    pub fn new<A, B>(allow: A, block: B) -> Self
+    where
+        A: IntoIterator,
+        A::Item: AsRef<str>,
+        B: IntoIterator,
+        B::Item: AsRef<str>,
+    {
+        DomainFilter {
+            allow: allow
+                .into_iter()
+                .filter_map(|p| HostPattern::parse(p.as_ref()))
+                .collect(),
+            block: block
+                .into_iter()
+                .filter_map(|p| HostPattern::parse(p.as_ref()))
+                .collect(),
+        }
+    }
+
+    /// Combines the `allow`/`block` lists of the config with the patterns in
+    /// `allow_file`/`block_file`, one per line. Missing files are treated as empty.
+    pub fn from_config(config: &DomainConfig) -> std::io::Result<Self> {
+        let mut allow = config.allow.clone();
+        let mut block = config.block.clone();
+        if let Some(path) = &config.allow_file {
+            allow.extend(read_patterns(path)?);
+        }
+        if let Some(path) = &config.block_file {
+            block.extend(read_patterns(path)?);
+        }
+        Ok(Self::new(allow, block))
+    }
+
+    pub fn allows(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_lowercase();
+        if self.block.iter().any(|pattern| pattern.matches(&host)) {
+            return false;
+        }
+        self.allow.is_empty() || self.allow.iter().any(|pattern| pattern.matches(&host))
+    }
+}
+
+// blank lines and `#` comments are skipped
+fn read_patterns(path: &Path) -> std::io::Result<Vec<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(content
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(str::to_string)
+            .collect()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,75 @@
+//! Why a page could not be crawled.
+
+use thiserror::Error;
+
+use crate::block_page::Blocked;
+use crate::html_parser::InvalidBaseUrl;
+use crate::network::{PageTooLarge, RateLimited};
+use crate::soft404::SoftNotFound;
+
+/// Returned by [`crate::crawler::process_page`] and the fetches behind it, so callers can
+/// tell a timeout from a 403 or a block page without downcasting.
+#[derive(Debug, Error)]
+pub enum CrawlError {
+    /// Connecting, or waiting for the response, took longer than `[timeouts]` allow
+    #[error("Request timed out: {0}")]
+    Timeout(#[source] reqwest::Error),
+    /// 403 from the tunnel or from every proxy tried
+    #[error("403 Forbidden")]
+    Forbidden,
+    #[error(transparent)]
+    Blocked(#[from] Blocked),
+    #[error(transparent)]
+    TooLarge(#[from] PageTooLarge),
+    #[error(transparent)]
+    RateLimited(#[from] RateLimited),
+    #[error(transparent)]
+    SoftNotFound(#[from] SoftNotFound),
+    /// Rejected by its `Content-Type` header before the body was read
+    #[error("Unsupported content type: {0}")]
+    UnsupportedContentType(String),
+    /// The body does not look like HTML, see [`crate::html_parser::looks_like_html`]
+    #[error("Not an HTML page")]
+    NotHtml,
+    /// The URL could not be parsed or normalized
+    #[error("Invalid URL: {0}")]
+    Parse(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// Any other transport failure: refused connections, TLS, resets
+    #[error(transparent)]
+    Network(reqwest::Error),
+    #[error("No proxy available")]
+    NoProxy,
+}
+
+impl CrawlError {
+    /// Whether the host is struggling or pushing back: it timed out, dropped the connection,
+    /// asked us to slow down or answered with a 403 or a block page. Pages that are too
+    /// large or not HTML still came from a healthy host, so they do not trip circuit
+    /// breakers or lower concurrency.
+    pub fn is_host_failure(&self) -> bool {
+        matches!(
+            self,
+            CrawlError::Timeout(_)
+                | CrawlError::Network(_)
+                | CrawlError::RateLimited(_)
+                | CrawlError::Forbidden
+                | CrawlError::Blocked(_)
+        )
+    }
+}
+
+impl From<reqwest::Error> for CrawlError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            CrawlError::Timeout(e)
+        } else {
+            CrawlError::Network(e)
+        }
+    }
+}
+
+impl From<InvalidBaseUrl> for CrawlError {
+    fn from(e: InvalidBaseUrl) -> Self {
+        CrawlError::Parse(e.into())
+    }
+}
@@ -1,8 +1,9 @@
 use rand::{rngs::StdRng, seq::IndexedRandom, Rng, SeedableRng};
+use reqwest::header::{self, HeaderName};
 use std::{
     collections::hash_map::DefaultHasher,
     hash::{Hash, Hasher},
-    net::IpAddr,
+    time::{SystemTime, UNIX_EPOCH},
 };
 use url::Url;
 
@@ -48,25 +49,93 @@ const MOBILE_USER_AGENTS: &[(&str, f32)] = &[
 
 const DESKTOP_MOBILE_RATIO: (f32, f32) = (57.4, 42.6);
 
+const ACCEPT_LOCALES: &[(&str, f32)] = &[
+    ("en-US", 70.0),
+    ("en-GB", 15.0),
+    ("en-CA", 8.0),
+    ("en-AU", 7.0),
+];
+
+const CHROMIUM_ACCEPT: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,image/apng,*/*;q=0.8,application/signed-exchange;v=b3;q=0.7";
+const DEFAULT_ACCEPT: &str = "text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8";
+
+/// How long a proxy keeps presenting the same fingerprint before it rotates.
+pub const FINGERPRINT_WINDOW_SECS: u64 = 3600;
+
+/// Browser engine behind a user agent, which decides the headers sent alongside it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Browser {
+    /// Chrome, Edge, Opera and Samsung Internet
+    Chromium,
+    Firefox,
+    /// Safari and every other iOS browser, which all run on WebKit
+    Safari,
+}
+
+impl Browser {
+    pub fn from_user_agent(user_agent: &str) -> Self {
+        if user_agent.contains("Firefox/") {
+            Browser::Firefox
+        } else if user_agent.contains("iPhone") || !user_agent.contains("Chrome/") {
+            Browser::Safari
+        } else {
+            Browser::Chromium
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct RequestFingerprint {
     pub user_agent: String,
     pub referrer: Option<String>,
+    pub browser: Browser,
+    /// Every header of a top level navigation, in the order `browser` sends them.
+    /// `Accept-Encoding` is left to the HTTP client, which only advertises what it can decode.
+    pub headers: Vec<(HeaderName, String)>,
 }
 
 impl RequestFingerprint {
-    pub fn new(ip: &IpAddr, url: &str) -> Self {
-        let mut rng = StdRng::seed_from_u64(Self::ip_seed(ip));
+    /// The fingerprint a proxy, identified by its IP or else its address, presents during
+    /// the current window.
+    pub fn new<K: Hash + ?Sized>(proxy: &K, url: &str) -> Self {
+        Self::for_window(proxy, url, Self::current_window())
+    }
+
+    /// Same `proxy` and `window` always give the same fingerprint, so a proxy looks like one
+    /// browser for a while and switches to another once the window moves on.
+    pub fn for_window<K: Hash + ?Sized>(proxy: &K, url: &str, window: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(Self::proxy_seed(proxy, window));
+
+        let user_agent = Self::generate_user_agent(&mut rng);
+        let referrer = Self::generate_referrer(url, &mut rng);
+        let browser = Browser::from_user_agent(&user_agent);
+        let locale = ACCEPT_LOCALES
+            .choose_weighted(&mut rng, |item| item.1)
+            .unwrap()
+            .0;
+        let headers =
+            Self::generate_headers(browser, &user_agent, url, referrer.as_deref(), locale);
 
         RequestFingerprint {
-            user_agent: Self::generate_user_agent(&mut rng),
-            referrer: Self::generate_referrer(url, &mut rng),
+            user_agent,
+            referrer,
+            browser,
+            headers,
         }
     }
 
-    fn ip_seed(ip: &IpAddr) -> u64 {
+    /// Index of the [`FINGERPRINT_WINDOW_SECS`] long window we are in.
+    pub fn current_window() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs() / FINGERPRINT_WINDOW_SECS)
+            .unwrap_or_default()
+    }
+
+    fn proxy_seed<K: Hash + ?Sized>(proxy: &K, window: u64) -> u64 {
         let mut hasher = DefaultHasher::new();
-        ip.hash(&mut hasher);
+        proxy.hash(&mut hasher);
+        window.hash(&mut hasher);
         hasher.finish()
     }
 
@@ -78,7 +147,11 @@ impl RequestFingerprint {
             DESKTOP_USER_AGENTS
         };
 
-        agent_pool.choose_weighted(rng, |item| item.1).unwrap().0.to_string()
+        agent_pool
+            .choose_weighted(rng, |item| item.1)
+            .unwrap()
+            .0
+            .to_string()
     }
 
     fn generate_referrer(url: &str, rng: &mut StdRng) -> Option<String> {
@@ -86,4 +159,129 @@ impl RequestFingerprint {
         (parsed.path() != "/" && !rng.random_bool(0.1))
             .then(|| format!("{}://{}", parsed.scheme(), parsed.host_str().unwrap_or("")))
     }
+
+    fn generate_headers(
+        browser: Browser,
+        user_agent: &str,
+        url: &str,
+        referrer: Option<&str>,
+        locale: &str,
+    ) -> Vec<(HeaderName, String)> {
+        let sec_fetch_site = HeaderName::from_static("sec-fetch-site");
+        let sec_fetch_mode = HeaderName::from_static("sec-fetch-mode");
+        let sec_fetch_user = HeaderName::from_static("sec-fetch-user");
+        let sec_fetch_dest = HeaderName::from_static("sec-fetch-dest");
+        let site = fetch_site(url, referrer).to_string();
+        let referrer = referrer.unwrap_or_default().to_string();
+
+        let mut headers = match browser {
+            Browser::Chromium => client_hints(user_agent)
+                .into_iter()
+                .flatten()
+                .chain([
+                    (header::UPGRADE_INSECURE_REQUESTS, "1".to_string()),
+                    (header::USER_AGENT, user_agent.to_string()),
+                    (header::ACCEPT, CHROMIUM_ACCEPT.to_string()),
+                    (sec_fetch_site, site),
+                    (sec_fetch_mode, "navigate".to_string()),
+                    (sec_fetch_user, "?1".to_string()),
+                    (sec_fetch_dest, "document".to_string()),
+                    (header::REFERER, referrer.clone()),
+                    (header::ACCEPT_LANGUAGE, format!("{},en;q=0.9", locale)),
+                ])
+                .collect(),
+            Browser::Firefox => vec![
+                (header::USER_AGENT, user_agent.to_string()),
+                (header::ACCEPT, DEFAULT_ACCEPT.to_string()),
+                (header::ACCEPT_LANGUAGE, format!("{},en;q=0.5", locale)),
+                (header::REFERER, referrer.clone()),
+                (header::UPGRADE_INSECURE_REQUESTS, "1".to_string()),
+                (sec_fetch_dest, "document".to_string()),
+                (sec_fetch_mode, "navigate".to_string()),
+                (sec_fetch_site, site),
+                (sec_fetch_user, "?1".to_string()),
+            ],
+            Browser::Safari => vec![
+                (header::ACCEPT, DEFAULT_ACCEPT.to_string()),
+                (sec_fetch_site, site),
+                (sec_fetch_dest, "document".to_string()),
+                (header::ACCEPT_LANGUAGE, format!("{},en;q=0.9", locale)),
+                (sec_fetch_mode, "navigate".to_string()),
+                (header::USER_AGENT, user_agent.to_string()),
+                (header::REFERER, referrer.clone()),
+            ],
+        };
+        // a page opened directly carries no referrer at all
+        headers.retain(|(name, _)| name != header::REFERER || !referrer.is_empty());
+        headers
+    }
+}
+
+/// `Sec-Fetch-Site` of a navigation to `url` coming from `referrer`: `none` when typed in
+/// directly, `same-origin` or `cross-site` otherwise.
+fn fetch_site(url: &str, referrer: Option<&str>) -> &'static str {
+    let referrer = match referrer.filter(|&referrer| referrer != url) {
+        Some(referrer) => referrer,
+        None => return "none",
+    };
+    match (Url::parse(url), Url::parse(referrer)) {
+        (Ok(url), Ok(referrer)) if url.origin() == referrer.origin() => "same-origin",
+        _ => "cross-site",
+    }
+}
+
+/// The `Sec-CH-UA` hints Chromium sends by default, matching the brand, version and platform
+/// of `user_agent`. Versions before 89 did not send them.
+fn client_hints(user_agent: &str) -> Option<[(HeaderName, String); 3]> {
+    let chromium = major_version(user_agent, "Chrome/").filter(|&version| version >= 89)?;
+    let brand = [
+        ("Edg/", "Microsoft Edge"),
+        ("OPR/", "Opera"),
+        ("SamsungBrowser/", "Samsung Internet"),
+    ]
+    .into_iter()
+    .find_map(|(token, name)| Some((name, major_version(user_agent, token)?)))
+    .unwrap_or(("Google Chrome", chromium));
+    let platform = if user_agent.contains("Android") {
+        "Android"
+    } else if user_agent.contains("Windows") {
+        "Windows"
+    } else if user_agent.contains("Macintosh") {
+        "macOS"
+    } else {
+        "Linux"
+    };
+    let mobile = if user_agent.contains("Mobile") {
+        "?1"
+    } else {
+        "?0"
+    };
+
+    Some([
+        (
+            HeaderName::from_static("sec-ch-ua"),
+            format!(
+                "\"Chromium\";v=\"{}\", \"{}\";v=\"{}\", \"Not A(Brand\";v=\"8\"",
+                chromium, brand.0, brand.1
+            ),
+        ),
+        (
+            HeaderName::from_static("sec-ch-ua-mobile"),
+            mobile.to_string(),
+        ),
+        (
+            HeaderName::from_static("sec-ch-ua-platform"),
+            format!("\"{}\"", platform),
+        ),
+    ])
+}
+
+/// Major version following `token` in a user agent, e.g. 132 for `Chrome/` in `Chrome/132.0.0.0`.
+fn major_version(user_agent: &str, token: &str) -> Option<u32> {
+    let start = user_agent.find(token)? + token.len();
+    let version = &user_agent[start..];
+    let end = version
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(version.len());
+    version[..end].parse().ok()
 }
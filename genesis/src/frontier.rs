@@ -0,0 +1,89 @@
+//! Checkpoints of the URLs that were discovered but not crawled yet.
+//!
+//! The queues between discovery and fetching only live in memory, so without a checkpoint
+//! a restarted crawl loses every URL it had found but not reached. [`Frontier`] mirrors
+//! what is in flight and [`Frontier::save`] writes it out as JSON lines for [`load`] to
+//! seed the next run with.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::crawler::CrawlItem;
+
+/// URLs somewhere between being discovered and being fetched: in a channel, a domain
+/// queue, or waiting out a rate limit before they are retried.
+#[derive(Default)]
+pub struct Frontier {
+    pending: Mutex<HashMap<String, CrawlItem>>,
+}
+
+impl Frontier {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `item`, replacing a pending entry for the same URL.
+    pub fn insert(&self, item: &CrawlItem) {
+        self.pending
+            .lock()
+            .unwrap()
+            .insert(item.url.clone(), item.clone());
+    }
+
+    pub fn remove(&self, url: &str) {
+        self.pending.lock().unwrap().remove(url);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The pending items, sorted by URL so successive checkpoints diff cleanly.
+    pub fn snapshot(&self) -> Vec<CrawlItem> {
+        let mut items: Vec<_> = self.pending.lock().unwrap().values().cloned().collect();
+        items.sort_by(|a, b| a.url.cmp(&b.url));
+        items
+    }
+
+    /// Writes the pending items to `path`, one JSON object per line. The file is replaced
+    /// in one rename so a crash mid-write leaves the previous checkpoint intact.
+    pub fn save(&self, path: &Path) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let items = self.snapshot();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("tmp");
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+        for item in &items {
+            serde_json::to_writer(&mut file, item)?;
+            file.write_all(b"\n")?;
+        }
+        file.into_inner()?.sync_all()?;
+        std::fs::rename(&tmp, path)?;
+        Ok(items.len())
+    }
+}
+
+/// Reads a checkpoint written by [`Frontier::save`], empty when there is none yet.
+pub fn load(path: &Path) -> Result<Vec<CrawlItem>, Box<dyn std::error::Error + Send + Sync>> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut items = Vec::new();
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            items.push(serde_json::from_str(&line)?);
+        }
+    }
+    Ok(items)
+}
@@ -1,5 +1,8 @@
-use crate::db::MetaTag;
-use lol_html::{element, text, HtmlRewriter, Settings};
+use crate::db::{Heading, MetaTag};
+use encoding_rs::{Encoding, UTF_8};
+use lol_html::{element, text, AsciiCompatibleEncoding, HtmlRewriter, Settings};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
 use std::collections::HashSet;
 use std::sync::Arc;
 use std::sync::Mutex;
@@ -7,45 +10,236 @@ use url::Url;
 
 pub struct ParsedHtml {
     pub links: Vec<String>,
+    /// Links marked `rel="nofollow"`, kept out of `links` so they are never enqueued
+    pub nofollow_links: Vec<String>,
     pub language: String,
     pub title: String,
     pub meta_tags: Vec<MetaTag>,
+    /// From `<meta name="robots">`
+    pub robots_directives: RobotsMeta,
     pub canonical_url: Option<String>,
     pub content_text: String,
+    pub headings: Vec<Heading>,
+    /// Every `application/ld+json` block that parsed as JSON
+    pub structured_data: Vec<serde_json::Value>,
+    /// First `<link rel="icon">`, absolute
+    pub favicon_url: Option<String>,
+    /// `og:image`, or else the first `<img>` not declared smaller than [`MIN_IMAGE_PX`], absolute
+    pub image_url: Option<String>,
+    /// `(hreflang, url)` of each `<link rel="alternate" hreflang>`, absolute, in document order
+    pub alternates: Vec<(String, String)>,
 }
 
-pub fn parse_html(html: &[u8], base_url: &str) -> Result<ParsedHtml, Box<dyn std::error::Error>> {
+/// The `noindex` and `nofollow` robots directives, from a `<meta name="robots">` tag or an
+/// `X-Robots-Tag` header. `none` sets both.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RobotsMeta {
+    /// The page must not be stored
+    pub noindex: bool,
+    /// The page's links must not be followed
+    pub nofollow: bool,
+}
+
+impl RobotsMeta {
+    /// Parses a comma separated list such as `noindex, nofollow`, ignoring other directives.
+    pub fn parse(directives: &str) -> Self {
+        directives
+            .split(',')
+            .map(|directive| directive.trim().to_ascii_lowercase())
+            .fold(RobotsMeta::default(), |robots, directive| RobotsMeta {
+                noindex: robots.noindex || directive == "noindex" || directive == "none",
+                nofollow: robots.nofollow || directive == "nofollow" || directive == "none",
+            })
+    }
+
+    /// Parses one `X-Robots-Tag` value. Values scoped to a named crawler, like
+    /// `googlebot: noindex`, do not apply to us, nor does a lone `unavailable_after`.
+    pub fn from_header(value: &str) -> Self {
+        match value.split_once(':') {
+            Some((prefix, _)) if !prefix.contains(',') => RobotsMeta::default(),
+            _ => Self::parse(value),
+        }
+    }
+
+    /// Directives from either source apply.
+    pub fn merge(self, other: RobotsMeta) -> Self {
+        RobotsMeta {
+            noindex: self.noindex || other.noindex,
+            nofollow: self.nofollow || other.nofollow,
+        }
+    }
+}
+
+/// Images declaring a smaller width or height are icons, badges or tracking pixels
+pub const MIN_IMAGE_PX: u32 = 200;
+
+const NON_HTML_CONTENT_TYPES: [&str; 8] = [
+    "json",
+    "application/pdf",
+    "application/octet-stream",
+    "application/zip",
+    "image/",
+    "audio/",
+    "video/",
+    "font/",
+];
+const SNIFF_LEN: usize = 1024;
+
+/// Cheap check, run before `parse_html`, that `body` is worth rewriting. Trusts an
+/// explicit HTML content type, rejects known non-HTML ones and otherwise sniffs the
+/// first bytes for JSON, XML, PDF and binary signatures.
+pub fn looks_like_html(body: &[u8], content_type: Option<&str>) -> bool {
+    if let Some(content_type) = content_type.map(str::to_lowercase) {
+        if content_type.contains("html") {
+            return true;
+        }
+        if NON_HTML_CONTENT_TYPES
+            .iter()
+            .any(|non_html| content_type.contains(non_html))
+        {
+            return false;
+        }
+    }
+
+    let head = &body[..body.len().min(SNIFF_LEN)];
+    let head = head.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(head);
+    let start = head
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(head.len());
+    let head = &head[start..];
+
+    if head.starts_with(b"%PDF") || head.contains(&0) {
+        return false;
+    }
+    if head.starts_with(b"{") || head.starts_with(b"[") {
+        return false;
+    }
+    if head.starts_with(b"<?xml") {
+        // XHTML documents also open with an XML declaration
+        return String::from_utf8_lossy(head)
+            .to_lowercase()
+            .contains("<html");
+    }
+    true
+}
+
+/// Returned by [`parse_base_url`] for a page URL that cannot be parsed.
+#[derive(Debug, thiserror::Error)]
+#[error("invalid base URL {url:?}: {source}")]
+pub struct InvalidBaseUrl {
+    pub url: String,
+    #[source]
+    pub source: url::ParseError,
+}
+
+/// Parses the URL a page was fetched from, for [`parse_html`].
+pub fn parse_base_url(url: &str) -> Result<Url, InvalidBaseUrl> {
+    Url::parse(url).map_err(|source| InvalidBaseUrl {
+        url: url.to_string(),
+        source,
+    })
+}
+
+/// Extracts links, metadata and text from a UTF-8 page. Malformed markup never fails, whatever
+/// could be made sense of is returned.
+pub fn parse_html(html: &[u8], base_url: &Url) -> ParsedHtml {
+    parse_html_with_encoding(html, base_url, UTF_8)
+}
+
+/// Like [`parse_html`], for a body in `encoding`. Encodings lol_html cannot stream
+/// (UTF-16, ISO-2022-JP) are transcoded to UTF-8 first.
+pub fn parse_html_with_encoding(
+    html: &[u8],
+    base_url: &Url,
+    encoding: &'static Encoding,
+) -> ParsedHtml {
+    let (html, encoding) = match AsciiCompatibleEncoding::new(encoding) {
+        Some(encoding) => (Cow::Borrowed(html), encoding),
+        None => {
+            let (text, _, _) = encoding.decode(html);
+            let utf8 = AsciiCompatibleEncoding::new(UTF_8).expect("UTF-8 is ASCII compatible");
+            (Cow::Owned(text.into_owned().into_bytes()), utf8)
+        }
+    };
+
     let mut result = ParsedHtml {
         links: Vec::new(),
+        nofollow_links: Vec::new(),
         language: String::new(),
         title: String::new(),
         meta_tags: Vec::new(),
+        robots_directives: RobotsMeta::default(),
         canonical_url: None,
         content_text: String::new(),
+        headings: Vec::new(),
+        structured_data: Vec::new(),
+        favicon_url: None,
+        image_url: None,
+        alternates: Vec::new(),
     };
 
-    let base_url = Url::parse(base_url)?;
-    let links = Arc::new(Mutex::new(HashSet::new()));
+    // hrefs are resolved once the whole document is seen, since `<base href>` applies to all of them
+    let mut hrefs = Vec::new();
+    let mut base_href = None;
+    let mut favicon_href = None;
+    let mut alternate_hrefs = Vec::new();
+    let mut image_src = None;
     let title = Arc::new(Mutex::new(String::new()));
     let content = Arc::new(Mutex::new(String::new()));
+    let headings = Arc::new(Mutex::new(Vec::new()));
 
-    let links_clone = links.clone();
     let title_clone = title.clone();
     let content_clone = content.clone();
+    let mut content_node = String::new();
+    let headings_open = headings.clone();
+    let headings_text = headings.clone();
+    let mut json_ld_block = String::new();
 
     let mut rewriter = HtmlRewriter::new(
         Settings {
             element_content_handlers: vec![
-                element!("a[href]", move |el| {
+                element!("a[href]", |el| {
                     if let Some(href) = el.get_attribute("href") {
-                        if let Ok(mut url) = base_url.join(&href) {
-                            url.set_fragment(None);
-                            if (url.scheme() == "http" || url.scheme() == "https")
-                                && !is_ignored_file_type(url.path())
-                            {
-                                links_clone.lock().unwrap().insert(url.to_string());
-                            }
-                        }
+                        let nofollow = el.get_attribute("rel").is_some_and(|rel| {
+                            rel.split_ascii_whitespace()
+                                .any(|token| token.eq_ignore_ascii_case("nofollow"))
+                        });
+                        hrefs.push((decode_entities(&href), nofollow));
+                    }
+                    Ok(())
+                }),
+                element!("base[href]", |el| {
+                    // only the first base element counts
+                    if base_href.is_none() {
+                        base_href = el.get_attribute("href").map(|href| decode_entities(&href));
+                    }
+                    Ok(())
+                }),
+                element!("link[rel][href]", |el| {
+                    let rel = el.get_attribute("rel").unwrap_or_default();
+                    let has_rel = |name: &str| {
+                        rel.split_ascii_whitespace()
+                            .any(|token| token.eq_ignore_ascii_case(name))
+                    };
+                    let href = el.get_attribute("href").map(|href| decode_entities(&href));
+                    if has_rel("icon") && favicon_href.is_none() {
+                        favicon_href = href.clone();
+                    }
+                    if let (true, Some(lang), Some(href)) =
+                        (has_rel("alternate"), el.get_attribute("hreflang"), href)
+                    {
+                        alternate_hrefs.push((lang.trim().to_string(), href));
+                    }
+                    Ok(())
+                }),
+                element!("img[src]", |el| {
+                    let src = el.get_attribute("src").unwrap_or_default();
+                    if image_src.is_none()
+                        && !src.trim_start().to_ascii_lowercase().starts_with("data:")
+                        && !declared_smaller_than(el, MIN_IMAGE_PX)
+                    {
+                        image_src = Some(decode_entities(&src));
                     }
                     Ok(())
                 }),
@@ -66,39 +260,200 @@ pub fn parse_html(html: &[u8], base_url: &str) -> Result<ParsedHtml, Box<dyn std
                         .or_else(|| el.get_attribute("property"))
                         .unwrap_or_default();
                     if let Some(content) = el.get_attribute("content") {
-                        result.meta_tags.push(MetaTag { name, content });
+                        result.meta_tags.push(MetaTag {
+                            name,
+                            content: decode_entities(&content),
+                        });
                     }
                     Ok(())
                 }),
                 element!("link[rel='canonical']", |el| {
                     if let Some(href) = el.get_attribute("href") {
-                        result.canonical_url = Some(href);
+                        result.canonical_url = Some(decode_entities(&href));
+                    }
+                    Ok(())
+                }),
+                element!("h1, h2, h3, h4, h5, h6", move |el| {
+                    let level = el.tag_name()[1..].parse().unwrap_or(1);
+                    headings_open.lock().unwrap().push(Heading {
+                        level,
+                        text: String::new(),
+                    });
+                    Ok(())
+                }),
+                text!("h1, h2, h3, h4, h5, h6", move |t| {
+                    if let Some(heading) = headings_text.lock().unwrap().last_mut() {
+                        heading.text.push_str(t.as_str());
                     }
                     Ok(())
                 }),
                 element!("h1, h2, h3, h4, h5, h6, p, li", |_| Ok(())),
                 text!("h1, h2, h3, h4, h5, h6, p, li", move |t| {
-                    let mut content = content_clone.lock().unwrap();
-                    if !content.is_empty() {
-                        content.push(' ');
+                    // chunks can end mid-word or mid-entity, so each text node is decoded whole
+                    content_node.push_str(t.as_str());
+                    if t.last_in_text_node() {
+                        let text = decode_entities(&content_node);
+                        let text = text.trim();
+                        if !text.is_empty() {
+                            let mut content = content_clone.lock().unwrap();
+                            if !content.is_empty() {
+                                content.push(' ');
+                            }
+                            content.push_str(text);
+                        }
+                        content_node.clear();
+                    }
+                    Ok(())
+                }),
+                text!("script[type='application/ld+json']", |t| {
+                    json_ld_block.push_str(t.as_str());
+                    if t.last_in_text_node() {
+                        // malformed blocks are common and simply skipped
+                        if let Ok(value) = serde_json::from_str(json_ld_block.trim()) {
+                            result.structured_data.push(value);
+                        }
+                        json_ld_block.clear();
                     }
-                    content.push_str(t.as_str().trim());
                     Ok(())
                 }),
             ],
+            encoding,
+            // ambiguous text (e.g. a stray `<svg>` in a `<select>`) is parsed on a best guess
+            // instead of aborting the whole page
+            strict: false,
             ..Settings::default()
         },
         |_: &[u8]| {},
     );
 
-    rewriter.write(html)?;
-    rewriter.end()?;
+    // with infallible handlers, no memory limit and strict mode off the rewriter cannot fail,
+    // and if it ever did whatever was collected so far is still worth keeping
+    let _ = rewriter.write(&html).and_then(|()| rewriter.end());
 
-    result.links = links.lock().unwrap().iter().cloned().collect();
-    result.title = title.lock().unwrap().clone();
+    let link_base = base_href
+        .and_then(|href| base_url.join(href.trim()).ok())
+        .filter(|url| url.scheme() == "http" || url.scheme() == "https")
+        .unwrap_or_else(|| base_url.clone());
+    let mut links = HashSet::new();
+    let mut nofollow_links = HashSet::new();
+    for (href, nofollow) in &hrefs {
+        if let Some(url) = resolve_link(&link_base, href) {
+            if *nofollow {
+                nofollow_links.insert(url);
+            } else {
+                links.insert(url);
+            }
+        }
+    }
+    result.favicon_url = favicon_href.and_then(|href| resolve_resource(&link_base, &href));
+    result.alternates = alternate_hrefs
+        .into_iter()
+        .filter(|(lang, _)| !lang.is_empty())
+        .filter_map(|(lang, href)| Some((lang, resolve_link(&link_base, &href)?)))
+        .collect();
+    let og_image = result
+        .meta_tags
+        .iter()
+        .find(|tag| tag.name == "og:image")
+        .and_then(|tag| resolve_resource(&link_base, &tag.content));
+    result.image_url =
+        og_image.or_else(|| image_src.and_then(|src| resolve_resource(&link_base, &src)));
+    result.links = links.into_iter().collect();
+    result.robots_directives = result
+        .meta_tags
+        .iter()
+        .filter(|tag| tag.name.eq_ignore_ascii_case("robots"))
+        .fold(RobotsMeta::default(), |robots, tag| {
+            robots.merge(RobotsMeta::parse(&tag.content))
+        });
+    result.nofollow_links = nofollow_links
+        .into_iter()
+        .filter(|url| !result.links.contains(url))
+        .collect();
+    result.title = decode_entities(&title.lock().unwrap());
     result.content_text = content.lock().unwrap().clone();
+    result.headings = headings
+        .lock()
+        .unwrap()
+        .drain(..)
+        .filter_map(|heading| {
+            let words: Vec<&str> = heading.text.split_whitespace().collect();
+            let text = decode_entities(&words.join(" "));
+            (!text.is_empty()).then_some(Heading { text, ..heading })
+        })
+        .collect();
+
+    result
+}
+
+// lol_html hands out attribute values and text verbatim, entities included
+pub(crate) fn decode_entities(raw: &str) -> String {
+    html_escape::decode_html_entities(raw).into_owned()
+}
+
+// schemes that never point at a crawlable document
+const NON_NAVIGATIONAL_SCHEMES: [&str; 7] = [
+    "javascript:",
+    "mailto:",
+    "tel:",
+    "data:",
+    "sms:",
+    "ftp:",
+    "file:",
+];
+
+fn resolve_link(base_url: &Url, href: &str) -> Option<String> {
+    let href = href.trim();
+    if href.is_empty() || href.starts_with('#') {
+        return None;
+    }
+
+    let href_lower = href.to_ascii_lowercase();
+    if NON_NAVIGATIONAL_SCHEMES
+        .iter()
+        .any(|&scheme| href_lower.starts_with(scheme))
+    {
+        return None;
+    }
+
+    // protocol-relative links inherit the scheme of the page they were found on
+    let mut url = match href.strip_prefix("//") {
+        Some(rest) => Url::parse(&format!("{}://{}", base_url.scheme(), rest)).ok()?,
+        None => base_url.join(href).ok()?,
+    };
+    url.set_fragment(None);
+
+    if (url.scheme() == "http" || url.scheme() == "https") && !is_ignored_file_type(url.path()) {
+        Some(url.to_string())
+    } else {
+        None
+    }
+}
+
+/// Resolves the URL of an image or icon. Unlike [`resolve_link`] media files are the point.
+fn resolve_resource(base_url: &Url, href: &str) -> Option<String> {
+    let href = href.trim();
+    if href.is_empty() || href.to_ascii_lowercase().starts_with("data:") {
+        return None;
+    }
+    let mut url = base_url.join(href).ok()?;
+    url.set_fragment(None);
+    (url.scheme() == "http" || url.scheme() == "https").then(|| url.to_string())
+}
 
-    Ok(result)
+fn declared_smaller_than(el: &lol_html::html_content::Element, min_px: u32) -> bool {
+    ["width", "height"].iter().any(|dimension| {
+        el.get_attribute(dimension)
+            .and_then(|value| {
+                let digits: String = value
+                    .trim()
+                    .chars()
+                    .take_while(char::is_ascii_digit)
+                    .collect();
+                digits.parse::<u32>().ok()
+            })
+            .is_some_and(|px| px < min_px)
+    })
 }
 
 fn is_ignored_file_type(path: &str) -> bool {
@@ -151,7 +506,7 @@ fn is_ignored_file_type(path: &str) -> bool {
     if path_lower.contains("/pdf/")
         && path_lower
             .split('/')
-            .last()
+            .next_back()
             .map(|s| s.chars().all(|c| c.is_numeric() || c == '.'))
             .unwrap_or(false)
     {
@@ -0,0 +1,71 @@
+//! Genesis is the crawler behind Vyntr.
+//!
+//! The binary is a thin wrapper around [`Crawler`]; embed it directly to drive a crawl
+//! from your own tool and consume the resulting [`SeoAnalysis`] stream.
+
+pub mod adaptive;
+pub mod block_page;
+pub mod charset;
+pub mod circuit;
+pub mod config;
+pub mod crawler;
+pub mod db;
+pub mod dedup;
+pub mod domain_filter;
+pub mod error;
+pub mod fingerprint;
+pub mod frontier;
+pub mod html_parser;
+pub mod lang_detect;
+pub mod limiter;
+pub mod logger;
+pub mod metrics;
+pub mod network;
+pub mod proxy;
+pub mod readability;
+pub mod sink;
+pub mod sitemap;
+pub mod soft404;
+pub mod utils;
+
+use std::env;
+
+pub use crate::config::Config;
+pub use crate::crawler::{Crawler, CrawlerBuilder};
+pub use crate::db::{Heading, MetaTag, SeoAnalysis};
+pub use crate::error::CrawlError;
+pub use crate::sink::Sink;
+
+lazy_static::lazy_static! {
+    pub static ref PROXY_TUNNEL_URL: String = env::var("PROXY_TUNNEL_URL")
+        .expect("PROXY_TUNNEL_URL must be set in environment");
+}
+
+#[macro_export]
+#[cfg(debug_assertions)]
+macro_rules! debug_only {
+    ($($stmt:stmt)*) => {
+        $($stmt)*
+    };
+}
+
+#[macro_export]
+#[cfg(not(debug_assertions))]
+macro_rules! debug_only {
+    ($($stmt:stmt)*) => {
+        // ... skidibvi
+    };
+}
+
+/// `println!` for progress and diagnostics, sent to stderr instead once
+/// [`utils::diagnostics_to_stderr`] is on so stdout only carries crawl output.
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::utils::diagnostics_on_stderr() {
+            eprintln!($($arg)*);
+        } else {
+            println!($($arg)*);
+        }
+    };
+}
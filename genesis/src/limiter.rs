@@ -0,0 +1,114 @@
+use dashmap::DashMap;
+use rand::Rng;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// Caps the number of simultaneous requests to any single host.
+pub struct DomainLimiter {
+    semaphores: DashMap<String, Arc<Semaphore>>,
+    max_per_domain: usize,
+}
+
+impl DomainLimiter {
+    pub fn new(max_per_domain: usize) -> Self {
+        DomainLimiter {
+            semaphores: DashMap::new(),
+            max_per_domain: max_per_domain.max(1),
+        }
+    }
+
+    /// Waits until fewer than `max_per_domain` requests to `domain` are in flight. The slot
+    /// is freed when the returned permit is dropped.
+    pub async fn acquire(&self, domain: &str) -> OwnedSemaphorePermit {
+        let semaphore = self
+            .semaphores
+            .entry(domain.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.max_per_domain)))
+            .clone();
+        semaphore
+            .acquire_owned()
+            .await
+            .expect("domain semaphores are never closed")
+    }
+}
+
+/// Keeps requests to the same host at least `min_delay` apart, plus up to `jitter` so hosts
+/// that came up in the same batch do not get hit in lockstep.
+pub struct DomainSpacing {
+    /// When each host was last given a slot, which may be in the future
+    last_request: DashMap<String, Instant>,
+    min_delay: Duration,
+    jitter: Duration,
+}
+
+impl DomainSpacing {
+    pub fn new(min_delay: Duration, jitter: Duration) -> Self {
+        DomainSpacing {
+            last_request: DashMap::new(),
+            min_delay,
+            jitter,
+        }
+    }
+
+    /// Waits for the next free slot of `domain`. Slots are handed out in call order, so
+    /// concurrent callers queue up behind each other instead of all firing at once.
+    pub async fn wait(&self, domain: &str) {
+        let now = Instant::now();
+        let slot = match self.last_request.entry(domain.to_string()) {
+            dashmap::Entry::Occupied(mut last) => {
+                let jitter = self.jitter.mul_f64(rand::rng().random_range(0.0..=1.0));
+                let slot = (*last.get() + self.min_delay + jitter).max(now);
+                last.insert(slot);
+                slot
+            }
+            dashmap::Entry::Vacant(last) => *last.insert(now),
+        };
+        tokio::time::sleep_until(slot).await;
+    }
+}
+
+/// Caps the total number of pages crawled from any single host.
+pub struct DomainPageCap {
+    counts: DashMap<String, AtomicUsize>,
+    max_pages: usize,
+}
+
+/// Outcome of [`DomainPageCap::take`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageSlot {
+    Granted,
+    /// Granted, and the domain has now reached its cap
+    Last,
+    Capped,
+}
+
+impl DomainPageCap {
+    pub fn new(max_pages: usize) -> Self {
+        DomainPageCap {
+            counts: DashMap::new(),
+            max_pages: max_pages.max(1),
+        }
+    }
+
+    pub fn is_capped(&self, domain: &str) -> bool {
+        self.counts
+            .get(domain)
+            .is_some_and(|count| count.load(Ordering::Relaxed) >= self.max_pages)
+    }
+
+    /// Counts a page against `domain`, unless the domain already hit its cap.
+    pub fn take(&self, domain: &str) -> PageSlot {
+        let count = self.counts.entry(domain.to_string()).or_default();
+        let taken = count.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |taken| {
+            (taken < self.max_pages).then_some(taken + 1)
+        });
+        match taken {
+            Ok(previous) if previous + 1 == self.max_pages => PageSlot::Last,
+            Ok(_) => PageSlot::Granted,
+            Err(_) => PageSlot::Capped,
+        }
+    }
+}
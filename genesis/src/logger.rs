@@ -1,56 +1,201 @@
 use chrono::Local;
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::fs::{File, OpenOptions};
 use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
+use crate::status;
+
 const ANIME_NAMES: &[&str] = &[
     "yuki", "sakura", "mikasa", "miku", "asuka", "rei", "misato", "hinata", "tohru", "zero", "rem",
     "ram", "emilia", "aqua", "nami", "lucy", "erza", "asuna", "misaka", "saber", "rin", "mai",
     "nezuko", "ichigo",
 ];
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `[timestamp] message` lines
+    #[default]
+    Text,
+    /// One JSON object per line, see [`LogEvent`]
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/// A structured log entry, written as `{"ts":...,"level":...,"event":...,"url":...}`
+/// in JSON mode.
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEvent {
+    pub level: LogLevel,
+    pub event: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl LogEvent {
+    pub fn new(level: LogLevel, event: impl Into<String>) -> Self {
+        LogEvent {
+            level,
+            event: event.into(),
+            url: None,
+            message: None,
+        }
+    }
+
+    pub fn info(event: impl Into<String>) -> Self {
+        Self::new(LogLevel::Info, event)
+    }
+
+    pub fn url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = Some(message.into());
+        self
+    }
+}
+
+#[derive(Serialize)]
+struct TimestampedEvent<'a> {
+    ts: String,
+    #[serde(flatten)]
+    event: &'a LogEvent,
+}
+
+/// Size based rotation: once the log grows past `max_bytes` it is moved to
+/// `<stem>.1.log`, shifting older files up, and at most `max_files` of them are kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rotation {
+    pub max_bytes: u64,
+    pub max_files: usize,
+}
+
 pub struct AsyncLogger {
     buffer: Vec<String>,
     file: File,
+    path: PathBuf,
+    written: u64,
+    rotation: Option<Rotation>,
     buffer_size: usize,
+    format: LogFormat,
 }
 
 impl AsyncLogger {
     pub fn new(buffer_size: usize) -> std::io::Result<Arc<Mutex<Self>>> {
+        Self::with_format(buffer_size, LogFormat::Text)
+    }
+
+    pub fn with_format(
+        buffer_size: usize,
+        format: LogFormat,
+    ) -> std::io::Result<Arc<Mutex<Self>>> {
+        Self::open(Self::default_path()?, buffer_size, format, None)
+    }
+
+    /// `logs/crawler-<name>.log` under the working directory, with a random name per run.
+    pub fn default_path() -> std::io::Result<PathBuf> {
         let mut rng = rand::rng();
         let idx = rng.random_range(0..ANIME_NAMES.len());
         let name = ANIME_NAMES[idx];
 
         let base_dir = std::env::current_dir()?;
-        let log_dir = base_dir.join("logs");
-        std::fs::create_dir_all(&log_dir)?;
+        Ok(base_dir.join("logs").join(format!("crawler-{}.log", name)))
+    }
 
-        let filename = log_dir.join(format!("crawler-{}.log", name));
+    pub fn open(
+        path: impl Into<PathBuf>,
+        buffer_size: usize,
+        format: LogFormat,
+        rotation: Option<Rotation>,
+    ) -> std::io::Result<Arc<Mutex<Self>>> {
+        let path = path.into();
+        if let Some(log_dir) = path.parent() {
+            std::fs::create_dir_all(log_dir)?;
+        }
 
-        println!("Creating log file: {}", filename.display());
+        status!("Creating log file: {}", path.display());
 
-        let file = OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .append(true)
-            .open(&filename)?;
+        let file = Self::open_file(&path)?;
+        let written = file.metadata()?.len();
 
         Ok(Arc::new(Mutex::new(Self {
             buffer: Vec::with_capacity(buffer_size),
             file,
+            path,
+            written,
+            rotation,
             buffer_size,
+            format,
         })))
     }
 
+    fn open_file(path: &Path) -> std::io::Result<File> {
+        OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+    }
+
+    /// Logs a free-form message. In JSON mode it becomes an info `log` event.
     pub fn add_entry(&mut self, message: String) -> std::io::Result<()> {
-        self.buffer.push(format!(
-            "[{}] {}\n",
-            Local::now().format("%Y-%m-%d %H:%M:%S"),
-            message
-        ));
+        match self.format {
+            LogFormat::Text => {
+                let line = format!(
+                    "[{}] {}\n",
+                    Local::now().format("%Y-%m-%d %H:%M:%S"),
+                    message
+                );
+                self.push_line(line)
+            }
+            LogFormat::Json => self.add_event(LogEvent::info("log").message(message)),
+        }
+    }
+
+    pub fn add_event(&mut self, event: LogEvent) -> std::io::Result<()> {
+        let line = match self.format {
+            LogFormat::Text => {
+                let mut line = format!(
+                    "[{}] {:?} {}",
+                    Local::now().format("%Y-%m-%d %H:%M:%S"),
+                    event.level,
+                    event.event
+                );
+                for detail in [&event.url, &event.message].into_iter().flatten() {
+                    line.push(' ');
+                    line.push_str(detail);
+                }
+                line + "\n"
+            }
+            LogFormat::Json => {
+                let entry = TimestampedEvent {
+                    ts: Local::now().to_rfc3339(),
+                    event: &event,
+                };
+                serde_json::to_string(&entry)? + "\n"
+            }
+        };
+        self.push_line(line)
+    }
+
+    fn push_line(&mut self, line: String) -> std::io::Result<()> {
+        self.buffer.push(line);
 
         if self.buffer.len() >= self.buffer_size {
             self.flush()?;
@@ -60,10 +205,50 @@ impl AsyncLogger {
 
     pub fn flush(&mut self) -> std::io::Result<()> {
         if !self.buffer.is_empty() {
-            self.file.write_all(self.buffer.concat().as_bytes())?;
+            let content = self.buffer.concat();
+            self.file.write_all(content.as_bytes())?;
             self.file.flush()?;
             self.buffer.clear();
+            self.written += content.len() as u64;
+        }
+
+        if let Some(rotation) = self.rotation {
+            if self.written >= rotation.max_bytes {
+                self.rotate(rotation.max_files)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `crawler-<name>.N.log` for the given rotation index
+    pub fn rotated_path(&self, index: usize) -> PathBuf {
+        let stem = self
+            .path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.path.with_file_name(format!("{}.{}.log", stem, index))
+    }
+
+    fn rotate(&mut self, max_files: usize) -> std::io::Result<()> {
+        if max_files == 0 {
+            std::fs::remove_file(&self.path)?;
+        } else {
+            let oldest = self.rotated_path(max_files);
+            if oldest.exists() {
+                std::fs::remove_file(&oldest)?;
+            }
+            for index in (1..max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    std::fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            std::fs::rename(&self.path, self.rotated_path(1))?;
         }
+
+        self.file = Self::open_file(&self.path)?;
+        self.written = 0;
         Ok(())
     }
 }
@@ -1,68 +1,72 @@
-mod crawler;
-mod db;
-mod fingerprint;
-mod html_parser;
-mod logger;
-mod metrics;
-mod network;
-mod proxy;
-mod utils;
-
-use std::collections::HashSet;
-use std::env;
 use std::fs;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 
-use fingerprint::RequestFingerprint;
+use clap::Parser;
 use futures::StreamExt;
-use rand::rngs::StdRng;
-use rand::seq::SliceRandom;
-use rand::SeedableRng;
-use tokio::sync::{Mutex, Semaphore};
-use tokio_stream::wrappers::UnboundedReceiverStream;
-
-use crate::crawler::{DomainQueues, extract_domain};
-use crate::metrics::Metrics;
-use crate::utils::{normalize_url, print_request_status};
-use crate::logger::AsyncLogger;
-use crate::proxy::ProxyManager;
-use crate::db::{create_db_pool, save_analyses_batch, SeoAnalysis};
-use crate::network::try_tunnel_request;
+use serde::Serialize;
+
+use genesis::config::{Cli, Config};
+use genesis::crawler::process_page;
+use genesis::db::SeoAnalysis;
+use genesis::logger::AsyncLogger;
+use genesis::metrics::Metrics;
+use genesis::proxy::ProxyManager;
+use genesis::sink::sink_from_config;
+use genesis::utils::diagnostics_to_stderr;
+use genesis::{status, Crawler};
 
-const MAX_PAGES: usize = 50_000;
-const CONCURRENCY: usize = 5_000;
-const DB_CONCURRENCY: usize = 20;
-const BATCH_SIZE: usize = 2_000;
-const MAX_TUNNEL_RETRIES: usize = 2;
 const LOG_BUFFER_SIZE: usize = 10000;
-const INACTIVITY_TIMEOUT: Duration = Duration::from_secs(60);
-
-lazy_static::lazy_static! {
-    static ref PROXY_TUNNEL_URL: String = env::var("PROXY_TUNNEL_URL")
-        .expect("PROXY_TUNNEL_URL must be set in environment");
-}
-
-#[macro_export]
-#[cfg(debug_assertions)]
-macro_rules! debug_only {
-    ($($stmt:stmt)*) => {
-        $($stmt)*
-    };
+const TOP_DOMAINS_LOGGED: usize = 10;
+
+/// What `--url` prints
+#[derive(Serialize)]
+struct PageReport<'a> {
+    #[serde(flatten)]
+    analysis: &'a SeoAnalysis,
+    links: &'a [String],
 }
 
-#[macro_export]
-#[cfg(not(debug_assertions))]
-macro_rules! debug_only {
-    ($($stmt:stmt)*) => {
-        // ... skidibvi
+/// Runs one page through the normal tunnel and proxy flow and prints everything extracted
+/// from it, without a sink, frontier or log file.
+async fn inspect_url(
+    url: &str,
+    config: &Config,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let proxy_manager = ProxyManager::new(config)?;
+    let metrics = Arc::new(Metrics::default());
+    let (links, analysis) = process_page(url, config, &proxy_manager, None, None, &metrics).await?;
+    let report = PageReport {
+        analysis: &analysis,
+        links: &links,
     };
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let logger = AsyncLogger::new(LOG_BUFFER_SIZE)?;
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    dotenvy::dotenv().ok();
+    let cli = Cli::parse();
+    let config = Config::load(&cli)?;
+    // no config dump, stdout is for the analysis
+    if let Some(url) = &cli.url {
+        diagnostics_to_stderr(true);
+        return inspect_url(url, &config).await;
+    }
+    // the stdout sink writes its JSONL there, so everything else goes to stderr
+    if !config.crawl.dry_run && config.sink.kind.eq_ignore_ascii_case("stdout") {
+        diagnostics_to_stderr(true);
+    }
+    status!("Resolved config:\n{}", config.to_toml());
+
+    let logger = AsyncLogger::open(
+        AsyncLogger::default_path()?,
+        LOG_BUFFER_SIZE,
+        config.logging.format,
+        config.logging.rotation(),
+    )?;
 
     tokio::spawn({
         let logger = logger.clone();
@@ -77,6 +81,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let metrics = Arc::new(Metrics::default());
     let start_time = Instant::now();
+
+    if let Ok(port) = std::env::var("METRICS_PORT") {
+        let port: u16 = port.parse()?;
+        status!("Serving Prometheus metrics at http://0.0.0.0:{}/metrics", port);
+        tokio::spawn({
+            let metrics = metrics.clone();
+            async move {
+                if let Err(e) = genesis::metrics::serve_prometheus(port, metrics).await {
+                    eprintln!("Metrics server stopped: {}", e);
+                }
+            }
+        });
+    }
     tokio::spawn({
         let logger = logger.clone();
         let metrics = metrics.clone();
@@ -93,306 +110,149 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 };
 
                 let metrics_str = format!(
-                    "[Metrics] Total: {}, Success: {}, Tunnel: {}, Proxy: {}, T-P Rate: {:.2}, Failed: {}, Left: {}, Rate: {:.2} req/sec",
+                    "[Metrics] Total: {}, Success: {}, Tunnel: {}, Proxy: {}, T-P Rate: {:.2}, Failed: {} ({}), Rate limited: {}, Soft 404s: {}, Thin: {}, 403 retries: {}, Left: {}, Concurrency: {}, Depth: {}, Capped: {}, Rate: {:.2} req/sec",
                     metrics.total.load(Ordering::Relaxed),
                     metrics.success.load(Ordering::Relaxed),
                     metrics.tunnel.load(Ordering::Relaxed),
                     metrics.proxy.load(Ordering::Relaxed),
                     t_p_rate,
                     metrics.failed.load(Ordering::Relaxed),
+                    metrics.failures(),
+                    metrics.rate_limited.load(Ordering::Relaxed),
+                    metrics.soft_404s.load(Ordering::Relaxed),
+                    metrics.thin_pages.load(Ordering::Relaxed),
+                    metrics.forbidden_retries.load(Ordering::Relaxed),
                     metrics.total_left.load(Ordering::Relaxed),
+                    metrics.effective_concurrency.load(Ordering::Relaxed),
+                    metrics.max_depth.load(Ordering::Relaxed),
+                    metrics.capped_domains.load(Ordering::Relaxed),
                     metrics.total.load(Ordering::Relaxed) as f64 / elapsed
                 );
+                let top_domains = metrics
+                    .top_domains(TOP_DOMAINS_LOGGED)
+                    .into_iter()
+                    .map(|(domain, stats)| {
+                        format!(
+                            "{} ({} total, {} ok, {} failed)",
+                            domain, stats.total, stats.success, stats.failed
+                        )
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
                 let mut log = logger.lock().await;
                 let _ = log.add_entry(metrics_str);
+                if !top_domains.is_empty() {
+                    let _ = log.add_entry(format!("[Domains] {}", top_domains));
+                }
                 let _ = log.flush();
             }
         }
     });
 
-    // inactivity checker task
-    tokio::spawn({
-        let metrics = metrics.clone();
-
-        debug_only! { let logger = logger.clone() }
-        async move {
-            let mut interval = tokio::time::interval(Duration::from_secs(5));
-            loop {
-                interval.tick().await;
-                let last_activity = *metrics.last_activity.lock().await;
-                let idle_time = last_activity.elapsed();
+    let shutdown = Arc::new(AtomicBool::new(false));
 
-                if idle_time >= INACTIVITY_TIMEOUT {
-                    debug_only! {
-                        let mut log = logger.lock().await
-                        let _ = log.add_entry(format!(
-                           "No activity for {}s, shutting down...",
-                           idle_time.as_secs()
-                       ))
-                        let _ = log.flush()
-                        std::process::exit(0)
+    // stops the crawl like Ctrl-C does, so pending batches are still saved
+    if config.crawl.exit_when_idle {
+        tokio::spawn({
+            let metrics = metrics.clone();
+            let logger = logger.clone();
+            let shutdown = shutdown.clone();
+            let inactivity_timeout = Duration::from_secs(config.crawl.inactivity_timeout_secs);
+            async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(5));
+                loop {
+                    interval.tick().await;
+                    let idle_time = metrics.last_activity.lock().await.elapsed();
+
+                    if idle_time >= inactivity_timeout {
+                        let message =
+                            format!("No activity for {}s, shutting down...", idle_time.as_secs());
+                        status!("{}", message);
+                        let mut log = logger.lock().await;
+                        let _ = log.add_entry(message);
+                        let _ = log.flush();
+                        shutdown.store(true, Ordering::Relaxed);
+                        break;
                     }
                 }
             }
-        }
-    });
-
-    let base_dir = std::env::current_dir()?;
+        });
+    }
 
-    let sites_file = fs::read_to_string(base_dir.join("data/sites.txt").to_str().unwrap())?;
+    let sites_file = fs::read_to_string(&config.crawl.seeds_file)?;
     let seeds: Vec<String> = sites_file
         .lines()
         .map(|line| line.trim().to_string())
         .filter(|s| !s.is_empty())
         .collect();
 
-    println!("Found {} seed URLs", seeds.len());
+    status!("Found {} seed URLs", seeds.len());
     if seeds.is_empty() {
-        println!("No seed URLs found in data/sites.txt, exiting...");
+        status!(
+            "No seed URLs found in {}, exiting...",
+            config.crawl.seeds_file.display()
+        );
         std::process::exit(1);
     }
 
-    let proxy_manager = ProxyManager::new(base_dir.join("data/proxies.txt").to_str().unwrap())?;
-    println!("Loaded {} proxies", proxy_manager.proxies.len());
-    let pool = create_db_pool().await?;
-    println!("Connected to database");
-    let visited = Arc::new(Mutex::new(HashSet::new()));
-    let pages_count = Arc::new(AtomicUsize::new(0));
-    let db_semaphore = Arc::new(Semaphore::new(DB_CONCURRENCY));
-    let pending_analyses = Arc::new(Mutex::new(Vec::new()));
-
-    let (discovered_tx, mut discovered_rx) = tokio::sync::mpsc::unbounded_channel::<String>();
-    let (processing_tx, processing_rx) = tokio::sync::mpsc::unbounded_channel::<String>();
-    let discovered_tx = Arc::new(discovered_tx);
-    let processing_tx = Arc::new(processing_tx);
+    let proxy_manager = ProxyManager::new(&config)?;
+    status!("Loaded {} proxies", proxy_manager.proxies.len());
+    // a dry run never writes, so it does not need sink credentials either
+    let sink = if config.crawl.dry_run {
+        status!("Dry run, analyses will be logged instead of saved");
+        None
+    } else {
+        Some(sink_from_config(&config.sink).await?)
+    };
 
-    let batch_task = tokio::spawn({
-        let processing_tx = processing_tx.clone();
+    tokio::spawn({
+        let shutdown = shutdown.clone();
         async move {
-            let mut domain_queues = DomainQueues::new();
-            let mut rng = StdRng::from_os_rng();
-            let mut interval = tokio::time::interval(Duration::from_secs(1));
-            const MAX_PER_DOMAIN: usize = 5;
-
-            loop {
-                tokio::select! {
-                    Some(link) = discovered_rx.recv() => {
-                        let normalized_url = match normalize_url(&link) {
-                            Ok(url) => url,
-                            Err(_) => continue,
-                        };
-                        let domain = match extract_domain(&normalized_url) {
-                            Ok(d) => d,
-                            Err(_) => continue,
-                        };
-                        domain_queues.add(domain, normalized_url);
-
-                        if domain_queues.total >= BATCH_SIZE {
-                            let batch = domain_queues.collect_batch(MAX_PER_DOMAIN);
-                            let mut shuffled = batch;
-                            shuffled.shuffle(&mut rng);
-                            for url in shuffled {
-                                if processing_tx.send(url).is_err() {
-                                    return;
-                                }
-                            }
-                        }
-                    },
-                    _ = interval.tick() => {
-                        if domain_queues.total > 0 {
-                            let batch = domain_queues.collect_batch(MAX_PER_DOMAIN);
-                            let mut shuffled = batch;
-                            shuffled.shuffle(&mut rng);
-                            for url in shuffled {
-                                let _ = processing_tx.send(url);
-                            }
-                        }
-                    }
-                }
+            if tokio::signal::ctrl_c().await.is_ok() {
+                status!("\nCtrl-C received, finishing in-flight pages (press again to force quit)...");
+                shutdown.store(true, Ordering::Relaxed);
+            }
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(130);
             }
         }
     });
 
-    {
-        let mut visited_lock = visited.lock().await;
-        for seed in seeds {
-            if visited_lock.insert(seed.clone()) {
-                discovered_tx
-                    .send(seed)
-                    .expect("Failed to enqueue seed URL");
-            }
-        }
+    let report_file = config.crawl.report_file.clone();
+    let mut builder = Crawler::builder()
+        .config(config)
+        .seeds(seeds)
+        .proxy_manager(proxy_manager)
+        .logger(logger.clone())
+        .metrics(metrics.clone())
+        .shutdown(shutdown);
+    if let Some(sink) = sink {
+        builder = builder.sink(sink);
     }
+    let crawler = builder.build();
 
-    println!("Starting crawl with limit of {} pages...", MAX_PAGES);
-
-    UnboundedReceiverStream::new(processing_rx)
-        .for_each_concurrent(CONCURRENCY, |url| {
-            let pool = pool.clone();
-            let proxy_manager = proxy_manager.clone();
-            let visited = visited.clone();
-            let pages_count = pages_count.clone();
-            let discovered_tx = discovered_tx.clone();
-            let pending_analyses = pending_analyses.clone();
-            let logger = logger.clone();
-            let metrics = metrics.clone();
-
-            {
-                let db_semaphore = db_semaphore.clone();
-                async move {
-                    let current_count = pages_count.fetch_add(1, Ordering::Relaxed) + 1;
-                    if current_count > MAX_PAGES {
-                        return;
-                    }
-
-                    match process_page(&url, &proxy_manager, &metrics).await {
-                        Ok((child_links, analysis)) => {
-                            // decrease total_left since we processed one
-                            metrics.total_left.fetch_sub(1, Ordering::Relaxed);
-                            
-                            debug_only! { println!("[DEBUG] Extracted {} links from {}", child_links.len(), url) }
-
-                            let mut analyses = pending_analyses.lock().await;
-                            analyses.push(analysis);
-
-                            if analyses.len() >= BATCH_SIZE {
-                                let analyses_to_save: Vec<SeoAnalysis> =
-                                    analyses.drain(..BATCH_SIZE).collect();
-                                let pool_clone = pool.clone();
-                                let _permit = db_semaphore.acquire().await;
-                                tokio::spawn(async move {
-                                    if let Err(e) =
-                                        save_analyses_batch(&pool_clone, &analyses_to_save).await
-                                    {
-                                        eprintln!("Batch save error: {:?}", e);
-                                    }
-                                });
-                            }
-
-                            for link in child_links {
-                                let mut visited_lock = visited.lock().await;
-                                if visited_lock.insert(link.clone()) {
-                                    // increase total_left for each new URL discovered
-                                    metrics.total_left.fetch_add(1, Ordering::Relaxed);
-                                    let _ = discovered_tx.send(link);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            debug_only! { eprintln!("Error processing {}: {:?}", url, e) }
-                        }
-                    }
-
-                    if current_count % BATCH_SIZE == 0 {
-                        let mut logger = logger.lock().await;
-                        let _ = logger.add_entry(format!(
-                            "======== Batch {} complete ========",
-                            current_count
-                        ));
-                        let _ = logger.flush();
-                    }
-                }
-            }
-        })
-        .await;
-
-    batch_task.await?;
-
-    let final_analyses = pending_analyses.lock().await.drain(..).collect::<Vec<_>>();
-    if !final_analyses.is_empty() {
-        save_analyses_batch(&pool, &final_analyses).await?;
+    let mut analyses = crawler.crawl();
+    let mut total_processed = 0;
+    while analyses.next().await.is_some() {
+        total_processed += 1;
     }
 
     logger.lock().await.flush()?;
 
-    let total_processed = pages_count.load(Ordering::Relaxed);
     let elapsed = start_time.elapsed();
-    println!(
+    status!(
         "\nProcessed {} pages in {:.2} seconds ({:.2}/sec)",
         total_processed,
         elapsed.as_secs_f64(),
         total_processed as f64 / elapsed.as_secs_f64()
     );
 
-    Ok(())
-}
-
-async fn process_page(
-    url: &str,
-    proxy_manager: &ProxyManager,
-    metrics: &Arc<Metrics>,
-) -> Result<(Vec<String>, SeoAnalysis), Box<dyn std::error::Error>> {
-    *metrics.last_activity.lock().await = Instant::now();
-
-    let base_url = normalize_url(url)?;
-
-    let mut tunnel_retries = 0;
-    let text = loop {
-        match try_tunnel_request(url, metrics).await {
-            Ok(text) => {
-                *metrics.last_activity.lock().await = Instant::now();
-                break text;
-            }
-            Err(_) => {
-                tunnel_retries += 1;
-                if tunnel_retries < MAX_TUNNEL_RETRIES {
-                    print_request_status(
-                        url,
-                        "TUNNEL",
-                        "RETRY",
-                        Some(&format!(
-                            "attempt {}/{}",
-                            tunnel_retries, MAX_TUNNEL_RETRIES
-                        )),
-                    );
-                    continue;
-                }
-
-                metrics.proxy.fetch_add(1, Ordering::Relaxed);
-
-                let proxy = proxy_manager.get_next_proxy().ok_or("No proxy available")?;
-                let fp = RequestFingerprint::new(&proxy.ip, url);
-
-                match proxy
-                    .client
-                    .get(&base_url)
-                    .header("User-Agent", &fp.user_agent)
-                    .header("Referer", fp.referrer.as_deref().unwrap_or(&base_url))
-                    .send()
-                    .await
-                {
-                    Ok(response) => {
-                        let status = response.status();
-                        let text = response.text().await?;
-                        if status == 403 || text.contains("403 Forbidden") {
-                            metrics.failed.fetch_add(1, Ordering::Relaxed);
-                            print_request_status(url, "PROXY", "FAILED", Some("403 Forbidden"));
-                            return Err("403 Forbidden".into());
-                        }
-                        print_request_status(url, "PROXY", "SUCCESS", None);
-                        break text;
-                    }
-                    Err(e) => {
-                        metrics.failed.fetch_add(1, Ordering::Relaxed);
-                        print_request_status(url, "PROXY", "FAILED", Some(&e.to_string()));
-                        return Err(e.into());
-                    }
-                }
-            }
-        }
-    };
-
-    let parsed = html_parser::parse_html(text.as_bytes(), &base_url)?;
-
-    let analysis = SeoAnalysis {
-        url: base_url,
-        language: parsed.language,
-        title: parsed.title,
-        meta_tags: parsed.meta_tags,
-        canonical_url: parsed.canonical_url,
-        content_text: parsed.content_text,
-    };
+    let summary = metrics.summary();
+    status!("\n{}", summary);
+    if let Some(path) = report_file {
+        fs::write(&path, serde_json::to_string_pretty(&summary)?)?;
+        status!("Wrote crawl report to {}", path.display());
+    }
 
-    metrics.success.fetch_add(1, Ordering::Relaxed);
-    Ok((parsed.links, analysis))
+    Ok(())
 }
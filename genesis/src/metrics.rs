@@ -1,16 +1,65 @@
-use std::sync::atomic::AtomicUsize;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::Instant;
+
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use dashmap::DashMap;
+use serde::Serialize;
 use tokio::sync::Mutex;
 
+use crate::error::CrawlError;
+
+/// Hosts listed in [`CrawlSummary::top_domains`]
+pub const SUMMARY_TOP_DOMAINS: usize = 10;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DomainStats {
+    pub total: usize,
+    pub success: usize,
+    pub failed: usize,
+}
+
 pub struct Metrics {
     pub total: AtomicUsize,
     pub tunnel: AtomicUsize,
     pub proxy: AtomicUsize,
+    /// Pages given up on, counted once per page, see [`Metrics::record_failure`]
     pub failed: AtomicUsize,
     pub success: AtomicUsize,
+    /// Pages dropped for exceeding `max_page_bytes`
+    pub too_large: AtomicUsize,
+    /// Failed pages that timed out
+    pub timeouts: AtomicUsize,
+    /// Failed pages answered with 403 through the tunnel and every proxy tried
+    pub forbidden: AtomicUsize,
+    /// Failed pages that only ever got a block page, see [`crate::block_page`]
+    pub blocked: AtomicUsize,
+    /// Failed pages that were not HTML
+    pub not_html: AtomicUsize,
+    /// Failed pages whose URL could not be parsed
+    pub parse_errors: AtomicUsize,
+    /// Failed pages lost to connection, TLS or proxy errors
+    pub network_errors: AtomicUsize,
+    /// 429 responses, the page is requeued so these are not failures
+    pub rate_limited: AtomicUsize,
+    /// Proxy 403s retried through another proxy
+    pub forbidden_retries: AtomicUsize,
+    /// Pages not saved because they look like error pages, see [`crate::soft404`]
+    pub soft_404s: AtomicUsize,
+    /// Pages not saved for having less than `min_content_words` of text
+    pub thin_pages: AtomicUsize,
+    /// Bytes of extracted text over all successful pages
+    pub content_bytes: AtomicUsize,
     pub last_activity: Arc<Mutex<Instant>>,
     pub total_left: AtomicUsize,
+    pub effective_concurrency: AtomicUsize,
+    pub max_depth: AtomicU32,
+    /// Domains that reached `max_pages_per_domain`
+    pub capped_domains: AtomicUsize,
+    pub started_at: Instant,
+    /// Page outcomes per host
+    pub domains: DashMap<String, DomainStats>,
 }
 
 impl Default for Metrics {
@@ -21,8 +70,330 @@ impl Default for Metrics {
             proxy: AtomicUsize::new(0),
             failed: AtomicUsize::new(0),
             success: AtomicUsize::new(0),
+            too_large: AtomicUsize::new(0),
+            timeouts: AtomicUsize::new(0),
+            forbidden: AtomicUsize::new(0),
+            blocked: AtomicUsize::new(0),
+            not_html: AtomicUsize::new(0),
+            parse_errors: AtomicUsize::new(0),
+            network_errors: AtomicUsize::new(0),
+            rate_limited: AtomicUsize::new(0),
+            forbidden_retries: AtomicUsize::new(0),
+            soft_404s: AtomicUsize::new(0),
+            thin_pages: AtomicUsize::new(0),
+            content_bytes: AtomicUsize::new(0),
             last_activity: Arc::new(Mutex::new(Instant::now())),
             total_left: AtomicUsize::new(0),
+            effective_concurrency: AtomicUsize::new(0),
+            max_depth: AtomicU32::new(0),
+            capped_domains: AtomicUsize::new(0),
+            started_at: Instant::now(),
+            domains: DashMap::new(),
+        }
+    }
+}
+
+impl Metrics {
+    pub fn record_domain(&self, domain: &str, success: bool) {
+        let mut stats = self.domains.entry(domain.to_string()).or_default();
+        stats.total += 1;
+        if success {
+            stats.success += 1;
+        } else {
+            stats.failed += 1;
+        }
+    }
+
+    /// Counts a page that could not be crawled under `failed` and the counter for its kind.
+    /// Soft 404s were served fine and rate limited pages are retried later, so neither is a
+    /// failure.
+    pub fn record_failure(&self, error: &CrawlError) {
+        let counter = match error {
+            CrawlError::SoftNotFound(_) => return,
+            CrawlError::RateLimited(_) => {
+                self.rate_limited.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+            CrawlError::Timeout(_) => &self.timeouts,
+            CrawlError::Forbidden => &self.forbidden,
+            CrawlError::Blocked(_) => &self.blocked,
+            CrawlError::TooLarge(_) => &self.too_large,
+            CrawlError::UnsupportedContentType(_) | CrawlError::NotHtml => &self.not_html,
+            CrawlError::Parse(_) => &self.parse_errors,
+            CrawlError::Network(_) | CrawlError::NoProxy => &self.network_errors,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Failed pages by kind, they add up to `failed`.
+    pub fn failures(&self) -> FailureBreakdown {
+        let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+        FailureBreakdown {
+            timeout: load(&self.timeouts),
+            forbidden: load(&self.forbidden),
+            blocked: load(&self.blocked),
+            too_large: load(&self.too_large),
+            not_html: load(&self.not_html),
+            parse: load(&self.parse_errors),
+            network: load(&self.network_errors),
+        }
+    }
+
+    /// The `n` hosts with the most pages processed, busiest first.
+    pub fn top_domains(&self, n: usize) -> Vec<(String, DomainStats)> {
+        let mut domains: Vec<(String, DomainStats)> = self
+            .domains
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect();
+        domains.sort_by(|a, b| b.1.total.cmp(&a.1.total).then_with(|| a.0.cmp(&b.0)));
+        domains.truncate(n);
+        domains
+    }
+
+    /// End-of-run report of the counters so far.
+    pub fn summary(&self) -> CrawlSummary {
+        let load = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+        let success = load(&self.success);
+        let elapsed_secs = self.started_at.elapsed().as_secs_f64();
+
+        CrawlSummary {
+            total: load(&self.total),
+            success,
+            failed: load(&self.failed),
+            failures: self.failures(),
+            rate_limited: load(&self.rate_limited),
+            forbidden_retries: load(&self.forbidden_retries),
+            soft_404s: load(&self.soft_404s),
+            thin_pages: load(&self.thin_pages),
+            tunnel: load(&self.tunnel),
+            proxy: load(&self.proxy),
+            tunnel_proxy_ratio: match load(&self.proxy) {
+                0 => 0.0,
+                proxy => load(&self.tunnel) as f64 / proxy as f64,
+            },
+            distinct_domains: self.domains.len(),
+            top_domains: self
+                .top_domains(SUMMARY_TOP_DOMAINS)
+                .into_iter()
+                .map(|(domain, stats)| DomainSummary { domain, stats })
+                .collect(),
+            average_content_bytes: match success {
+                0 => 0.0,
+                success => load(&self.content_bytes) as f64 / success as f64,
+            },
+            elapsed_secs,
+            pages_per_second: load(&self.total) as f64 / elapsed_secs.max(f64::EPSILON),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct FailureBreakdown {
+    pub timeout: usize,
+    pub forbidden: usize,
+    pub blocked: usize,
+    pub too_large: usize,
+    pub not_html: usize,
+    pub parse: usize,
+    pub network: usize,
+}
+
+impl std::fmt::Display for FailureBreakdown {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} timeout, {} forbidden, {} blocked, {} too large, {} not html, {} parse, {} network",
+            self.timeout,
+            self.forbidden,
+            self.blocked,
+            self.too_large,
+            self.not_html,
+            self.parse,
+            self.network
+        )
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DomainSummary {
+    pub domain: String,
+    #[serde(flatten)]
+    pub stats: DomainStats,
+}
+
+/// What a crawl did, see [`Metrics::summary`].
+#[derive(Debug, Clone, Serialize)]
+pub struct CrawlSummary {
+    pub total: usize,
+    pub success: usize,
+    pub failed: usize,
+    pub failures: FailureBreakdown,
+    /// 429s, the pages were requeued rather than failed
+    pub rate_limited: usize,
+    pub forbidden_retries: usize,
+    pub soft_404s: usize,
+    pub thin_pages: usize,
+    pub tunnel: usize,
+    pub proxy: usize,
+    /// Tunnel fetches per proxy fetch, 0 when no proxy was used
+    pub tunnel_proxy_ratio: f64,
+    pub distinct_domains: usize,
+    pub top_domains: Vec<DomainSummary>,
+    /// Mean length of the extracted text of successful pages
+    pub average_content_bytes: f64,
+    pub elapsed_secs: f64,
+    pub pages_per_second: f64,
+}
+
+impl std::fmt::Display for CrawlSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Pages: {} ({} ok, {} failed, {} soft 404s, {} thin, {} 403 retries)",
+            self.total,
+            self.success,
+            self.failed,
+            self.soft_404s,
+            self.thin_pages,
+            self.forbidden_retries
+        )?;
+        writeln!(
+            f,
+            "Failures: {}; {} rate limited",
+            self.failures, self.rate_limited
+        )?;
+        writeln!(
+            f,
+            "Fetched via: {} tunnel, {} proxy (T-P rate {:.2})",
+            self.tunnel, self.proxy, self.tunnel_proxy_ratio
+        )?;
+        writeln!(
+            f,
+            "Domains: {}, average content {:.0} bytes",
+            self.distinct_domains, self.average_content_bytes
+        )?;
+        for domain in &self.top_domains {
+            writeln!(
+                f,
+                "  {} ({} total, {} ok, {} failed)",
+                domain.domain, domain.stats.total, domain.stats.success, domain.stats.failed
+            )?;
         }
+        write!(
+            f,
+            "Elapsed: {:.2}s ({:.2} pages/sec)",
+            self.elapsed_secs, self.pages_per_second
+        )
     }
 }
+
+/// Renders the counters in the Prometheus text exposition format.
+pub fn render_prometheus(metrics: &Metrics) -> String {
+    let counters = [
+        ("total", "Pages attempted", &metrics.total),
+        ("success", "Pages fetched successfully", &metrics.success),
+        ("tunnel", "Pages fetched through the tunnel", &metrics.tunnel),
+        ("proxy", "Pages fetched through a proxy", &metrics.proxy),
+        ("failed", "Pages that could not be fetched", &metrics.failed),
+        ("too_large", "Pages larger than max_page_bytes", &metrics.too_large),
+        ("timeouts", "Pages that failed with a timeout", &metrics.timeouts),
+        (
+            "forbidden",
+            "Pages that failed with 403 Forbidden",
+            &metrics.forbidden,
+        ),
+        ("blocked", "Pages that failed on a block page", &metrics.blocked),
+        ("not_html", "Pages that failed for not being HTML", &metrics.not_html),
+        (
+            "parse_errors",
+            "Pages that failed on an unparseable URL",
+            &metrics.parse_errors,
+        ),
+        (
+            "network_errors",
+            "Pages that failed on a connection or proxy error",
+            &metrics.network_errors,
+        ),
+        (
+            "rate_limited",
+            "429 responses, requeued rather than failed",
+            &metrics.rate_limited,
+        ),
+        (
+            "forbidden_retries",
+            "Proxy 403 responses retried through another proxy",
+            &metrics.forbidden_retries,
+        ),
+        (
+            "soft_404",
+            "Pages skipped as soft 404 error pages",
+            &metrics.soft_404s,
+        ),
+        (
+            "thin_pages",
+            "Pages not saved for having too little text",
+            &metrics.thin_pages,
+        ),
+    ];
+
+    let mut output = String::new();
+    for (name, help, value) in counters {
+        let _ = writeln!(output, "# HELP genesis_{}_total {}", name, help);
+        let _ = writeln!(output, "# TYPE genesis_{}_total counter", name);
+        let _ = writeln!(
+            output,
+            "genesis_{}_total {}",
+            name,
+            value.load(Ordering::Relaxed)
+        );
+    }
+
+    let gauges = [
+        (
+            "total_left",
+            "Pages left before max_pages is reached",
+            metrics.total_left.load(Ordering::Relaxed) as f64,
+        ),
+        (
+            "capped_domains",
+            "Domains that reached max_pages_per_domain",
+            metrics.capped_domains.load(Ordering::Relaxed) as f64,
+        ),
+        (
+            "effective_concurrency",
+            "Concurrent requests currently allowed by the adaptive limiter",
+            metrics.effective_concurrency.load(Ordering::Relaxed) as f64,
+        ),
+        (
+            "requests_per_second",
+            "Average pages attempted per second since start",
+            metrics.total.load(Ordering::Relaxed) as f64
+                / metrics.started_at.elapsed().as_secs_f64().max(f64::EPSILON),
+        ),
+    ];
+    for (name, help, value) in gauges {
+        let _ = writeln!(output, "# HELP genesis_{} {}", name, help);
+        let _ = writeln!(output, "# TYPE genesis_{} gauge", name);
+        let _ = writeln!(output, "genesis_{} {}", name, value);
+    }
+    output
+}
+
+async fn metrics_handler(State(metrics): State<Arc<Metrics>>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render_prometheus(&metrics),
+    )
+}
+
+/// Serves `/metrics` on all interfaces until the process exits.
+pub async fn serve_prometheus(port: u16, metrics: Arc<Metrics>) -> std::io::Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(metrics_handler))
+        .with_state(metrics);
+
+    let listener = tokio::net::TcpListener::bind(("0.0.0.0", port)).await?;
+    axum::serve(listener, app).await
+}
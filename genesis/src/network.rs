@@ -1,7 +1,18 @@
 use crate::metrics::Metrics;
+use crate::proxy::ProxyManager;
 use crate::utils::print_request_status;
-use crate::utils::is_cloudflare_error;
+use crate::block_page::{detect_block_page, Blocked};
+use crate::error::CrawlError;
+use crate::utils::retry_after;
+use bytes::Bytes;
+use crate::charset::detect_encoding;
+use encoding_rs::Encoding;
+use reqwest::header::{HeaderMap, CONTENT_TYPE, LAST_MODIFIED, RETRY_AFTER};
+use reqwest::{Response, StatusCode};
+use std::borrow::Cow;
+use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 use std::sync::atomic::Ordering;
 
 const ALLOWED_CONTENT_TYPES: [&str; 4] = [
@@ -11,15 +22,132 @@ const ALLOWED_CONTENT_TYPES: [&str; 4] = [
     "text/plain",
 ];
 
-pub async fn try_tunnel_request(
-    url: &str,
-    metrics: &Arc<Metrics>,
-) -> Result<String, Box<dyn std::error::Error>> {
-    metrics.total.fetch_add(1, Ordering::Relaxed);
-    metrics.tunnel.fetch_add(1, Ordering::Relaxed);
+/// Returned when a site answers 429, so the URL can be requeued instead of indexed.
+#[derive(Debug)]
+pub struct RateLimited {
+    /// Delay requested by the `Retry-After` header, if any
+    pub retry_after: Option<Duration>,
+}
 
-    let original_url = url.to_string();
+impl RateLimited {
+    pub fn from_response(response: &Response) -> Option<Self> {
+        if response.status() != StatusCode::TOO_MANY_REQUESTS {
+            return None;
+        }
+        Some(RateLimited {
+            retry_after: header_value(response.headers(), RETRY_AFTER)
+                .and_then(|value| retry_after(&value)),
+        })
+    }
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.retry_after {
+            Some(delay) => write!(f, "429 Too Many Requests, retry after {:?}", delay),
+            None => write!(f, "429 Too Many Requests"),
+        }
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// Returned when a body is larger than `crawl.max_page_bytes`, either by its declared
+/// `Content-Length` or once that many bytes have been read.
+#[derive(Debug)]
+pub struct PageTooLarge {
+    pub limit: usize,
+}
+
+impl fmt::Display for PageTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Page larger than {} bytes", self.limit)
+    }
+}
+
+impl std::error::Error for PageTooLarge {}
+
+/// A fetched response body along with the status and headers worth keeping.
+#[derive(Debug, Clone)]
+pub struct FetchedPage {
+    /// Where the request ended up after following redirects
+    pub final_url: String,
+    /// Raw body, undecoded
+    pub body: Bytes,
+    /// Character encoding of `body`, see [`detect_encoding`]
+    pub encoding: &'static Encoding,
+    pub status_code: u16,
+    pub content_type: Option<String>,
+    pub last_modified: Option<String>,
+    /// Every `X-Robots-Tag` header, see [`crate::html_parser::RobotsMeta::from_header`]
+    pub robots_tags: Vec<String>,
+}
+
+impl FetchedPage {
+    /// Reads the body chunk by chunk, giving up as soon as it grows past `max_bytes`.
+    pub async fn read(
+        mut response: Response,
+        max_bytes: Option<usize>,
+    ) -> Result<Self, CrawlError> {
+        let final_url = response.url().to_string();
+        let status_code = response.status().as_u16();
+        let content_type = header_value(response.headers(), CONTENT_TYPE);
+        let last_modified = header_value(response.headers(), LAST_MODIFIED);
+        let robots_tags = response
+            .headers()
+            .get_all("x-robots-tag")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(str::to_string)
+            .collect();
+
+        if let (Some(limit), Some(length)) = (max_bytes, response.content_length()) {
+            if length > limit as u64 {
+                return Err(PageTooLarge { limit }.into());
+            }
+        }
+
+        let mut bytes = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+            if let Some(limit) = max_bytes.filter(|&limit| bytes.len() > limit) {
+                return Err(PageTooLarge { limit }.into());
+            }
+        }
+        let encoding = detect_encoding(&bytes, content_type.as_deref());
+
+        Ok(FetchedPage {
+            final_url,
+            body: Bytes::from(bytes),
+            encoding,
+            status_code,
+            content_type,
+            last_modified,
+            robots_tags,
+        })
+    }
+}
+
+impl FetchedPage {
+    /// The body decoded with [`encoding`](Self::encoding), for checks that need text.
+    pub fn text(&self) -> Cow<'_, str> {
+        self.encoding.decode_with_bom_removal(&self.body).0
+    }
+}
+
+fn header_value(headers: &HeaderMap, name: reqwest::header::HeaderName) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
 
+/// Builds the tunnel endpoint for `url`, e.g. `https://tunnel/` + `https://a.com/x`
+/// becomes `https://tunnel/https:/a.com/x`.
+pub fn tunnel_request_url(
+    tunnel_base: &str,
+    url: &str,
+) -> Result<String, CrawlError> {
     let parsed_url = if !url.contains("://") {
         format!("http://{}", url)
     } else {
@@ -28,43 +156,70 @@ pub async fn try_tunnel_request(
 
     let url_parts: Vec<&str> = parsed_url.splitn(2, "://").collect();
     if url_parts.len() != 2 {
-        return Err("Invalid URL format".into());
+        return Err(CrawlError::Parse("Invalid URL format".into()));
     }
 
     let scheme = url_parts[0];
     let rest = url_parts[1];
-    let tunnel_url = format!("{}{}:/{}", *crate::PROXY_TUNNEL_URL, scheme, rest);
+    Ok(format!("{}{}:/{}", tunnel_base, scheme, rest))
+}
+
+/// Inverse of [`tunnel_request_url`]: recovers the target URL from a tunnel endpoint.
+pub fn url_from_tunnel_url(tunnel_base: &str, tunnel_url: &str) -> Option<String> {
+    let (scheme, rest) = tunnel_url.strip_prefix(tunnel_base)?.split_once(":/")?;
+    Some(format!("{}://{}", scheme, rest))
+}
 
-    match crate::proxy::TUNNEL_CLIENT.get(&tunnel_url).send().await {
+pub async fn try_tunnel_request(
+    url: &str,
+    proxy_manager: &ProxyManager,
+    metrics: &Arc<Metrics>,
+    max_page_bytes: Option<usize>,
+) -> Result<FetchedPage, CrawlError> {
+    metrics.total.fetch_add(1, Ordering::Relaxed);
+    metrics.tunnel.fetch_add(1, Ordering::Relaxed);
+
+    let original_url = url.to_string();
+    let tunnel_url = tunnel_request_url(proxy_manager.tunnel_url(), url)?;
+
+    match proxy_manager.tunnel_client.get(&tunnel_url).send().await {
         Ok(response) => {
             let status = response.status();
-            
+
+            if let Some(rate_limited) = RateLimited::from_response(&response) {
+                print_request_status(&original_url, "TUNNEL", "FAILED", Some("429 Too Many Requests"));
+                return Err(rate_limited.into());
+            }
+
             // check content type before downloading body
             if let Some(content_type) = response.headers().get("content-type") {
                 let content_type = content_type.to_str().unwrap_or_default().to_lowercase();
                 if !ALLOWED_CONTENT_TYPES.iter().any(|&allowed| content_type.contains(allowed)) {
                     print_request_status(&original_url, "TUNNEL", "SKIPPED", Some(&content_type));
-                    return Err(format!("Unsupported content type: {}", content_type).into());
+                    return Err(CrawlError::UnsupportedContentType(content_type));
                 }
             }
 
-            let text = response.text().await?;
+            let mut page = match FetchedPage::read(response, max_page_bytes).await {
+                Ok(page) => page,
+                Err(e) => {
+                    print_request_status(&original_url, "TUNNEL", "FAILED", Some(&e.to_string()));
+                    return Err(e);
+                }
+            };
+            page.final_url = url_from_tunnel_url(proxy_manager.tunnel_url(), &page.final_url)
+                .unwrap_or_else(|| original_url.clone());
+            let text = page.text();
+            if let Some(kind) = detect_block_page(&text, status.as_u16()) {
+                print_request_status(&original_url, "TUNNEL", "FAILED", Some(&kind.to_string()));
+                return Err(Blocked(kind).into());
+            }
             if status == 403 || text.contains("403 Forbidden") {
                 print_request_status(&original_url, "TUNNEL", "FAILED", Some("403 Forbidden"));
-                return Err("403 Forbidden".into());
-            }
-            if is_cloudflare_error(&text) {
-                print_request_status(
-                    &original_url,
-                    "TUNNEL",
-                    "FAILED",
-                    Some("Cloudflare error detected"),
-                );
-                Err("Cloudflare error in response content".into())
-            } else {
-                print_request_status(&original_url, "TUNNEL", "SUCCESS", None);
-                Ok(text)
+                return Err(CrawlError::Forbidden);
             }
+            print_request_status(&original_url, "TUNNEL", "SUCCESS", None);
+            Ok(page)
         }
         Err(e) => {
             print_request_status(&original_url, "TUNNEL", "FAILED", Some(&e.to_string()));
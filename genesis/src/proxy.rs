@@ -1,91 +1,374 @@
+use rand::distr::{Alphanumeric, SampleString};
 use rayon::prelude::*;
-use reqwest::Client;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Client, ClientBuilder};
+use std::collections::BTreeMap;
 use std::fs;
 use std::net::IpAddr;
-use std::str::FromStr;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use url::{Host, Url};
+
+use crate::config::{Config, ProxyConfig};
 
 #[derive(Clone)]
 #[allow(dead_code)]
 pub struct Proxy {
     pub addr: String,
-    pub ip: IpAddr,
+    /// `None` for gateways given by hostname, which are not resolved up front
+    pub ip: Option<IpAddr>,
     pub username: String,
     pub password: String,
     pub client: Client,
+    /// Set when `proxy.username_template` or `proxy.password_template` is configured
+    pub session: Option<Arc<StickySession>>,
+}
+
+const SUPPORTED_SCHEMES: [&str; 4] = ["http", "https", "socks5", "socks5h"];
+
+/// One line of the proxy file: `[scheme://]host:port:username:password`.
+/// Lines without a scheme are treated as `http` proxies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyLine {
+    pub scheme: String,
+    pub host: String,
+    pub port: String,
+    pub username: String,
+    pub password: String,
+}
+
+impl ProxyLine {
+    pub fn proxy_url(&self) -> String {
+        format!("{}://{}:{}", self.scheme, self.host, self.port)
+    }
+}
+
+pub fn parse_proxy_line(line: &str) -> Option<ProxyLine> {
+    let line = line.trim();
+    let (scheme, rest) = match line.split_once("://") {
+        Some((scheme, rest)) => (scheme.to_lowercase(), rest),
+        None => ("http".to_string(), line),
+    };
+    if !SUPPORTED_SCHEMES.contains(&scheme.as_str()) {
+        return None;
+    }
+
+    let parts: Vec<&str> = rest.split(':').collect();
+    if parts.len() != 4 {
+        return None;
+    }
+
+    Some(ProxyLine {
+        scheme,
+        host: parts[0].to_string(),
+        port: parts[1].to_string(),
+        username: parts[2].to_string(),
+        password: parts[3].to_string(),
+    })
+}
+
+const SESSION_TOKEN_LEN: usize = 10;
+
+/// Credentials of a gateway proxy that keeps the exit IP for as long as a session token in
+/// the username or password stays the same. The client asks for the proxy URL on every
+/// request, so a new token takes effect without rebuilding the client. Pooled connections
+/// keep the session they were opened with until they close.
+pub struct StickySession {
+    line: ProxyLine,
+    username_template: String,
+    password_template: String,
+    requests_per_session: usize,
+    picks: AtomicUsize,
+    current: RwLock<Url>,
+}
+
+impl StickySession {
+    /// `None` unless the config has a credential template.
+    pub fn new(line: ProxyLine, config: &ProxyConfig) -> Option<Self> {
+        if config.username_template.is_none() && config.password_template.is_none() {
+            return None;
+        }
+        let mut session = StickySession {
+            username_template: config
+                .username_template
+                .clone()
+                .unwrap_or_else(|| "{username}".to_string()),
+            password_template: config
+                .password_template
+                .clone()
+                .unwrap_or_else(|| "{password}".to_string()),
+            requests_per_session: config.session_requests,
+            picks: AtomicUsize::new(0),
+            current: RwLock::new(Url::parse(&line.proxy_url()).ok()?),
+            line,
+        };
+        *session.current.get_mut().unwrap() = session.mint()?;
+        Some(session)
+    }
+
+    /// Proxy URL with the credentials of the current session.
+    pub fn proxy_url(&self) -> Url {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Counts a pick of the proxy, starting a new session every `requests_per_session`.
+    pub fn on_pick(&self) {
+        let picks = self.picks.fetch_add(1, Ordering::Relaxed);
+        // the first pick keeps the session minted with the client
+        if picks == 0
+            || self.requests_per_session == 0
+            || !picks.is_multiple_of(self.requests_per_session)
+        {
+            return;
+        }
+        if let Some(url) = self.mint() {
+            *self.current.write().unwrap() = url;
+        }
+    }
+
+    fn mint(&self) -> Option<Url> {
+        let token = Alphanumeric.sample_string(&mut rand::rng(), SESSION_TOKEN_LEN);
+        let fill = |template: &str| {
+            template
+                .replace("{username}", &self.line.username)
+                .replace("{password}", &self.line.password)
+                .replace("{session}", &token)
+        };
+        let mut url = Url::parse(&self.line.proxy_url()).ok()?;
+        url.set_username(&fill(&self.username_template)).ok()?;
+        url.set_password(Some(&fill(&self.password_template))).ok()?;
+        Some(url)
+    }
+}
+
+/// Weight of a new sample in the rolling latency average
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+#[derive(Default)]
+struct ProxyStats {
+    // rolling average in microseconds, 0 until the first sample
+    latency_micros: AtomicU64,
+    successes: AtomicU64,
+    failures: AtomicU64,
+}
+
+impl ProxyStats {
+    /// Expected cost of a request through this proxy, lower is better. Untried proxies
+    /// cost nothing so that every proxy gets tried, while one that has failed without ever
+    /// succeeding costs the most.
+    fn cost(&self) -> f64 {
+        let latency = self.latency_micros.load(Ordering::Relaxed) as f64;
+        let successes = self.successes.load(Ordering::Relaxed) as f64;
+        let failures = self.failures.load(Ordering::Relaxed) as f64;
+        if latency == 0.0 && failures > 0.0 {
+            return f64::MAX;
+        }
+        let success_rate = (successes + 1.0) / (successes + failures + 2.0);
+        latency / success_rate
+    }
 }
 
 #[derive(Clone)]
 pub struct ProxyManager {
     pub proxies: Arc<Vec<Proxy>>,
+    pub tunnel_client: Client,
+    pub tunnel_url: Option<String>,
     current: Arc<AtomicUsize>,
+    stats: Arc<Vec<ProxyStats>>,
 }
 
-lazy_static::lazy_static! {
-    pub static ref TUNNEL_CLIENT: Client = Client::builder()
-        .timeout(std::time::Duration::from_secs(30))
-        .build()
-        .unwrap();
+impl Default for ProxyManager {
+    fn default() -> Self {
+        ProxyManager::with_proxies(Vec::new(), &Config::default())
+    }
 }
 
 impl ProxyManager {
-    pub fn new(proxy_file: &str) -> std::io::Result<Self> {
-        let content = fs::read_to_string(proxy_file)?;
-        let lines: Vec<&str> = content.lines().collect();
-        let processed_count = AtomicUsize::new(0);
+    pub fn new(config: &Config) -> std::io::Result<Self> {
+        let content = fs::read_to_string(&config.proxy.file)?;
+        let lines: Vec<&str> = content
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+        let skipped = AtomicUsize::new(0);
 
         let proxies: Vec<Proxy> = lines
             .par_iter()
-            .enumerate()
-            .filter_map(|(_, line)| {
-                let parts: Vec<&str> = line.split(':').collect();
-                if parts.len() == 4 {
-                    processed_count.fetch_add(1, Ordering::Relaxed);
-                    let ip = match IpAddr::from_str(parts[0]) {
-                        Ok(ip) => ip,
-                        Err(_) => IpAddr::from_str("0.0.0.0").unwrap(),
-                    };
-
-                    let proxy_url = format!("http://{}:{}", parts[0], parts[1]);
-                    let proxy = match reqwest::Proxy::all(&proxy_url) {
-                        Ok(p) => p,
-                        Err(_) => return None,
-                    };
-                    let proxy_with_auth = proxy.basic_auth(parts[2], parts[3]);
-
-                    match Client::builder()
-                        .proxy(proxy_with_auth)
-                        .timeout(std::time::Duration::from_secs(30))
-                        .build()
-                    {
-                        Ok(client) => Some(Proxy {
-                            addr: proxy_url,
-                            ip,
-                            username: parts[2].to_string(),
-                            password: parts[3].to_string(),
-                            client,
-                        }),
-                        Err(_) => None,
-                    }
-                } else {
-                    None
+            .filter_map(|line| {
+                let proxy = build_proxy(line, config);
+                if proxy.is_none() {
+                    skipped.fetch_add(1, Ordering::Relaxed);
                 }
+                proxy
             })
             .collect();
 
-        Ok(ProxyManager {
+        let skipped = skipped.into_inner();
+        if skipped > 0 {
+            eprintln!(
+                "Skipped {} malformed proxy lines in {}",
+                skipped,
+                config.proxy.file.display()
+            );
+        }
+        Ok(ProxyManager::with_proxies(proxies, config))
+    }
+
+    pub fn with_proxies(proxies: Vec<Proxy>, config: &Config) -> Self {
+        let pool = &config.tunnel_pool;
+        let tunnel_client = client_builder(config)
+            .user_agent(&config.proxy.tunnel_user_agent)
+            .default_headers(tunnel_headers(&config.proxy.tunnel_headers))
+            .pool_max_idle_per_host(pool.max_idle_per_host)
+            .pool_idle_timeout(Duration::from_secs(pool.idle_timeout_secs))
+            .tcp_keepalive(
+                (pool.tcp_keepalive_secs > 0).then(|| Duration::from_secs(pool.tcp_keepalive_secs)),
+            )
+            .http2_adaptive_window(pool.http2_adaptive_window)
+            .build()
+            .unwrap();
+
+        ProxyManager {
+            stats: Arc::new(proxies.iter().map(|_| ProxyStats::default()).collect()),
             proxies: Arc::new(proxies),
+            tunnel_client,
+            tunnel_url: config.proxy.tunnel_url.clone(),
             current: Arc::new(AtomicUsize::new(0)),
-        })
+        }
+    }
+
+    pub fn tunnel_url(&self) -> &str {
+        match &self.tunnel_url {
+            Some(tunnel_url) => tunnel_url,
+            None => &crate::PROXY_TUNNEL_URL,
+        }
     }
 
-    pub fn get_next_proxy(&self) -> Option<Proxy> {
-        if self.proxies.is_empty() {
+    /// Picks a proxy and returns it with its index, for reporting back through
+    /// [`record_latency`](Self::record_latency) and [`record_failure`](Self::record_failure).
+    ///
+    /// Each pick compares the next proxy in round-robin order with a second one whose
+    /// offset shifts on every pass over the pool, and takes whichever has the lower
+    /// latency weighted by success rate. Faster proxies are favoured while slower ones
+    /// still win against even slower partners, and the order stays deterministic.
+    pub fn get_next_proxy(&self) -> Option<(usize, Proxy)> {
+        let len = self.proxies.len();
+        if len == 0 {
             return None;
         }
 
-        let current = self.current.fetch_add(1, Ordering::Relaxed) % self.proxies.len();
-        Some(self.proxies[current].clone())
+        let counter = self.current.fetch_add(1, Ordering::Relaxed);
+        let first = counter % len;
+        let index = if len == 1 {
+            first
+        } else {
+            let offset = 1 + (counter / len) % (len - 1);
+            let second = (first + offset) % len;
+            if self.stats[second].cost() < self.stats[first].cost() {
+                second
+            } else {
+                first
+            }
+        };
+        let proxy = self.proxies[index].clone();
+        if let Some(session) = &proxy.session {
+            session.on_pick();
+        }
+        Some((index, proxy))
+    }
+
+    pub fn record_latency(&self, index: usize, latency: Duration) {
+        let Some(stats) = self.stats.get(index) else {
+            return;
+        };
+        let sample = latency.as_micros().min(u64::MAX as u128) as u64;
+        let _ = stats
+            .latency_micros
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |average| {
+                Some(match average {
+                    0 => sample.max(1),
+                    _ => (average as f64 * (1.0 - LATENCY_SMOOTHING)
+                        + sample as f64 * LATENCY_SMOOTHING) as u64,
+                })
+            });
+        stats.successes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_failure(&self, index: usize) {
+        if let Some(stats) = self.stats.get(index) {
+            stats.failures.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// `None` for lines that do not parse, or whose port or host is invalid, since such a proxy
+/// would fail every request.
+fn build_proxy(line: &str, config: &Config) -> Option<Proxy> {
+    let line = parse_proxy_line(line)?;
+    let ip = match proxy_host(&line)? {
+        Host::Ipv4(ip) => Some(IpAddr::V4(ip)),
+        Host::Ipv6(ip) => Some(IpAddr::V6(ip)),
+        Host::Domain(_) => None,
+    };
+
+    let proxy_url = line.proxy_url();
+    let session = StickySession::new(line.clone(), &config.proxy).map(Arc::new);
+    let proxy_with_auth = match &session {
+        Some(session) => {
+            let session = session.clone();
+            reqwest::Proxy::custom(move |_| Some(session.proxy_url()))
+        }
+        None => reqwest::Proxy::all(&proxy_url)
+            .ok()?
+            .basic_auth(&line.username, &line.password),
+    };
+
+    let client = client_builder(config)
+        .proxy(proxy_with_auth)
+        .cookie_store(config.proxy.cookies)
+        .build()
+        .ok()?;
+    Some(Proxy {
+        addr: proxy_url,
+        ip,
+        username: line.username,
+        password: line.password,
+        client,
+        session,
+    })
+}
+
+// hosts such as `300.1.1.1` or `not a host` do not parse; names are not looked up, so a
+// resolver hiccup at startup cannot drop a working gateway
+fn proxy_host(line: &ProxyLine) -> Option<Host> {
+    line.port.parse::<u16>().ok()?;
+    Host::parse(&line.host).ok()
+}
+
+// many servers compress regardless of Accept-Encoding, so bodies are always decoded before parsing
+fn client_builder(config: &Config) -> ClientBuilder {
+    config
+        .timeouts
+        .apply(Client::builder())
+        .gzip(true)
+        .brotli(true)
+        .deflate(true)
+}
+
+// invalid names or values are reported and skipped rather than failing startup
+fn tunnel_headers(headers: &BTreeMap<String, String>) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        match (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) {
+            (Ok(name), Ok(value)) => {
+                map.insert(name, value);
+            }
+            _ => eprintln!("Skipping invalid tunnel header {}: {}", name, value),
+        }
     }
+    map
 }
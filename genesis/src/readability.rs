@@ -0,0 +1,159 @@
+//! Readability style main content extraction.
+//!
+//! Paragraph-like blocks are scored by their length and punctuation, the scores are
+//! credited to their parent and grandparent, and the best scoring container, discounted
+//! by how much of its text is links, is taken as the article body. Unlike the streaming
+//! pass in [`crate::html_parser`] this builds a full DOM, so it only runs when
+//! `crawl.extract_main_content` is set.
+
+use scraper::{ElementRef, Html, Selector};
+use std::collections::HashMap;
+
+const SCORED_BLOCKS: &str = "p, pre, td, blockquote";
+const TEXT_BLOCKS: &[&str] = &[
+    "p",
+    "pre",
+    "li",
+    "blockquote",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+];
+const SKIPPED_TAGS: &[&str] = &[
+    "nav", "header", "footer", "aside", "form", "script", "style", "noscript",
+];
+const NEGATIVE_HINTS: &[&str] = &[
+    "comment",
+    "footer",
+    "sidebar",
+    "nav",
+    "menu",
+    "share",
+    "social",
+    "related",
+    "advert",
+    "promo",
+    "cookie",
+    "banner",
+    "widget",
+    "breadcrumb",
+];
+const POSITIVE_HINTS: &[&str] = &[
+    "article", "content", "entry", "main", "post", "story", "body", "text",
+];
+// blocks shorter than this are captions, bylines and buttons rather than prose
+const MIN_BLOCK_CHARS: usize = 25;
+
+/// Text of the main article in `html`, empty when no block looks like prose.
+pub fn extract_main_content(html: &str) -> String {
+    let document = Html::parse_document(html);
+    let blocks = Selector::parse(SCORED_BLOCKS).expect("static selector");
+
+    let mut scores = HashMap::new();
+    for block in document.select(&blocks) {
+        if is_boilerplate(block) {
+            continue;
+        }
+        let text = normalized_text(block);
+        let chars = text.chars().count();
+        if chars < MIN_BLOCK_CHARS {
+            continue;
+        }
+
+        let score = 1.0 + text.matches(',').count() as f64 + (chars as f64 / 100.0).min(3.0);
+        let ancestors = block.ancestors().filter_map(ElementRef::wrap).take(2);
+        for (depth, ancestor) in ancestors.enumerate() {
+            let entry = scores
+                .entry(ancestor.id())
+                .or_insert_with(|| (ancestor, initial_score(ancestor)));
+            entry.1 += if depth == 0 { score } else { score / 2.0 };
+        }
+    }
+
+    let best = scores
+        .into_values()
+        .map(|(candidate, score)| (candidate, score * (1.0 - link_density(candidate))))
+        .max_by(|a, b| a.1.total_cmp(&b.1));
+    let Some((best, _)) = best else {
+        return String::new();
+    };
+
+    let text_blocks = Selector::parse(&TEXT_BLOCKS.join(", ")).expect("static selector");
+    best.select(&text_blocks)
+        .filter(|block| !is_boilerplate(*block) && !is_nested_block(*block, best))
+        .map(normalized_text)
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn initial_score(element: ElementRef) -> f64 {
+    let tag_score = match element.value().name() {
+        "article" | "main" => 10.0,
+        "div" => 5.0,
+        "section" | "pre" | "td" | "blockquote" => 3.0,
+        "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    };
+    tag_score + hint_score(element)
+}
+
+/// +25 for class or id names that suggest content, -25 for ones that suggest chrome.
+fn hint_score(element: ElementRef) -> f64 {
+    let value = element.value();
+    let hints = format!(
+        "{} {}",
+        value.attr("class").unwrap_or_default(),
+        value.id().unwrap_or_default()
+    )
+    .to_lowercase();
+
+    let mut score = 0.0;
+    if NEGATIVE_HINTS.iter().any(|hint| hints.contains(hint)) {
+        score -= 25.0;
+    }
+    if POSITIVE_HINTS.iter().any(|hint| hints.contains(hint)) {
+        score += 25.0;
+    }
+    score
+}
+
+fn is_boilerplate(element: ElementRef) -> bool {
+    std::iter::once(element)
+        .chain(element.ancestors().filter_map(ElementRef::wrap))
+        .any(|element| SKIPPED_TAGS.contains(&element.value().name()) || hint_score(element) < 0.0)
+}
+
+// a paragraph inside a list item is already part of the item's text
+fn is_nested_block(element: ElementRef, root: ElementRef) -> bool {
+    element
+        .ancestors()
+        .filter_map(ElementRef::wrap)
+        .take_while(|ancestor| *ancestor != root)
+        .any(|ancestor| TEXT_BLOCKS.contains(&ancestor.value().name()))
+}
+
+fn link_density(element: ElementRef) -> f64 {
+    let text_len = normalized_text(element).len();
+    if text_len == 0 {
+        return 0.0;
+    }
+    let links = Selector::parse("a").expect("static selector");
+    let link_len: usize = element
+        .select(&links)
+        .map(|link| normalized_text(link).len())
+        .sum();
+    (link_len as f64 / text_len as f64).min(1.0)
+}
+
+fn normalized_text(element: ElementRef) -> String {
+    element
+        .text()
+        .flat_map(str::split_whitespace)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,229 @@
+use async_trait::async_trait;
+use aws_sdk_s3::Client;
+use std::env;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::config::SinkConfig;
+use crate::db::{
+    compress_raw_html, create_db_pool, encode_batch, partitioned_runs, raw_html_key,
+    sanitize_analysis, save_analyses_batch, save_raw_html_batch, to_jsonl, BatchFormat,
+    SeoAnalysis,
+};
+use crate::status;
+
+#[async_trait]
+pub trait Sink: Send + Sync {
+    async fn write_batch(
+        &self,
+        analyses: &[SeoAnalysis],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+pub struct S3Sink {
+    client: Client,
+    format: BatchFormat,
+    compress: bool,
+}
+
+impl S3Sink {
+    /// With `compress`, batches are uploaded as zstd compressed `.jsonl.zst` objects.
+    pub fn new(client: Client, compress: bool) -> Self {
+        Self {
+            client,
+            format: BatchFormat::Jsonl,
+            compress,
+        }
+    }
+
+    /// Parquet batches go under `parquet/` instead of `analyses/`.
+    pub fn format(mut self, format: BatchFormat) -> Self {
+        self.format = format;
+        self
+    }
+}
+
+#[async_trait]
+impl Sink for S3Sink {
+    async fn write_batch(
+        &self,
+        analyses: &[SeoAnalysis],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        save_analyses_batch(&self.client, analyses, self.format, self.compress).await?;
+        if analyses.iter().any(|analysis| analysis.raw_html.is_some()) {
+            save_raw_html_batch(&self.client, analyses).await?;
+        }
+        Ok(())
+    }
+}
+
+pub struct PostgresSink {
+    client: Mutex<tokio_postgres::Client>,
+}
+
+impl PostgresSink {
+    pub async fn connect(db_url: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let (client, connection) = tokio_postgres::connect(db_url, tokio_postgres::NoTls).await?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                eprintln!("[Postgres] Connection error: {:?}", e);
+            }
+        });
+
+        Ok(Self {
+            client: Mutex::new(client),
+        })
+    }
+}
+
+#[async_trait]
+impl Sink for PostgresSink {
+    async fn write_batch(
+        &self,
+        analyses: &[SeoAnalysis],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut client = self.client.lock().await;
+        let transaction = client.transaction().await?;
+
+        let insert_site = transaction
+            .prepare(
+                "INSERT INTO sites (url, language, title, canonical_url, content_text) \
+                 VALUES ($1, $2, $3, $4, $5) RETURNING id",
+            )
+            .await?;
+        let insert_meta = transaction
+            .prepare("INSERT INTO meta_tags (analysis_id, name, content) VALUES ($1, $2, $3)")
+            .await?;
+
+        for analysis in analyses {
+            let analysis = sanitize_analysis(analysis);
+            let row = transaction
+                .query_one(
+                    &insert_site,
+                    &[
+                        &analysis.url,
+                        &analysis.language,
+                        &analysis.title,
+                        &analysis.canonical_url,
+                        &analysis.content_text,
+                    ],
+                )
+                .await?;
+            let id: i32 = row.get(0);
+
+            for tag in &analysis.meta_tags {
+                transaction
+                    .execute(&insert_meta, &[&id, &tag.name, &tag.content])
+                    .await?;
+            }
+        }
+
+        transaction.commit().await?;
+        status!("[Postgres] Saved {} analyses", analyses.len());
+        Ok(())
+    }
+}
+
+/// Writes the same `date=YYYY-MM-DD/partition=XX/batch_<uuid>.jsonl[.zst]` layout as the S3 sink
+/// to a local directory, with archived HTML under `raw/`.
+pub struct JsonlSink {
+    dir: PathBuf,
+    format: BatchFormat,
+    compress: bool,
+}
+
+impl JsonlSink {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            format: BatchFormat::Jsonl,
+            compress: false,
+        }
+    }
+
+    pub fn compressed(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Writes `batch_<uuid>.parquet` files in the same layout instead.
+    pub fn format(mut self, format: BatchFormat) -> Self {
+        self.format = format;
+        self
+    }
+}
+
+#[async_trait]
+impl Sink for JsonlSink {
+    async fn write_batch(
+        &self,
+        analyses: &[SeoAnalysis],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        for (prefix, run) in partitioned_runs(analyses) {
+            let dir = self.dir.join(prefix);
+            tokio::fs::create_dir_all(&dir).await?;
+
+            let path = dir.join(format!(
+                "batch_{}.{}",
+                Uuid::new_v4(),
+                self.format.extension(self.compress)
+            ));
+            tokio::fs::write(&path, encode_batch(run, self.format, self.compress)?).await?;
+
+            status!("[JSONL] Wrote {} analyses to {}", run.len(), path.display());
+        }
+
+        for analysis in analyses {
+            if let Some(raw_html) = &analysis.raw_html {
+                let path = self.dir.join(raw_html_key(analysis));
+                if let Some(parent) = path.parent() {
+                    tokio::fs::create_dir_all(parent).await?;
+                }
+                tokio::fs::write(&path, compress_raw_html(raw_html)?).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct StdoutSink;
+
+#[async_trait]
+impl Sink for StdoutSink {
+    async fn write_batch(
+        &self,
+        analyses: &[SeoAnalysis],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let body = to_jsonl(analyses)?;
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}", body)?;
+        Ok(())
+    }
+}
+
+/// Picks the sink named by `kind` (`s3`, `postgres`, `jsonl` or `stdout`).
+pub async fn sink_from_config(
+    config: &SinkConfig,
+) -> Result<Arc<dyn Sink>, Box<dyn std::error::Error + Send + Sync>> {
+    dotenvy::dotenv().ok();
+
+    let sink: Arc<dyn Sink> = match config.kind.to_lowercase().as_str() {
+        "s3" => {
+            Arc::new(S3Sink::new(create_db_pool().await?, config.compress).format(config.format))
+        }
+        "postgres" => Arc::new(PostgresSink::connect(&env::var("PRIVATE_DB_URL")?).await?),
+        "jsonl" => Arc::new(
+            JsonlSink::new(&config.jsonl_dir)
+                .compressed(config.compress)
+                .format(config.format),
+        ),
+        "stdout" => Arc::new(StdoutSink),
+        other => return Err(format!("Unknown sink: {}", other).into()),
+    };
+
+    status!("Using {} sink", config.kind);
+    Ok(sink)
+}
@@ -0,0 +1,133 @@
+use flate2::read::GzDecoder;
+use std::collections::{HashSet, VecDeque};
+use std::io::Read;
+
+use crate::html_parser::decode_entities;
+use crate::network::tunnel_request_url;
+use crate::proxy::ProxyManager;
+
+const SITEMAP_PATHS: [&str; 2] = ["/sitemap.xml", "/sitemap_index.xml"];
+const MAX_SITEMAP_DEPTH: usize = 3;
+const MAX_SITEMAP_URLS: usize = 50_000;
+// body and decompressed size cap, guards against huge sitemaps and gzip bombs
+const MAX_SITEMAP_BYTES: u64 = 50 * 1024 * 1024;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Sitemap {
+    UrlSet(Vec<String>),
+    Index(Vec<String>),
+}
+
+/// Fetches `/sitemap.xml` and `/sitemap_index.xml` for `domain` through the tunnel and
+/// returns every page URL they list, following nested sitemap indexes up to 3 levels deep.
+pub async fn fetch_sitemap(domain: &str, proxy_manager: &ProxyManager) -> Vec<String> {
+    let mut urls = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending: VecDeque<(String, usize)> = SITEMAP_PATHS
+        .iter()
+        .map(|path| (format!("https://{}{}", domain, path), 0))
+        .collect();
+
+    while let Some((sitemap_url, depth)) = pending.pop_front() {
+        if !seen.insert(sitemap_url.clone()) {
+            continue;
+        }
+
+        let body = match fetch_body(&sitemap_url, proxy_manager).await {
+            Some(body) => body,
+            None => continue,
+        };
+
+        match parse_sitemap(&body) {
+            Some(Sitemap::Index(children)) if depth < MAX_SITEMAP_DEPTH => {
+                pending.extend(children.into_iter().map(|child| (child, depth + 1)));
+            }
+            Some(Sitemap::Index(_)) | None => {}
+            Some(Sitemap::UrlSet(locs)) => {
+                urls.extend(locs);
+                if urls.len() >= MAX_SITEMAP_URLS {
+                    urls.truncate(MAX_SITEMAP_URLS);
+                    break;
+                }
+            }
+        }
+    }
+
+    urls
+}
+
+async fn fetch_body(url: &str, proxy_manager: &ProxyManager) -> Option<Vec<u8>> {
+    let tunnel_url = tunnel_request_url(proxy_manager.tunnel_url(), url).ok()?;
+    let mut response = proxy_manager
+        .tunnel_client
+        .get(&tunnel_url)
+        .send()
+        .await
+        .ok()?;
+    if !response.status().is_success() {
+        return None;
+    }
+    if response
+        .content_length()
+        .is_some_and(|length| length > MAX_SITEMAP_BYTES)
+    {
+        return None;
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.ok()? {
+        body.extend_from_slice(&chunk);
+        if body.len() as u64 > MAX_SITEMAP_BYTES {
+            return None;
+        }
+    }
+    Some(body)
+}
+
+/// Parses a `<urlset>` or `<sitemapindex>` document, transparently gunzipping `.xml.gz` bodies.
+pub fn parse_sitemap(body: &[u8]) -> Option<Sitemap> {
+    let xml = if body.starts_with(&[0x1f, 0x8b]) {
+        let mut decompressed = String::new();
+        GzDecoder::new(body)
+            .take(MAX_SITEMAP_BYTES)
+            .read_to_string(&mut decompressed)
+            .ok()?;
+        decompressed
+    } else {
+        String::from_utf8_lossy(body).into_owned()
+    };
+
+    let locs = extract_locs(&xml);
+    if xml.contains("<sitemapindex") {
+        Some(Sitemap::Index(locs))
+    } else if xml.contains("<urlset") {
+        Some(Sitemap::UrlSet(locs))
+    } else {
+        None
+    }
+}
+
+fn extract_locs(xml: &str) -> Vec<String> {
+    let mut locs = Vec::new();
+    let mut rest = xml;
+
+    while let Some(start) = rest.find("<loc>") {
+        rest = &rest[start + "<loc>".len()..];
+        let end = match rest.find("</loc>") {
+            Some(end) => end,
+            None => break,
+        };
+
+        let loc = rest[..end].trim();
+        let loc = loc
+            .strip_prefix("<![CDATA[")
+            .and_then(|loc| loc.strip_suffix("]]>"))
+            .unwrap_or(loc);
+        if !loc.is_empty() {
+            locs.push(decode_entities(loc.trim()));
+        }
+        rest = &rest[end..];
+    }
+
+    locs
+}
@@ -0,0 +1,38 @@
+//! Soft 404s: pages that answer 200 but only say the page does not exist.
+//!
+//! Error templates nearly always say so in their title, so a title pattern alone is enough
+//! while body patterns have to be specific. Either only counts on short pages, so an article
+//! titled "Peugeot 404 review" or one that mentions "page not found" is still indexed.
+
+use std::fmt;
+
+use crate::config::Soft404Config;
+
+/// Returned by [`crate::crawler::process_page`] for pages [`is_soft_404`] flags.
+#[derive(Debug)]
+pub struct SoftNotFound;
+
+impl fmt::Display for SoftNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Page looks like a soft 404")
+    }
+}
+
+impl std::error::Error for SoftNotFound {}
+
+/// Whether a page with this title and extracted text is an error page. Patterns are
+/// matched case-insensitively.
+pub fn is_soft_404(title: &str, content_text: &str, config: &Soft404Config) -> bool {
+    if content_text.chars().count() > config.max_content_chars {
+        return false;
+    }
+
+    contains_any(&title.to_lowercase(), &config.title_patterns)
+        || contains_any(&content_text.to_lowercase(), &config.content_patterns)
+}
+
+fn contains_any(text: &str, patterns: &[String]) -> bool {
+    patterns
+        .iter()
+        .any(|pattern| !pattern.is_empty() && text.contains(&pattern.to_lowercase()))
+}
@@ -1,14 +1,104 @@
-use url::Url;
+use url::{form_urlencoded, Url};
 use colored::Colorize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use crate::debug_only;
 
-pub fn normalize_url(url: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let parsed = Url::parse(url).or_else(|_| Url::parse(&format!("http://{}", url)))?;
+/// Default for `[crawl] tracking_params`, entries ending in `*` match any parameter with
+/// that prefix
+pub const DEFAULT_TRACKING_PARAMS: &[&str] = &[
+    "utm_*", "fbclid", "gclid", "dclid", "gbraid", "wbraid", "msclkid", "yclid", "igshid",
+    "mc_cid", "mc_eid", "_ga", "_gl", "ref", "ref_src",
+];
+
+/// Canonicalizes a URL so that variants of the same page compare equal. Parsing already
+/// lowercases the scheme and host and drops default ports (`:80`, `:443`); on top of that
+/// `tracking_params` are stripped and repeated slashes in the path are collapsed. A trailing
+/// slash is kept: `/docs/` and `/docs` are different bases for relative links, and some
+/// hosts only serve one of them.
+pub fn normalize_url(
+    url: &str,
+    tracking_params: &[String],
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let mut parsed = Url::parse(url).or_else(|_| Url::parse(&format!("http://{}", url)))?;
+    strip_tracking_params(&mut parsed, tracking_params);
+    normalize_path(&mut parsed);
     Ok(parsed.to_string())
 }
 
-pub fn is_cloudflare_error(text: &str) -> bool {
-    text.contains("Cloudflare") && text.contains("Worker threw exception")
+fn normalize_path(url: &mut Url) {
+    if url.cannot_be_a_base() {
+        return;
+    }
+
+    let segments: Vec<&str> = url.path().split('/').filter(|s| !s.is_empty()).collect();
+    let mut path = format!("/{}", segments.join("/"));
+    if !segments.is_empty() && url.path().ends_with('/') {
+        path.push('/');
+    }
+    if path != url.path() {
+        url.set_path(&path);
+    }
+}
+
+fn is_tracking_param(name: &str, tracking_params: &[String]) -> bool {
+    let name = name.to_lowercase();
+    tracking_params.iter().map(|param| param.to_lowercase()).any(|param| {
+        match param.strip_suffix('*') {
+            Some(prefix) => name.starts_with(prefix),
+            None => name == param,
+        }
+    })
+}
+
+// the params that are kept are copied as they were written, so `?flag` stays `?flag`
+fn strip_tracking_params(url: &mut Url, tracking_params: &[String]) {
+    let Some(query) = url.query() else {
+        return;
+    };
+
+    let pairs: Vec<&str> = query.split('&').collect();
+    let kept: Vec<&str> = pairs
+        .iter()
+        .copied()
+        .filter(|pair| {
+            let name = form_urlencoded::parse(pair.as_bytes())
+                .next()
+                .map(|(name, _)| name.into_owned())
+                .unwrap_or_default();
+            !is_tracking_param(&name, tracking_params)
+        })
+        .collect();
+    if kept.len() == pairs.len() {
+        return;
+    }
+
+    let kept = kept.join("&");
+    url.set_query((!kept.is_empty()).then_some(kept.as_str()));
+}
+
+/// Parses a `Retry-After` header, given either as delta-seconds (`120`) or as an
+/// HTTP-date (`Wed, 21 Oct 2015 07:28:00 GMT`). Dates in the past yield a zero delay.
+pub fn retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+static DIAGNOSTICS_TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Routes [`crate::status!`] output to stderr, for when stdout carries the crawl output itself.
+pub fn diagnostics_to_stderr(enabled: bool) {
+    DIAGNOSTICS_TO_STDERR.store(enabled, Ordering::Relaxed);
+}
+
+pub fn diagnostics_on_stderr() -> bool {
+    DIAGNOSTICS_TO_STDERR.load(Ordering::Relaxed)
 }
 
 pub fn print_request_status(url: &str, method: &str, status: &str, details: Option<&str>) {
@@ -28,7 +118,7 @@ pub fn print_request_status(url: &str, method: &str, status: &str, details: Opti
         _ => status.normal(),
     };
 
-    debug_only! { println!(
+    debug_only! { crate::status!(
         "[{}] {} | {} | {} {}",
         timestamp.to_string().bright_black(),
         colored_method,
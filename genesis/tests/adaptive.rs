@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+use genesis::adaptive::AdaptiveConcurrency;
+use genesis::config::AdaptiveConfig;
+
+fn limiter(max: usize, min_concurrency: usize) -> AdaptiveConcurrency {
+    AdaptiveConcurrency::new(
+        max,
+        AdaptiveConfig {
+            enabled: true,
+            min_concurrency,
+            increase_step: 3,
+            decrease_factor: 0.5,
+            failure_threshold: 0.5,
+            window_secs: 1,
+            min_samples: 4,
+        },
+    )
+}
+
+fn record(limiter: &AdaptiveConcurrency, successes: usize, failures: usize) {
+    for _ in 0..successes {
+        limiter.record(true);
+    }
+    for _ in 0..failures {
+        limiter.record(false);
+    }
+}
+
+/// How many permits can be held at once, up to `max`.
+async fn available(limiter: &AdaptiveConcurrency, max: usize) -> usize {
+    let mut permits = Vec::new();
+    while permits.len() < max {
+        match tokio::time::timeout(Duration::from_millis(20), limiter.acquire()).await {
+            Ok(permit) => permits.push(permit),
+            Err(_) => break,
+        }
+    }
+    permits.len()
+}
+
+#[tokio::test]
+async fn failures_cut_the_limit_down_to_min_concurrency() {
+    let limiter = limiter(16, 3);
+    record(&limiter, 1, 3);
+    assert_eq!(limiter.adjust(), 8);
+    record(&limiter, 0, 4);
+    assert_eq!(limiter.adjust(), 4);
+    record(&limiter, 0, 4);
+    assert_eq!(limiter.adjust(), 3);
+    assert_eq!(available(&limiter, 16).await, 3);
+
+    // too few outcomes wait for the next window
+    record(&limiter, 0, 3);
+    assert_eq!(limiter.adjust(), 3);
+}
+
+#[tokio::test]
+async fn successes_grow_the_limit_up_to_max() {
+    let limiter = limiter(10, 2);
+    record(&limiter, 0, 4);
+    assert_eq!(limiter.adjust(), 5);
+    record(&limiter, 4, 0);
+    assert_eq!(limiter.adjust(), 8);
+    record(&limiter, 4, 0);
+    assert_eq!(limiter.adjust(), 10);
+    assert_eq!(available(&limiter, 20).await, 10);
+}
+
+#[tokio::test]
+async fn min_concurrency_above_max_never_raises_the_limit() {
+    let limiter = limiter(10, 50);
+    record(&limiter, 0, 4);
+    assert_eq!(limiter.adjust(), 10);
+    assert_eq!(available(&limiter, 60).await, 10);
+}
+
+#[tokio::test]
+async fn permits_in_flight_are_removed_once_released() {
+    let limiter = limiter(4, 1);
+    let held: Vec<_> = futures::future::join_all((0..4).map(|_| limiter.acquire())).await;
+    record(&limiter, 0, 4);
+    assert_eq!(limiter.adjust(), 2);
+
+    drop(held);
+    assert_eq!(available(&limiter, 4).await, 2);
+
+    // growing first cancels what the in-flight permits still owe
+    let held: Vec<_> = futures::future::join_all((0..2).map(|_| limiter.acquire())).await;
+    record(&limiter, 0, 4);
+    assert_eq!(limiter.adjust(), 1);
+    record(&limiter, 4, 0);
+    assert_eq!(limiter.adjust(), 4);
+    drop(held);
+    assert_eq!(available(&limiter, 8).await, 4);
+}
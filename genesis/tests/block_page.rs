@@ -0,0 +1,152 @@
+mod common;
+
+use common::{fixture, html, metrics, mock_proxy, site_url, MockSite};
+use genesis::block_page::{detect_block_page, BlockKind, Blocked};
+use genesis::crawler::process_page;
+use genesis::proxy::ProxyManager;
+use genesis::CrawlError;
+use std::sync::atomic::Ordering;
+use wiremock::matchers::path;
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+fn detect(name: &str, status: u16) -> Option<BlockKind> {
+    detect_block_page(&String::from_utf8(fixture(name)).unwrap(), status)
+}
+
+#[test]
+fn cloudflare_challenges_are_detected() {
+    assert_eq!(
+        detect("cloudflare_challenge.html", 403),
+        Some(BlockKind::CloudflareChallenge)
+    );
+    assert_eq!(
+        detect("cloudflare_challenge.html", 200),
+        Some(BlockKind::CloudflareChallenge)
+    );
+    assert_eq!(
+        detect_block_page(
+            "<title>Attention Required</title>Checking your browser before accessing site.test. \
+             DDoS protection by Cloudflare",
+            503
+        ),
+        Some(BlockKind::CloudflareChallenge)
+    );
+}
+
+#[test]
+fn cloudflare_errors_are_detected() {
+    assert_eq!(
+        detect_block_page("Error 1101 Worker threw exception | Cloudflare", 500),
+        Some(BlockKind::CloudflareError)
+    );
+    let unavailable = "<h1>503 Service Temporarily Unavailable</h1><p>Ray ID: 8f1c2a3b4c5d6e7f</p>\
+                       <center>cloudflare</center>";
+    assert_eq!(
+        detect_block_page(unavailable, 503),
+        Some(BlockKind::CloudflareError)
+    );
+    assert_eq!(detect_block_page(unavailable, 200), None);
+}
+
+#[test]
+fn captcha_interstitials_are_detected() {
+    assert_eq!(detect("captcha.html", 403), Some(BlockKind::Captcha));
+    assert_eq!(detect("captcha.html", 200), Some(BlockKind::Captcha));
+    assert_eq!(detect("contact_form.html", 403), Some(BlockKind::Captcha));
+}
+
+#[test]
+fn firewall_block_pages_are_detected() {
+    assert_eq!(detect("incapsula.html", 200), Some(BlockKind::Waf));
+}
+
+#[test]
+fn regular_pages_are_not_block_pages() {
+    assert_eq!(detect("contact_form.html", 200), None);
+    assert_eq!(detect("index.html", 200), None);
+    assert_eq!(detect("blog_post.html", 200), None);
+    assert_eq!(
+        detect_block_page(
+            "<p>Just a moment... we moved our CDN from Cloudflare to our own servers.</p>",
+            200
+        ),
+        None
+    );
+}
+
+#[tokio::test]
+async fn challenged_pages_are_fetched_through_a_proxy_instead() {
+    let site = MockSite::start().await;
+    site.serve(
+        "/",
+        ResponseTemplate::new(403)
+            .insert_header("content-type", "text/html")
+            .set_body_bytes(fixture("cloudflare_challenge.html")),
+    )
+    .await;
+    let origin = MockServer::start().await;
+    Mock::given(path("/"))
+        .respond_with(html(fixture("index.html")))
+        .mount(&origin)
+        .await;
+    let mut config = site.config();
+    config.crawl.max_tunnel_retries = 3;
+    let proxy_manager = ProxyManager::with_proxies(vec![mock_proxy(&origin, 1)], &config);
+    let metrics = metrics();
+
+    let (_, analysis) = process_page(
+        &site_url("/"),
+        &config,
+        &proxy_manager,
+        None,
+        None,
+        &metrics,
+    )
+    .await
+    .expect("the proxy should get past the challenge");
+
+    assert_eq!(analysis.title, "Site Test Home");
+    // a block page is not retried through the tunnel
+    assert_eq!(site.server.received_requests().await.unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn pages_blocked_on_every_proxy_are_not_analysed() {
+    let site = MockSite::start().await;
+    site.serve("/", html(fixture("captcha.html"))).await;
+    let origin = MockServer::start().await;
+    Mock::given(path("/"))
+        .respond_with(html(fixture("captcha.html")))
+        .mount(&origin)
+        .await;
+    let config = site.config();
+    let proxy_manager = ProxyManager::with_proxies(
+        vec![mock_proxy(&origin, 1), mock_proxy(&origin, 2)],
+        &config,
+    );
+    let metrics = metrics();
+
+    let error = process_page(
+        &site_url("/"),
+        &config,
+        &proxy_manager,
+        None,
+        None,
+        &metrics,
+    )
+    .await
+    .expect_err("captcha pages must not be analysed");
+
+    assert!(
+        matches!(error, CrawlError::Blocked(Blocked(BlockKind::Captcha))),
+        "{:?}",
+        error
+    );
+    assert_eq!(metrics.success.load(Ordering::Relaxed), 0);
+    assert_eq!(metrics.failed.load(Ordering::Relaxed), 1);
+    assert_eq!(metrics.blocked.load(Ordering::Relaxed), 1);
+    assert_eq!(
+        metrics.forbidden_retries.load(Ordering::Relaxed),
+        config.crawl.max_forbidden_retries
+    );
+}
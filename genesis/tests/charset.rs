@@ -0,0 +1,45 @@
+use encoding_rs::{GBK, SHIFT_JIS, UTF_16LE, UTF_8, WINDOWS_1252};
+use genesis::charset::{detect_encoding, meta_charset};
+
+#[test]
+fn meta_tags_declare_the_charset() {
+    assert_eq!(
+        meta_charset(b"<meta charset=\"shift_jis\">"),
+        Some(SHIFT_JIS)
+    );
+    assert_eq!(meta_charset(b"<META CHARSET=gbk>"), Some(GBK));
+    assert_eq!(
+        meta_charset(
+            b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=iso-8859-1\">"
+        ),
+        Some(WINDOWS_1252)
+    );
+    assert_eq!(
+        meta_charset(b"<meta name=\"description\" content=\"x\">"),
+        None
+    );
+    // an ASCII document claiming to be UTF-16 cannot be
+    assert_eq!(meta_charset(b"<meta charset=\"utf-16\">"), Some(UTF_8));
+}
+
+#[test]
+fn meta_tags_past_the_prescan_window_are_ignored() {
+    let mut body = vec![b' '; 2048];
+    body.extend(b"<meta charset=\"gbk\">");
+    assert_eq!(meta_charset(&body), None);
+}
+
+#[test]
+fn bom_then_header_then_meta() {
+    let meta = b"<meta charset=\"gbk\">";
+    assert_eq!(detect_encoding(meta, None), GBK);
+    assert_eq!(
+        detect_encoding(meta, Some("text/html; charset=shift_jis")),
+        SHIFT_JIS
+    );
+    assert_eq!(
+        detect_encoding(b"\xFF\xFE<\0", Some("text/html; charset=gbk")),
+        UTF_16LE
+    );
+    assert_eq!(detect_encoding(b"<p>plain</p>", Some("text/html")), UTF_8);
+}
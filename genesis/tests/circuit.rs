@@ -0,0 +1,94 @@
+mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{site_url, MockSite};
+use futures::StreamExt;
+use genesis::circuit::{CircuitBreakers, CircuitState};
+use genesis::config::CircuitBreakerConfig;
+use genesis::Crawler;
+use wiremock::ResponseTemplate;
+
+fn breakers(cooldown_secs: u64) -> CircuitBreakers {
+    CircuitBreakers::new(&CircuitBreakerConfig {
+        enabled: true,
+        failure_threshold: 2,
+        cooldown_secs,
+    })
+}
+
+#[test]
+fn circuit_opens_after_consecutive_failures_and_closes_on_a_successful_probe() {
+    let breakers = breakers(0);
+    breakers.record_failure("down.example");
+    assert_eq!(breakers.allowance("down.example", 4), 4);
+    breakers.record_failure("down.example");
+    assert!(matches!(
+        breakers.state("down.example"),
+        CircuitState::Open { .. }
+    ));
+
+    assert_eq!(breakers.allowance("down.example", 4), 1);
+    breakers.record_success("down.example");
+    assert_eq!(breakers.state("down.example"), CircuitState::Closed);
+    assert_eq!(breakers.allowance("down.example", 4), 4);
+}
+
+#[test]
+fn dropped_probes_are_replaced_once_the_cooldown_passes() {
+    let waiting = breakers(60);
+    waiting.record_failure("down.example");
+    waiting.record_failure("down.example");
+    assert_eq!(waiting.allowance("down.example", 4), 0);
+
+    // a probe that was dropped without reporting back is replaced once the cooldown passes
+    let expiring = breakers(0);
+    expiring.record_failure("down.example");
+    expiring.record_failure("down.example");
+    assert_eq!(expiring.allowance("down.example", 4), 1);
+    assert_eq!(expiring.allowance("down.example", 4), 1);
+    expiring.record_failure("down.example");
+    assert!(matches!(
+        expiring.state("down.example"),
+        CircuitState::Open { .. }
+    ));
+}
+
+#[tokio::test]
+async fn repeated_403s_open_the_circuit() {
+    let site = MockSite::start().await;
+    let pages = ["/a", "/b", "/c", "/d", "/e"];
+    for page in pages {
+        site.serve(page, ResponseTemplate::new(403)).await;
+    }
+    let mut config = site.config();
+    config.crawl.max_per_domain = 1;
+    config.crawl.discover_sitemaps = false;
+    config.circuit_breaker = CircuitBreakerConfig {
+        enabled: true,
+        failure_threshold: 2,
+        cooldown_secs: 60,
+    };
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let crawler = Crawler::builder()
+        .config(config)
+        .seeds(pages.map(site_url))
+        .shutdown(shutdown.clone())
+        .build();
+    let metrics = crawler.metrics();
+    let mut analyses = crawler.crawl();
+
+    // one URL is released per second, so every page would have been tried by now
+    tokio::time::sleep(Duration::from_secs(6)).await;
+    shutdown.store(true, Ordering::Relaxed);
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while analyses.next().await.is_some() {}
+    })
+    .await
+    .expect("the stream should end after shutdown");
+
+    assert_eq!(metrics.forbidden.load(Ordering::Relaxed), 2);
+}
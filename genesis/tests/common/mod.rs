@@ -0,0 +1,134 @@
+#![allow(dead_code)]
+
+use std::io::Write;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+use genesis::config::Config;
+use genesis::metrics::Metrics;
+use genesis::proxy::{Proxy, ProxyManager};
+use genesis::{SeoAnalysis, Sink};
+
+/// Host the fake site pretends to live on. Requests reach the mock server through the
+/// tunnel URL format, e.g. `http://127.0.0.1:1234/http:/site.test/about`.
+pub const SITE: &str = "http://site.test";
+
+pub fn fixture(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name);
+    std::fs::read(&path).unwrap_or_else(|e| panic!("missing fixture {}: {}", path.display(), e))
+}
+
+pub fn gzip(body: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(body).unwrap();
+    encoder.finish().unwrap()
+}
+
+pub fn site_url(page: &str) -> String {
+    format!("{}{}", SITE, page)
+}
+
+/// Path under which the mock tunnel serves `page` of the fake site.
+pub fn tunnel_path(page: &str) -> String {
+    format!("/http:/site.test{}", page)
+}
+
+pub fn html(body: Vec<u8>) -> ResponseTemplate {
+    ResponseTemplate::new(200)
+        .insert_header("content-type", "text/html; charset=utf-8")
+        .set_body_bytes(body)
+}
+
+pub struct MockSite {
+    pub server: MockServer,
+}
+
+impl MockSite {
+    pub async fn start() -> Self {
+        MockSite {
+            server: MockServer::start().await,
+        }
+    }
+
+    pub async fn serve(&self, page: &str, response: ResponseTemplate) {
+        Mock::given(method("GET"))
+            .and(path(tunnel_path(page)))
+            .respond_with(response)
+            .mount(&self.server)
+            .await;
+    }
+
+    pub async fn redirect(&self, from: &str, to: &str) {
+        self.serve(
+            from,
+            ResponseTemplate::new(301).insert_header("location", tunnel_path(to).as_str()),
+        )
+        .await;
+    }
+
+    /// A crawler config whose tunnel points at the mock server and that never falls back to proxies.
+    pub fn config(&self) -> Config {
+        let mut config = Config::default();
+        config.proxy.tunnel_url = Some(format!("{}/", self.server.uri()));
+        config.crawl.max_tunnel_retries = 1;
+        config
+    }
+
+    pub fn proxy_manager(&self, config: &Config) -> ProxyManager {
+        ProxyManager::with_proxies(Vec::new(), config)
+    }
+}
+
+/// A proxy whose requests all end up at `server`, which sees the fake site's own paths.
+pub fn mock_proxy(server: &MockServer, octet: u8) -> Proxy {
+    Proxy {
+        addr: server.uri(),
+        ip: Some(IpAddr::V4(Ipv4Addr::new(10, 0, 0, octet))),
+        username: String::new(),
+        password: String::new(),
+        client: reqwest::Client::builder()
+            .proxy(reqwest::Proxy::http(server.uri()).unwrap())
+            .build()
+            .unwrap(),
+        session: None,
+    }
+}
+
+pub fn metrics() -> Arc<Metrics> {
+    Arc::new(Metrics::default())
+}
+
+/// Remembers the url of every analysis written to it.
+#[derive(Default)]
+pub struct CollectingSink {
+    pub saved: Mutex<Vec<String>>,
+    /// URLs of the saved pages that came with their raw body
+    pub archived: Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl Sink for CollectingSink {
+    async fn write_batch(
+        &self,
+        analyses: &[SeoAnalysis],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut saved = self.saved.lock().unwrap();
+        saved.extend(analyses.iter().map(|analysis| analysis.url.clone()));
+        self.archived.lock().unwrap().extend(
+            analyses
+                .iter()
+                .filter(|analysis| analysis.raw_html.is_some())
+                .map(|analysis| analysis.url.clone()),
+        );
+        Ok(())
+    }
+}
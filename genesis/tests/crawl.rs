@@ -0,0 +1,663 @@
+mod common;
+
+use common::{fixture, gzip, html, metrics, mock_proxy, site_url, MockSite};
+use genesis::config::CrawlConfig;
+use genesis::crawler::{domain_key, process_page, registrable_domain, CrawlItem, DomainQueues};
+use genesis::network::PageTooLarge;
+use genesis::proxy::ProxyManager;
+use genesis::utils::{normalize_url, retry_after};
+use genesis::CrawlError;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+use wiremock::matchers::{header_exists, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+#[tokio::test]
+async fn extracts_analysis_and_links_from_a_page() {
+    let site = MockSite::start().await;
+    site.serve("/", html(fixture("index.html"))).await;
+    let config = site.config();
+
+    let (links, analysis) = process_page(
+        &site_url("/"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics(),
+    )
+    .await
+    .expect("page should be crawled");
+
+    assert_eq!(analysis.url, site_url("/"));
+    assert_eq!(analysis.status_code, 200);
+    assert!((chrono::Utc::now().timestamp() - analysis.crawled_at).abs() < 60);
+    assert_eq!(
+        analysis.content_type.as_deref(),
+        Some("text/html; charset=utf-8")
+    );
+    assert_eq!(analysis.language, "en");
+    assert_eq!(analysis.title, "Site Test Home");
+    assert_eq!(analysis.canonical_url.as_deref(), Some("http://site.test/"));
+    assert!(analysis.meta_tags.iter().any(
+        |tag| tag.name == "description" && tag.content == "A small fake site for crawler tests"
+    ));
+    assert!(analysis
+        .meta_tags
+        .iter()
+        .any(|tag| tag.name == "og:title" && tag.content == "Site Test"));
+    assert!(analysis.content_text.contains("Welcome to Site Test"));
+
+    let mut links = links;
+    links.sort();
+    assert_eq!(
+        links,
+        vec![
+            "http://cdn.site.test/assets/page",
+            "http://site.test/about",
+            "http://site.test/blog/post?utm_source=x&id=5",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn hreflang_alternates_are_followed() {
+    let site = MockSite::start().await;
+    site.serve(
+        "/en/",
+        html(
+            br#"<link rel="alternate" hreflang="en" href="/en/">
+            <link rel="alternate" hreflang="fr" href="/fr/">
+            <a href="/en/about">About</a>"#
+                .to_vec(),
+        ),
+    )
+    .await;
+    let config = site.config();
+
+    let (mut links, analysis) = process_page(
+        &site_url("/en/"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics(),
+    )
+    .await
+    .unwrap();
+
+    links.sort();
+    assert_eq!(links, vec![site_url("/en/about"), site_url("/fr/")]);
+    assert_eq!(analysis.alternates.len(), 2);
+}
+
+#[tokio::test]
+async fn relative_links_resolve_against_directory_pages() {
+    let site = MockSite::start().await;
+    site.serve(
+        "/docs/",
+        html(br#"<a href="intro">Intro</a><a href="../blog">Blog</a>"#.to_vec()),
+    )
+    .await;
+    let config = site.config();
+
+    let (mut links, analysis) = process_page(
+        &site_url("/docs/"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics(),
+    )
+    .await
+    .expect("directory page should be crawled");
+
+    links.sort();
+    assert_eq!(links, vec![site_url("/blog"), site_url("/docs/intro")]);
+    assert_eq!(analysis.url, site_url("/docs/"));
+}
+
+#[tokio::test]
+async fn raw_html_is_only_kept_when_archiving() {
+    let site = MockSite::start().await;
+    let body = b"<html><head><title>Home</title></head></html>".to_vec();
+    site.serve("/", html(body.clone())).await;
+    let mut config = site.config();
+    let proxy_manager = site.proxy_manager(&config);
+
+    let (_, analysis) = process_page(
+        &site_url("/"),
+        &config,
+        &proxy_manager,
+        None,
+        None,
+        &metrics(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(analysis.raw_html, None);
+
+    config.sink.archive_raw_html = true;
+    let (_, analysis) = process_page(
+        &site_url("/"),
+        &config,
+        &proxy_manager,
+        None,
+        None,
+        &metrics(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(analysis.raw_html, Some(body));
+}
+
+#[tokio::test]
+async fn follows_redirects_to_the_final_page() {
+    let site = MockSite::start().await;
+    site.redirect("/older", "/old").await;
+    site.redirect("/old", "/").await;
+    site.serve("/", html(fixture("index.html"))).await;
+    let config = site.config();
+
+    let (_, analysis) = process_page(
+        &site_url("/older"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics(),
+    )
+    .await
+    .expect("redirect should be followed");
+
+    assert_eq!(analysis.url, site_url("/"));
+    assert_eq!(analysis.title, "Site Test Home");
+}
+
+#[tokio::test]
+async fn forbidden_pages_are_not_analysed() {
+    let site = MockSite::start().await;
+    site.serve(
+        "/private",
+        ResponseTemplate::new(403)
+            .insert_header("content-type", "text/html")
+            .set_body_bytes(fixture("forbidden.html")),
+    )
+    .await;
+    let config = site.config();
+    let metrics = metrics();
+
+    let result = process_page(
+        &site_url("/private"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics,
+    )
+    .await;
+
+    assert!(result.is_err());
+    assert_eq!(
+        metrics.success.load(std::sync::atomic::Ordering::Relaxed),
+        0
+    );
+}
+
+#[tokio::test]
+async fn rate_limited_pages_report_retry_after() {
+    let site = MockSite::start().await;
+    site.serve(
+        "/busy",
+        ResponseTemplate::new(429)
+            .insert_header("content-type", "text/html")
+            .insert_header("retry-after", "120")
+            .set_body_string("<h1>Slow down</h1>"),
+    )
+    .await;
+    let config = site.config();
+
+    let error = process_page(
+        &site_url("/busy"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics(),
+    )
+    .await
+    .expect_err("rate limited pages must not be analysed");
+
+    let CrawlError::RateLimited(rate_limited) = error else {
+        panic!("error should be RateLimited, got {:?}", error);
+    };
+    assert_eq!(rate_limited.retry_after, Some(Duration::from_secs(120)));
+}
+
+#[tokio::test]
+async fn decodes_non_utf8_pages_using_the_declared_charset() {
+    let site = MockSite::start().await;
+    site.serve(
+        "/fr",
+        ResponseTemplate::new(200)
+            .insert_header("content-type", "text/html; charset=iso-8859-1")
+            .set_body_bytes(fixture("latin1.html")),
+    )
+    .await;
+    let config = site.config();
+
+    let (_, analysis) = process_page(
+        &site_url("/fr"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics(),
+    )
+    .await
+    .expect("latin-1 page should be crawled");
+
+    assert_eq!(analysis.language, "fr");
+    assert_eq!(analysis.title, "Café crème");
+    assert!(analysis.content_text.contains("très chaud"));
+}
+
+#[tokio::test]
+async fn proxy_403s_are_retried_through_another_proxy() {
+    let site = MockSite::start().await;
+    site.serve("/private", ResponseTemplate::new(403)).await;
+    let blocked = MockServer::start().await;
+    Mock::given(path("/private"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&blocked)
+        .await;
+    let allowed = MockServer::start().await;
+    Mock::given(path("/private"))
+        .and(header_exists("accept-language"))
+        .and(header_exists("sec-fetch-mode"))
+        .respond_with(html(fixture("index.html")))
+        .mount(&allowed)
+        .await;
+    let config = site.config();
+    let proxy_manager = ProxyManager::with_proxies(
+        vec![mock_proxy(&blocked, 1), mock_proxy(&allowed, 2)],
+        &config,
+    );
+    let metrics = metrics();
+
+    let (_, analysis) = process_page(
+        &site_url("/private"),
+        &config,
+        &proxy_manager,
+        None,
+        None,
+        &metrics,
+    )
+    .await
+    .expect("the second proxy should get through");
+
+    assert_eq!(analysis.title, "Site Test Home");
+    assert_eq!(metrics.forbidden_retries.load(Ordering::Relaxed), 1);
+    assert_eq!(metrics.failed.load(Ordering::Relaxed), 0);
+}
+
+#[tokio::test]
+async fn failed_pages_are_counted_once_however_often_they_are_retried() {
+    let site = MockSite::start().await;
+    site.serve("/private", ResponseTemplate::new(403)).await;
+    let blocked = MockServer::start().await;
+    Mock::given(path("/private"))
+        .respond_with(ResponseTemplate::new(403))
+        .mount(&blocked)
+        .await;
+    let mut config = site.config();
+    config.crawl.max_tunnel_retries = 3;
+    let proxy_manager = ProxyManager::with_proxies(
+        vec![mock_proxy(&blocked, 1), mock_proxy(&blocked, 2)],
+        &config,
+    );
+    let metrics = metrics();
+
+    let err = process_page(
+        &site_url("/private"),
+        &config,
+        &proxy_manager,
+        None,
+        None,
+        &metrics,
+    )
+    .await
+    .expect_err("every proxy answers 403");
+
+    assert!(matches!(err, CrawlError::Forbidden), "{:?}", err);
+    assert_eq!(metrics.failed.load(Ordering::Relaxed), 1);
+    assert_eq!(metrics.failures().forbidden, 1);
+    assert_eq!(metrics.failures().timeout, 0);
+}
+
+#[tokio::test]
+async fn decodes_shift_jis_pages_declared_in_a_meta_tag() {
+    let site = MockSite::start().await;
+    let page = "<html><head><meta charset=\"Shift_JIS\"><title>日本語のページ</title></head>\
+                <body><p>こんにちは、世界</p></body></html>";
+    let (body, _, _) = encoding_rs::SHIFT_JIS.encode(page);
+    site.serve(
+        "/ja",
+        ResponseTemplate::new(200)
+            .insert_header("content-type", "text/html")
+            .set_body_bytes(body.into_owned()),
+    )
+    .await;
+    let config = site.config();
+
+    let (_, analysis) = process_page(
+        &site_url("/ja"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics(),
+    )
+    .await
+    .expect("shift-jis page should be crawled");
+
+    assert_eq!(analysis.charset.as_deref(), Some("Shift_JIS"));
+    assert_eq!(analysis.title, "日本語のページ");
+    assert!(analysis.content_text.contains("こんにちは、世界"));
+}
+
+#[tokio::test]
+async fn utf16_pages_are_detected_from_their_bom() {
+    let site = MockSite::start().await;
+    let page = "<html lang=\"de\"><title>Grüße</title><p>Schöne Grüße aus Köln</p></html>";
+    let mut body = vec![0xFF, 0xFE];
+    body.extend(page.encode_utf16().flat_map(u16::to_le_bytes));
+    site.serve(
+        "/de",
+        ResponseTemplate::new(200)
+            .insert_header("content-type", "text/html")
+            .set_body_bytes(body),
+    )
+    .await;
+    let config = site.config();
+
+    let (_, analysis) = process_page(
+        &site_url("/de"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics(),
+    )
+    .await
+    .expect("utf-16 page should be crawled");
+
+    assert_eq!(analysis.title, "Grüße");
+    assert!(analysis.content_text.contains("Schöne Grüße aus Köln"));
+}
+
+#[tokio::test]
+async fn stalled_responses_hit_the_read_timeout() {
+    let site = MockSite::start().await;
+    site.serve(
+        "/slow",
+        html(b"<p>Eventually</p>".to_vec()).set_delay(Duration::from_secs(5)),
+    )
+    .await;
+    let mut config = site.config();
+    config.timeouts.read_secs = 1;
+    let started = std::time::Instant::now();
+
+    let err = process_page(
+        &site_url("/slow"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics(),
+    )
+    .await
+    .expect_err("the response should time out");
+
+    assert!(started.elapsed() < Duration::from_secs(4));
+    assert!(matches!(err, CrawlError::Timeout(_)), "{:?}", err);
+}
+
+#[tokio::test]
+async fn oversized_pages_are_rejected_before_parsing() {
+    let site = MockSite::start().await;
+    let mut page = b"<html><body><p>".to_vec();
+    page.extend(std::iter::repeat_n(b'a', 4096));
+    page.extend(b"</p></body></html>");
+    site.serve("/huge", html(page)).await;
+    let mut config = site.config();
+    config.crawl.max_page_bytes = 1024;
+    let metrics = metrics();
+
+    let err = process_page(
+        &site_url("/huge"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics,
+    )
+    .await
+    .expect_err("oversized page should be rejected");
+
+    assert!(matches!(err, CrawlError::TooLarge(PageTooLarge { .. })));
+    assert_eq!(metrics.too_large.load(Ordering::Relaxed), 1);
+}
+
+#[tokio::test]
+async fn decompresses_gzip_responses() {
+    let site = MockSite::start().await;
+    site.serve(
+        "/",
+        html(gzip(&fixture("index.html"))).insert_header("content-encoding", "gzip"),
+    )
+    .await;
+    let config = site.config();
+
+    let (_, analysis) = process_page(
+        &site_url("/"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics(),
+    )
+    .await
+    .expect("gzip page should be crawled");
+
+    assert_eq!(analysis.title, "Site Test Home");
+}
+
+fn normalize(url: &str) -> String {
+    normalize_url(url, &CrawlConfig::default().tracking_params).unwrap()
+}
+
+#[test]
+fn normalize_url_strips_tracking_params() {
+    assert_eq!(
+        normalize("https://site.test/post?utm_source=x&id=5"),
+        "https://site.test/post?id=5"
+    );
+    assert_eq!(
+        normalize("https://site.test/post?fbclid=abc&gclid=def"),
+        "https://site.test/post"
+    );
+    assert_eq!(
+        normalize("https://site.test/post?UTM_Campaign=x&Ref=y&id=5"),
+        "https://site.test/post?id=5"
+    );
+
+    // the rest of the query is left exactly as written
+    for url in [
+        "https://site.test/post?flag",
+        "https://site.test/post?q=a+b&path=%2Fdocs",
+        "https://site.test/post?a=1&&b=2",
+    ] {
+        assert_eq!(normalize(url), url);
+    }
+    assert_eq!(
+        normalize("https://site.test/post?flag&utm_medium=mail&q=a+b"),
+        "https://site.test/post?flag&q=a+b"
+    );
+
+    let params = vec!["sessionid".to_string(), "trk_*".to_string()];
+    assert_eq!(
+        normalize_url(
+            "https://site.test/?sessionid=1&trk_a=2&utm_source=x",
+            &params
+        )
+        .unwrap(),
+        "https://site.test/?utm_source=x"
+    );
+}
+
+#[test]
+fn equivalent_urls_normalize_identically() {
+    let pairs = [
+        ("https://Site.TEST/about", "https://site.test/about"),
+        ("HTTPS://site.test/about", "https://site.test/about"),
+        ("http://site.test:80/about", "http://site.test/about"),
+        ("https://site.test:443/about", "https://site.test/about"),
+        (
+            "https://site.test//blog///post",
+            "https://site.test/blog/post",
+        ),
+        ("https://site.test//docs//", "https://site.test/docs/"),
+        ("https://site.test", "https://site.test/"),
+        (
+            "https://site.test/post?utm_medium=mail&id=5&fbclid=x",
+            "https://site.test/post?id=5",
+        ),
+    ];
+
+    for (variant, canonical) in pairs {
+        assert_eq!(
+            normalize(variant),
+            normalize(canonical),
+            "{} should normalize like {}",
+            variant,
+            canonical
+        );
+    }
+
+    // a trailing slash is part of the page's address
+    assert_eq!(
+        normalize("https://site.test/docs/"),
+        "https://site.test/docs/"
+    );
+    // non-default ports and meaningful params are kept
+    assert_eq!(
+        normalize("https://site.test:8443/post?id=5"),
+        "https://site.test:8443/post?id=5"
+    );
+}
+
+#[test]
+fn retry_after_accepts_seconds_and_http_dates() {
+    assert_eq!(retry_after("30"), Some(Duration::from_secs(30)));
+    assert_eq!(
+        retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+        Some(Duration::ZERO)
+    );
+    let in_a_minute = (chrono::Utc::now() + chrono::Duration::seconds(60)).to_rfc2822();
+    let delay = retry_after(&in_a_minute).unwrap();
+    assert!(delay > Duration::from_secs(55) && delay <= Duration::from_secs(60));
+    assert_eq!(retry_after("soon"), None);
+}
+
+fn crawl_config(collapse_www: bool, group_by_registrable_domain: bool) -> CrawlConfig {
+    CrawlConfig {
+        collapse_www,
+        group_by_registrable_domain,
+        ..CrawlConfig::default()
+    }
+}
+
+#[test]
+fn www_and_bare_hosts_share_a_queue_when_collapsed() {
+    let collapsed = crawl_config(true, false);
+    let separate = crawl_config(false, false);
+    let www = "https://www.example.com/a";
+    let bare = "https://example.com/b";
+    assert_eq!(domain_key(www, &collapsed).unwrap(), "example.com");
+    assert_eq!(domain_key(bare, &collapsed).unwrap(), "example.com");
+    assert_eq!(domain_key(www, &separate).unwrap(), "www.example.com");
+    // only a leading www. is a prefix to strip
+    assert_eq!(
+        domain_key("https://wwwexample.com/", &collapsed).unwrap(),
+        "wwwexample.com"
+    );
+
+    let mut queues = DomainQueues::new();
+    assert!(queues.add(domain_key(www, &collapsed).unwrap(), CrawlItem::seed(www)));
+    assert!(!queues.add(domain_key(bare, &collapsed).unwrap(), CrawlItem::seed(bare)));
+
+    let mut queues = DomainQueues::new();
+    assert!(queues.add(domain_key(www, &separate).unwrap(), CrawlItem::seed(www)));
+    assert!(queues.add(domain_key(bare, &separate).unwrap(), CrawlItem::seed(bare)));
+}
+
+#[test]
+fn registrable_domains_follow_the_public_suffix_list() {
+    let registrable = |url| registrable_domain(url).unwrap();
+    assert_eq!(registrable("https://www.example.com/"), "example.com");
+    assert_eq!(registrable("https://a.b.example.co.uk/"), "example.co.uk");
+    assert_eq!(registrable("https://example.co.uk/"), "example.co.uk");
+    assert_eq!(registrable("https://a.blogspot.com/"), "blogspot.com");
+    assert_eq!(registrable("https://b.blogspot.com/post"), "blogspot.com");
+    // suffixes and addresses have no registrable domain to shorten to
+    assert_eq!(registrable("https://co.uk/"), "co.uk");
+    assert_eq!(registrable("http://127.0.0.1:8080/"), "127.0.0.1");
+    assert_eq!(registrable("http://localhost/"), "localhost");
+
+    let grouped = crawl_config(false, true);
+    assert_eq!(
+        domain_key("https://a.example.co.uk/", &grouped).unwrap(),
+        domain_key("https://www.example.co.uk/", &grouped).unwrap()
+    );
+    assert_ne!(
+        domain_key("https://a.blogspot.com/", &crawl_config(true, false)).unwrap(),
+        domain_key("https://b.blogspot.com/", &crawl_config(true, false)).unwrap()
+    );
+}
+
+#[test]
+fn seeds_drain_before_deep_links() {
+    let seed = CrawlItem::seed("https://example.com/");
+    let child = seed.child("https://example.com/docs");
+    let deep = child.child("https://example.com/docs/a");
+    let deeper = deep.child("https://example.com/docs/a/b");
+    assert_eq!(deep.priority, deeper.priority);
+
+    let mut queues = DomainQueues::new();
+    for item in [&deeper, &deep, &child, &seed] {
+        queues.add("example.com".to_string(), item.clone());
+    }
+    let other_seed = CrawlItem::seed("https://example.org/");
+    queues.add("example.org".to_string(), other_seed.clone());
+    queues.add(
+        "example.org".to_string(),
+        other_seed.child("https://example.org/about"),
+    );
+
+    let urls = |batch: Vec<CrawlItem>| batch.into_iter().map(|item| item.url).collect::<Vec<_>>();
+    assert_eq!(
+        urls(queues.collect_batch(1)),
+        ["https://example.com/", "https://example.org/"]
+    );
+    assert_eq!(
+        urls(queues.collect_batch(1)),
+        ["https://example.org/about", "https://example.com/docs"]
+    );
+    // equal priorities keep the order they were found in
+    assert_eq!(
+        urls(queues.collect_batch(2)),
+        ["https://example.com/docs/a/b", "https://example.com/docs/a"]
+    );
+    assert_eq!(queues.total, 0);
+}
@@ -0,0 +1,40 @@
+use genesis::config::DedupConfig;
+use genesis::dedup::{hamming_distance, simhash, ContentHasher};
+
+const ARTICLE: &str = "Rust is a general purpose programming language emphasizing performance, \
+type safety and concurrency. It enforces memory safety, meaning that all references point to \
+valid memory, without a garbage collector. To simultaneously enforce memory safety and prevent \
+data races, its borrow checker tracks the object lifetime of all references in a program during \
+compilation. Rust was influenced by ideas from functional programming, including immutability, \
+higher order functions, algebraic data types and pattern matching. It also supports object \
+oriented programming via structs, enums, traits and methods. Software developer Graydon Hoare \
+created Rust as a personal project while working at Mozilla Research in 2006. Mozilla officially \
+sponsored the project in 2009. The first stable release, Rust 1.0, was published in May 2015.";
+
+#[test]
+fn pages_differing_only_in_whitespace_and_navigation_are_duplicates() {
+    let page = format!("Home About Contact {}", ARTICLE);
+    let print_view = format!(
+        "Home  Blog\n\n  Contact Print\t{}",
+        ARTICLE.replace(". ", ".\n\n    ")
+    );
+    let other = "A completely different page about baking sourdough bread at home, \
+        feeding the starter, shaping the loaf and scoring it before it goes into a hot oven.";
+
+    let config = DedupConfig::default();
+    let hasher = ContentHasher::from_config(&config);
+    assert!(!hasher.is_duplicate(simhash(&page)));
+    assert!(hasher.is_duplicate(simhash(&print_view)));
+    assert!(!hasher.is_duplicate(simhash(other)));
+    assert!(hamming_distance(simhash(&page), simhash(other)) > config.max_distance);
+}
+
+#[test]
+fn content_hasher_forgets_least_recently_seen_hashes() {
+    let hasher = ContentHasher::new(2, 0);
+    assert!(!hasher.is_duplicate(0b0001));
+    assert!(!hasher.is_duplicate(0b0010));
+    assert!(hasher.is_duplicate(0b0001));
+    assert!(!hasher.is_duplicate(0b0100));
+    assert!(!hasher.is_duplicate(0b0010));
+}
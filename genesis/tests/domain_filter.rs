@@ -0,0 +1,69 @@
+use genesis::config::DomainConfig;
+use genesis::domain_filter::DomainFilter;
+
+#[test]
+fn empty_filter_allows_everything() {
+    let filter = DomainFilter::new(Vec::<String>::new(), Vec::<String>::new());
+    assert!(filter.allows("example.com"));
+    assert!(filter.allows("anything.test"));
+}
+
+#[test]
+fn wildcards_match_subdomains_only() {
+    let filter = DomainFilter::new(["*.example.com", "exact.test"], Vec::<String>::new());
+    assert!(filter.allows("www.example.com"));
+    assert!(filter.allows("a.b.example.com"));
+    assert!(filter.allows("WWW.Example.COM"));
+    assert!(!filter.allows("example.com"));
+    assert!(!filter.allows("badexample.com"));
+    assert!(filter.allows("exact.test"));
+    assert!(!filter.allows("sub.exact.test"));
+}
+
+#[test]
+fn block_wins_over_allow() {
+    let filter = DomainFilter::new(
+        ["*.example.com"],
+        ["ads.example.com", "*.tracker.example.com"],
+    );
+    assert!(filter.allows("www.example.com"));
+    assert!(!filter.allows("ads.example.com"));
+    assert!(!filter.allows("cdn.tracker.example.com"));
+
+    let filter = DomainFilter::new(Vec::<String>::new(), ["blocked.test"]);
+    assert!(!filter.allows("blocked.test"));
+    assert!(filter.allows("other.test"));
+}
+
+#[test]
+fn patterns_are_read_from_files() {
+    let dir = std::env::temp_dir().join(format!("genesis-domains-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let allow_file = dir.join("allow.txt");
+    let block_file = dir.join("block.txt");
+    std::fs::write(&allow_file, "# news sites\n*.news.test\n\nblog.test\n").unwrap();
+    std::fs::write(&block_file, "spam.news.test\n").unwrap();
+
+    let config = DomainConfig {
+        allow: vec!["seed.test".to_string()],
+        block: Vec::new(),
+        allow_file: Some(allow_file),
+        block_file: Some(block_file),
+    };
+    let filter = DomainFilter::from_config(&config).unwrap();
+    assert!(filter.allows("seed.test"));
+    assert!(filter.allows("world.news.test"));
+    assert!(filter.allows("blog.test"));
+    assert!(!filter.allows("spam.news.test"));
+    assert!(!filter.allows("elsewhere.test"));
+
+    let missing = DomainConfig {
+        allow_file: Some(dir.join("missing.txt")),
+        ..DomainConfig::default()
+    };
+    assert!(DomainFilter::from_config(&missing)
+        .unwrap()
+        .allows("elsewhere.test"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
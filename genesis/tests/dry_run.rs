@@ -0,0 +1,48 @@
+mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{fixture, html, site_url, CollectingSink, MockSite};
+use futures::StreamExt;
+use genesis::Crawler;
+
+#[tokio::test]
+async fn dry_run_follows_links_without_writing_to_the_sink() {
+    let site = MockSite::start().await;
+    site.serve("/", html(fixture("index.html"))).await;
+    site.serve(
+        "/about",
+        html(b"<html><head><title>About</title></head><body>About the team</body></html>".to_vec()),
+    )
+    .await;
+    let mut config = site.config();
+    config.crawl.visited_file = None;
+    config.crawl.dry_run = true;
+
+    let sink = Arc::new(CollectingSink::default());
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let mut analyses = Crawler::builder()
+        .config(config)
+        .seeds([site_url("/")])
+        .sink(sink.clone())
+        .shutdown(shutdown.clone())
+        .build()
+        .crawl();
+
+    let mut crawled = Vec::new();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while let Some(analysis) = analyses.next().await {
+            crawled.push(analysis.url);
+            if crawled.contains(&site_url("/about")) {
+                shutdown.store(true, Ordering::Relaxed);
+            }
+        }
+    })
+    .await
+    .expect("the linked page should be crawled");
+
+    assert!(crawled.contains(&site_url("/")));
+    assert!(sink.saved.lock().unwrap().is_empty());
+}
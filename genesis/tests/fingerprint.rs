@@ -0,0 +1,125 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr};
+
+use genesis::fingerprint::{Browser, RequestFingerprint};
+
+const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7));
+const URL: &str = "https://site.test/about";
+
+#[test]
+fn user_agent_is_stable_within_a_window() {
+    let window = RequestFingerprint::current_window();
+    let first = RequestFingerprint::for_window(&IP, URL, window);
+    for _ in 0..10 {
+        let again = RequestFingerprint::for_window(&IP, URL, window);
+        assert_eq!(again.user_agent, first.user_agent);
+        assert_eq!(again.referrer, first.referrer);
+    }
+}
+
+#[test]
+fn user_agent_rotates_across_windows() {
+    let user_agents: HashSet<String> = (0..24)
+        .map(|window| RequestFingerprint::for_window(&IP, URL, window).user_agent)
+        .collect();
+    assert!(user_agents.len() > 1);
+}
+
+#[test]
+fn headers_follow_the_chosen_browser() {
+    for window in 0..50 {
+        let fp = RequestFingerprint::for_window(&IP, URL, window);
+        let names: Vec<&str> = fp.headers.iter().map(|(name, _)| name.as_str()).collect();
+        let value = |name: &str| {
+            fp.headers
+                .iter()
+                .find(|(header, _)| header.as_str() == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        assert_eq!(value("user-agent"), Some(fp.user_agent.as_str()));
+        assert_eq!(value("sec-fetch-mode"), Some("navigate"));
+        assert!(value("accept-language").is_some_and(|languages| languages.starts_with("en-")));
+        assert_eq!(Browser::from_user_agent(&fp.user_agent), fp.browser);
+        match &fp.referrer {
+            Some(referrer) => {
+                assert_eq!(value("referer"), Some(referrer.as_str()));
+                assert_eq!(value("sec-fetch-site"), Some("same-origin"));
+            }
+            None => {
+                assert_eq!(value("referer"), None);
+                assert_eq!(value("sec-fetch-site"), Some("none"));
+            }
+        }
+        let first = match fp.browser {
+            Browser::Chromium => "sec-ch-ua",
+            Browser::Firefox => "user-agent",
+            Browser::Safari => "accept",
+        };
+        assert_eq!(names[0], first, "{}", fp.user_agent);
+    }
+}
+
+#[test]
+fn browsers_are_recognised_from_their_user_agent() {
+    let cases = [
+        ("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/132.0.0.0 Safari/537.36 Edg/132.0.0.", Browser::Chromium),
+        ("Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:134.0) Gecko/20100101 Firefox/134.", Browser::Firefox),
+        ("Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/18.1.1 Safari/605.1.1", Browser::Safari),
+        ("Mozilla/5.0 (iPhone; CPU iPhone OS 17_6_1 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) CriOS/132.0.6834.100 Mobile/15E148 Safari/604.", Browser::Safari),
+    ];
+    for (user_agent, browser) in cases {
+        assert_eq!(
+            Browser::from_user_agent(user_agent),
+            browser,
+            "{}",
+            user_agent
+        );
+    }
+}
+
+#[test]
+fn chromium_client_hints_match_the_user_agent() {
+    let mut seen = 0;
+    for window in 0..200 {
+        let fp = RequestFingerprint::for_window(&IP, URL, window);
+        let value = |name: &str| {
+            fp.headers
+                .iter()
+                .find(|(header, _)| header.as_str() == name)
+                .map(|(_, value)| value.as_str())
+        };
+        if fp.browser != Browser::Chromium {
+            assert_eq!(value("sec-ch-ua"), None);
+            continue;
+        }
+        seen += 1;
+
+        let chrome = fp.user_agent.split("Chrome/").nth(1).unwrap();
+        let major = chrome.split('.').next().unwrap();
+        let brands = value("sec-ch-ua").unwrap();
+        assert!(
+            brands.contains(&format!("\"Chromium\";v=\"{}\"", major)),
+            "{} for {}",
+            brands,
+            fp.user_agent
+        );
+        assert_eq!(
+            brands.contains("Microsoft Edge"),
+            fp.user_agent.contains("Edg/")
+        );
+        let mobile = if fp.user_agent.contains("Mobile") {
+            "?1"
+        } else {
+            "?0"
+        };
+        assert_eq!(value("sec-ch-ua-mobile"), Some(mobile));
+        let platform = if fp.user_agent.contains("Android") {
+            "\"Android\""
+        } else {
+            "\"Windows\""
+        };
+        assert_eq!(value("sec-ch-ua-platform"), Some(platform));
+    }
+    assert!(seen > 0);
+}
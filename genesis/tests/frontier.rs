@@ -0,0 +1,95 @@
+mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{html, site_url, MockSite};
+use futures::StreamExt;
+use genesis::crawler::CrawlItem;
+use genesis::frontier::{self, Frontier};
+use genesis::Crawler;
+
+fn page(title: &str) -> Vec<u8> {
+    format!(
+        "<html><head><title>{}</title></head><body><p>{}</p></body></html>",
+        title, title
+    )
+    .into_bytes()
+}
+
+#[test]
+fn saved_frontier_restores_pending_items() {
+    let path = std::env::temp_dir().join(format!("genesis-frontier-{}.jsonl", std::process::id()));
+    let seed = CrawlItem::seed("https://example.com/");
+    let child = seed.child("https://example.com/docs");
+    let retry = child.child("https://example.org/busy").retry();
+
+    let frontier = Frontier::new();
+    for item in [&seed, &child, &retry] {
+        frontier.insert(item);
+    }
+    frontier.remove(&seed.url);
+    assert_eq!(frontier.save(&path).unwrap(), 2);
+
+    let restored = frontier::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(restored, vec![child, retry]);
+    assert!(frontier::load(&path).unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn restored_urls_are_crawled_once_alongside_the_seeds() {
+    let site = MockSite::start().await;
+    site.serve("/", html(page("Home"))).await;
+    site.serve("/restored", html(page("Restored"))).await;
+
+    let path = std::env::temp_dir().join(format!("genesis-resume-{}.jsonl", std::process::id()));
+    let previous = Frontier::new();
+    let seed = CrawlItem::seed(site_url("/"));
+    previous.insert(&seed);
+    previous.insert(&seed.child(site_url("/restored")));
+    previous.save(&path).unwrap();
+
+    let mut config = site.config();
+    config.crawl.visited_file = None;
+    config.crawl.frontier_file = Some(path.clone());
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let crawler = Crawler::builder()
+        .config(config)
+        .seeds([site_url("/")])
+        .shutdown(shutdown.clone())
+        .build();
+    let metrics = crawler.metrics();
+    let mut analyses = crawler.crawl();
+
+    let mut crawled = Vec::new();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while crawled.len() < 2 {
+            crawled.push(
+                analyses
+                    .next()
+                    .await
+                    .expect("both pages should be crawled")
+                    .url,
+            );
+        }
+        shutdown.store(true, Ordering::Relaxed);
+        while let Some(analysis) = analyses.next().await {
+            crawled.push(analysis.url);
+        }
+    })
+    .await
+    .expect("the stream should end after shutdown");
+
+    crawled.sort();
+    assert_eq!(crawled, vec![site_url("/"), site_url("/restored")]);
+    assert_eq!(metrics.success.load(Ordering::Relaxed), 2);
+    let remaining = frontier::load(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert!(
+        remaining.is_empty(),
+        "left in the frontier: {:?}",
+        remaining
+    );
+}
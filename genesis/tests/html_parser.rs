@@ -0,0 +1,364 @@
+mod common;
+
+use common::fixture;
+use genesis::html_parser::{looks_like_html, parse_base_url, parse_html, InvalidBaseUrl};
+use genesis::Heading;
+use serde_json::json;
+
+#[test]
+fn fixture_home_page_is_fully_extracted() {
+    let parsed = parse_html(
+        &fixture("index.html"),
+        &parse_base_url("http://site.test/").unwrap(),
+    );
+
+    assert_eq!(parsed.title, "Site Test Home");
+    assert_eq!(parsed.language, "en");
+    assert_eq!(parsed.canonical_url.as_deref(), Some("http://site.test/"));
+    let meta: Vec<(&str, &str)> = parsed
+        .meta_tags
+        .iter()
+        .map(|tag| (tag.name.as_str(), tag.content.as_str()))
+        .collect();
+    assert_eq!(
+        meta,
+        vec![
+            ("description", "A small fake site for crawler tests"),
+            ("og:title", "Site Test"),
+        ]
+    );
+
+    let mut links = parsed.links;
+    links.sort();
+    assert_eq!(
+        links,
+        vec![
+            "http://cdn.site.test/assets/page",
+            "http://site.test/about",
+            "http://site.test/blog/post?utm_source=x&id=5",
+        ]
+    );
+    assert!(parsed.content_text.starts_with("Welcome to Site Test"));
+    assert!(parsed
+        .content_text
+        .contains("This page links to a handful of places."));
+}
+
+#[test]
+fn fixture_blog_post_keeps_its_title_and_text() {
+    let parsed = parse_html(
+        &fixture("blog_post.html"),
+        &parse_base_url("http://site.test/blog/tuning").unwrap(),
+    );
+
+    assert_eq!(
+        parsed.title,
+        "Tuning a Crawler for Throughput | Site Test Blog"
+    );
+    assert_eq!(parsed.canonical_url, None);
+    assert!(parsed
+        .content_text
+        .contains("Most crawlers spend their time waiting"));
+    assert!(parsed.links.contains(&"http://site.test/blog".to_string()));
+}
+
+#[test]
+fn malformed_markup_is_parsed_as_far_as_it_goes() {
+    let page = b"<html><head><title>Broken</title></head><body><p>Unclosed <b>bold\
+        <a href='/one'>one<a href=/two>two</p></div></span><select><svg><p>After</body>";
+
+    let parsed = parse_html(page, &parse_base_url("https://site.test/").unwrap());
+
+    let mut links = parsed.links;
+    links.sort();
+    assert_eq!(
+        links,
+        vec!["https://site.test/one", "https://site.test/two"]
+    );
+    assert!(parsed.content_text.contains("Unclosed"));
+
+    let parsed = parse_html(
+        b"\xff\xfe<<<>>>&&&",
+        &parse_base_url("https://site.test/").unwrap(),
+    );
+    assert!(parsed.links.is_empty());
+}
+
+#[test]
+fn unparseable_base_urls_are_reported() {
+    let err: InvalidBaseUrl = parse_base_url("not a url").unwrap_err();
+    assert_eq!(err.url, "not a url");
+    assert!(err.to_string().contains("not a url"));
+}
+
+#[test]
+fn html_is_recognised_with_or_without_a_content_type() {
+    let page = b"\n  <!DOCTYPE html><html><body>Hi</body></html>";
+    assert!(looks_like_html(page, Some("text/html; charset=utf-8")));
+    assert!(looks_like_html(page, Some("text/plain")));
+    assert!(looks_like_html(page, None));
+    assert!(looks_like_html(
+        b"<?xml version=\"1.0\"?>\n<html xmlns=\"http://www.w3.org/1999/xhtml\"></html>",
+        Some("application/xhtml+xml")
+    ));
+}
+
+#[test]
+fn xml_is_not_html() {
+    let feed = b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<rss version=\"2.0\"></rss>";
+    assert!(!looks_like_html(feed, None));
+    assert!(!looks_like_html(feed, Some("text/plain")));
+}
+
+#[test]
+fn json_is_not_html() {
+    assert!(!looks_like_html(b"{\"html\": \"<p>\"}", None));
+    assert!(!looks_like_html(b"  [1, 2, 3]", Some("text/plain")));
+    assert!(!looks_like_html(b"<p>", Some("application/json")));
+}
+
+#[test]
+fn pdf_is_not_html() {
+    assert!(!looks_like_html(b"%PDF-1.7\n%\xE2\xE3\xCF\xD3", None));
+    assert!(!looks_like_html(b"<html>", Some("application/pdf")));
+}
+
+#[test]
+fn json_ld_blocks_are_extracted_and_malformed_ones_skipped() {
+    let page = br#"<html><head>
+        <script type="application/ld+json">
+          {"@context": "https://schema.org", "@type": "Article", "headline": "Hello"}
+        </script>
+        <script type="application/ld+json">{"@type": "Broken",</script>
+        <script>var notJsonLd = {};</script>
+    </head><body><p>Text</p></body></html>"#;
+
+    let parsed = parse_html(page, &parse_base_url("https://example.com/").unwrap());
+
+    assert_eq!(
+        parsed.structured_data,
+        vec![json!({
+            "@context": "https://schema.org",
+            "@type": "Article",
+            "headline": "Hello"
+        })]
+    );
+}
+
+#[test]
+fn headings_keep_their_level_and_order() {
+    let page = b"<html><body>
+        <h1>Guide</h1><p>Intro</p>
+        <h2>Getting <a href=\"/start\">started</a></h2>
+        <h2>  Next\n steps &amp; more </h2>
+        <h3></h3>
+    </body></html>";
+
+    let parsed = parse_html(page, &parse_base_url("https://example.com/").unwrap());
+
+    assert_eq!(
+        parsed.headings,
+        vec![
+            Heading {
+                level: 1,
+                text: "Guide".into()
+            },
+            Heading {
+                level: 2,
+                text: "Getting started".into()
+            },
+            Heading {
+                level: 2,
+                text: "Next steps & more".into()
+            },
+        ]
+    );
+    assert!(parsed.content_text.contains("Guide"));
+}
+
+#[test]
+fn entities_are_decoded_in_the_title_and_the_text() {
+    let page =
+        b"<html><head><title>Tom &amp; Jerry &mdash; It&rsquo;s back &copy; 2024</title></head>
+        <body><p>Tom &amp; Jerry&hellip; caf&eacute; &#169; &#x2014;</p></body></html>";
+
+    let parsed = parse_html(page, &parse_base_url("https://example.com/").unwrap());
+
+    assert_eq!(
+        parsed.title,
+        "Tom & Jerry \u{2014} It\u{2019}s back \u{a9} 2024"
+    );
+    assert_eq!(
+        parsed.content_text,
+        "Tom & Jerry\u{2026} caf\u{e9} \u{a9} \u{2014}"
+    );
+}
+
+#[test]
+fn a_target_linked_five_times_is_returned_once() {
+    let page = br#"<a href="/pricing">Pricing</a>
+        <a href="/pricing#plans">Plans</a>
+        <a href="https://SITE.test/pricing">Header</a>
+        <a href="https://site.test/pricing#faq">FAQ</a>
+        <a href="pricing">Footer</a>"#;
+
+    let parsed = parse_html(page, &parse_base_url("https://site.test/").unwrap());
+
+    assert_eq!(parsed.links, vec!["https://site.test/pricing"]);
+}
+
+#[test]
+fn non_navigational_links_are_skipped() {
+    let page = br#"<a href="mailto:a@b.c">m</a><a href="javascript:alert(1)">j</a>
+        <a href=" JavaScript:void(0)">J</a><a href="data:text/plain,x">d</a>
+        <a href="tel:123">t</a><a href="ftp://site.test/file">f</a><a href="/about">a</a>"#;
+
+    let parsed = parse_html(page, &parse_base_url("https://site.test/").unwrap());
+
+    assert_eq!(parsed.links, vec!["https://site.test/about"]);
+}
+
+#[test]
+fn protocol_relative_links_take_the_page_scheme() {
+    let page = br#"<a href="//other.test/x">o</a>"#;
+
+    let https = parse_html(page, &parse_base_url("https://site.test/").unwrap());
+    assert_eq!(https.links, vec!["https://other.test/x"]);
+    let http = parse_html(page, &parse_base_url("http://site.test/").unwrap());
+    assert_eq!(http.links, vec!["http://other.test/x"]);
+}
+
+#[test]
+fn links_resolve_against_the_base_element() {
+    let page = br#"<html><head><a href="early">before base</a>
+        <base href="https://cdn.example.com/assets/"><base href="https://ignored.example.com/">
+        </head><body><a href="page">relative</a><a href="/root">absolute path</a>
+        <a href="https://other.example.com/x">absolute</a></body></html>"#;
+
+    let parsed = parse_html(
+        page,
+        &parse_base_url("https://site.test/dir/index.html").unwrap(),
+    );
+
+    let mut links = parsed.links;
+    links.sort();
+    assert_eq!(
+        links,
+        vec![
+            "https://cdn.example.com/assets/early",
+            "https://cdn.example.com/assets/page",
+            "https://cdn.example.com/root",
+            "https://other.example.com/x",
+        ]
+    );
+}
+
+#[test]
+fn relative_base_element_resolves_against_the_page() {
+    let page = br#"<base href="/docs/"><a href="intro">intro</a>"#;
+
+    let parsed = parse_html(
+        page,
+        &parse_base_url("https://site.test/dir/index.html").unwrap(),
+    );
+
+    assert_eq!(parsed.links, vec!["https://site.test/docs/intro"]);
+}
+
+#[test]
+fn nofollow_links_are_kept_out_of_the_frontier() {
+    let page = br#"<a href="/followed">a</a>
+        <a href="/sponsored" rel="sponsored nofollow">b</a>
+        <a href="/upper" rel="NoFollow">c</a>
+        <a href="/noopener" rel="noopener noreferrer">d</a>"#;
+
+    let parsed = parse_html(page, &parse_base_url("https://site.test/").unwrap());
+
+    let mut links = parsed.links;
+    links.sort();
+    assert_eq!(
+        links,
+        vec!["https://site.test/followed", "https://site.test/noopener"]
+    );
+    let mut nofollow_links = parsed.nofollow_links;
+    nofollow_links.sort();
+    assert_eq!(
+        nofollow_links,
+        vec!["https://site.test/sponsored", "https://site.test/upper"]
+    );
+}
+
+#[test]
+fn relative_favicons_resolve_against_the_page() {
+    let page = br#"<html><head>
+        <link rel="apple-touch-icon" href="/apple.png">
+        <link rel="shortcut icon" href="../static/favicon.ico">
+        <link rel="icon" href="/second.png">
+        </head></html>"#;
+
+    let parsed = parse_html(
+        page,
+        &parse_base_url("https://site.test/blog/post").unwrap(),
+    );
+    assert_eq!(
+        parsed.favicon_url.as_deref(),
+        Some("https://site.test/static/favicon.ico")
+    );
+
+    let parsed = parse_html(
+        br#"<base href="https://cdn.site.test/assets/"><link rel="icon" href="icon.svg">"#,
+        &parse_base_url("https://site.test/").unwrap(),
+    );
+    assert_eq!(
+        parsed.favicon_url.as_deref(),
+        Some("https://cdn.site.test/assets/icon.svg")
+    );
+}
+
+#[test]
+fn og_image_is_preferred_over_inline_images() {
+    let page = br#"<meta property="og:image" content="/cover.jpg">
+        <img src="/hero.jpg" width="1200">"#;
+    let parsed = parse_html(page, &parse_base_url("https://site.test/post").unwrap());
+    assert_eq!(
+        parsed.image_url.as_deref(),
+        Some("https://site.test/cover.jpg")
+    );
+}
+
+#[test]
+fn small_and_inline_images_are_not_thumbnails() {
+    let page = br#"<img src="/pixel.gif" width="1" height="1">
+        <img src="/badge.png" height="32px">
+        <img src="data:image/png;base64,AAAA">
+        <img src="photos/hero.jpg" width="800" height="450">"#;
+    let parsed = parse_html(page, &parse_base_url("https://site.test/post").unwrap());
+    assert_eq!(
+        parsed.image_url.as_deref(),
+        Some("https://site.test/photos/hero.jpg")
+    );
+}
+
+#[test]
+fn hreflang_alternates_are_resolved() {
+    let page = br#"<head>
+        <link rel="alternate" hreflang="en" href="https://site.test/en/pricing">
+        <link rel="alternate" hreflang="fr" href="/fr/tarifs">
+        <link rel="alternate" hreflang="de" href="../de/preise#plans">
+        <link rel="alternate" type="application/rss+xml" href="/feed.xml">
+        <link rel="stylesheet" hreflang="en" href="/style.css">
+    </head>"#;
+    let parsed = parse_html(
+        page,
+        &parse_base_url("https://site.test/en/pricing").unwrap(),
+    );
+
+    assert_eq!(
+        parsed.alternates,
+        vec![
+            ("en".to_string(), "https://site.test/en/pricing".to_string()),
+            ("fr".to_string(), "https://site.test/fr/tarifs".to_string()),
+            ("de".to_string(), "https://site.test/de/preise".to_string()),
+        ]
+    );
+}
@@ -0,0 +1,18 @@
+use genesis::lang_detect::detect_language;
+
+#[test]
+fn detects_english_and_french() {
+    let english = detect_language(
+        "The quick brown fox jumps over the lazy dog while the farmer watches from the porch.",
+    )
+    .unwrap();
+    assert_eq!(english.code, "en");
+    assert!(english.confidence > 0.5);
+
+    let french = detect_language(
+        "Le renard brun rapide saute par-dessus le chien paresseux pendant que le fermier regarde.",
+    )
+    .unwrap();
+    assert_eq!(french.code, "fr");
+    assert!(french.confidence > 0.5);
+}
@@ -0,0 +1,35 @@
+use std::time::Duration;
+
+use genesis::limiter::{DomainPageCap, DomainSpacing, PageSlot};
+use tokio::time::Instant;
+
+#[test]
+fn domains_are_capped_independently() {
+    let cap = DomainPageCap::new(2);
+
+    assert!(!cap.is_capped("forum.test"));
+    assert_eq!(cap.take("forum.test"), PageSlot::Granted);
+    assert_eq!(cap.take("forum.test"), PageSlot::Last);
+    assert!(cap.is_capped("forum.test"));
+    assert_eq!(cap.take("forum.test"), PageSlot::Capped);
+    assert_eq!(cap.take("forum.test"), PageSlot::Capped);
+
+    assert!(!cap.is_capped("blog.test"));
+    assert_eq!(cap.take("blog.test"), PageSlot::Granted);
+}
+
+#[tokio::test]
+async fn rapid_requests_to_one_host_are_spaced_out() {
+    let spacing = DomainSpacing::new(Duration::from_millis(200), Duration::from_millis(50));
+    let start = Instant::now();
+
+    spacing.wait("forum.test").await;
+    assert!(start.elapsed() < Duration::from_millis(100));
+    spacing.wait("blog.test").await;
+    assert!(start.elapsed() < Duration::from_millis(100));
+
+    spacing.wait("forum.test").await;
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(200), "waited {:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(400), "waited {:?}", elapsed);
+}
@@ -0,0 +1,69 @@
+use genesis::logger::{AsyncLogger, LogEvent, LogFormat, Rotation};
+
+#[tokio::test]
+async fn flush_rotates_past_the_size_threshold() {
+    let dir = std::env::temp_dir().join(format!("genesis-logs-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let path = dir.join("crawler-test.log");
+
+    let rotation = Rotation {
+        max_bytes: 64,
+        max_files: 2,
+    };
+    let logger = AsyncLogger::open(&path, 100, LogFormat::Text, Some(rotation)).unwrap();
+    let mut logger = logger.lock().await;
+
+    for round in 0..4 {
+        logger
+            .add_entry(format!("round {} {}", round, "x".repeat(64)))
+            .unwrap();
+        logger.flush().unwrap();
+    }
+
+    // every flush crossed the threshold, so only the two newest rotations survive
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+    assert!(std::fs::read_to_string(logger.rotated_path(1))
+        .unwrap()
+        .contains("round 3"));
+    assert!(std::fs::read_to_string(logger.rotated_path(2))
+        .unwrap()
+        .contains("round 2"));
+    assert!(!logger.rotated_path(3).exists());
+
+    logger.add_entry("after rotation".to_string()).unwrap();
+    logger.flush().unwrap();
+    assert!(std::fs::read_to_string(&path)
+        .unwrap()
+        .contains("after rotation"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn json_mode_writes_one_object_per_line() {
+    let dir = std::env::temp_dir().join(format!("genesis-json-logs-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let path = dir.join("crawler-test.log");
+
+    let logger = AsyncLogger::open(&path, 100, LogFormat::Json, None).unwrap();
+    let mut logger = logger.lock().await;
+    logger
+        .add_event(LogEvent::info("fetched").url("https://example.com/"))
+        .unwrap();
+    logger.add_entry("plain message".to_string()).unwrap();
+    logger.flush().unwrap();
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<serde_json::Value> = content
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["level"], "info");
+    assert_eq!(lines[0]["event"], "fetched");
+    assert_eq!(lines[0]["url"], "https://example.com/");
+    assert!(lines[0]["ts"].is_string());
+    assert_eq!(lines[1]["message"], "plain message");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
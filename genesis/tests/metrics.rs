@@ -0,0 +1,118 @@
+use std::sync::atomic::Ordering;
+
+use genesis::block_page::{BlockKind, Blocked};
+use genesis::metrics::{render_prometheus, DomainStats, FailureBreakdown, Metrics};
+use genesis::network::{PageTooLarge, RateLimited};
+use genesis::soft404::SoftNotFound;
+use genesis::CrawlError;
+
+#[test]
+fn prometheus_output_lists_counters_and_gauges() {
+    let metrics = Metrics::default();
+    metrics.total.store(12, Ordering::Relaxed);
+    metrics.success.store(10, Ordering::Relaxed);
+    metrics.failed.store(2, Ordering::Relaxed);
+    metrics.total_left.store(88, Ordering::Relaxed);
+    metrics.effective_concurrency.store(6, Ordering::Relaxed);
+
+    let output = render_prometheus(&metrics);
+    assert!(output.contains("# TYPE genesis_total_total counter\ngenesis_total_total 12\n"));
+    assert!(output.contains("genesis_success_total 10\n"));
+    assert!(output.contains("genesis_failed_total 2\n"));
+    assert!(output.contains("genesis_tunnel_total 0\n"));
+    assert!(output.contains("# TYPE genesis_total_left gauge\ngenesis_total_left 88\n"));
+    assert!(output.contains("genesis_effective_concurrency 6\n"));
+    assert!(output.contains("# TYPE genesis_requests_per_second gauge\n"));
+}
+
+#[test]
+fn top_domains_are_ordered_by_volume() {
+    let metrics = Metrics::default();
+    for _ in 0..3 {
+        metrics.record_domain("busy.example", true);
+    }
+    metrics.record_domain("busy.example", false);
+    metrics.record_domain("quiet.example", false);
+    metrics.record_domain("medium.example", true);
+    metrics.record_domain("medium.example", true);
+
+    let top = metrics.top_domains(2);
+    assert_eq!(top.len(), 2);
+    assert_eq!(top[0].0, "busy.example");
+    assert_eq!(
+        top[0].1,
+        DomainStats {
+            total: 4,
+            success: 3,
+            failed: 1
+        }
+    );
+    assert_eq!(top[1].0, "medium.example");
+}
+
+#[test]
+fn summary_reports_breakdowns_and_averages() {
+    let metrics = Metrics::default();
+    metrics.total.store(5, Ordering::Relaxed);
+    metrics.success.store(4, Ordering::Relaxed);
+    metrics.failed.store(1, Ordering::Relaxed);
+    metrics.tunnel.store(3, Ordering::Relaxed);
+    metrics.proxy.store(2, Ordering::Relaxed);
+    metrics.content_bytes.store(1000, Ordering::Relaxed);
+    for domain in ["a.example", "a.example", "b.example", "c.example"] {
+        metrics.record_domain(domain, true);
+    }
+    metrics.record_domain("c.example", false);
+
+    let summary = metrics.summary();
+    assert_eq!(summary.distinct_domains, 3);
+    assert_eq!(summary.tunnel_proxy_ratio, 1.5);
+    assert_eq!(summary.average_content_bytes, 250.0);
+    assert_eq!(summary.top_domains.len(), 3);
+
+    let json = serde_json::to_value(&summary).unwrap();
+    assert_eq!(json["total"], 5);
+    assert_eq!(json["failed"], 1);
+    assert_eq!(json["top_domains"][0]["domain"], "a.example");
+    assert_eq!(json["top_domains"][0]["success"], 2);
+    assert!(json["elapsed_secs"].is_number());
+}
+
+#[test]
+fn failures_are_bucketed_by_kind() {
+    let metrics = Metrics::default();
+    metrics.record_failure(&CrawlError::Forbidden);
+    metrics.record_failure(&CrawlError::Forbidden);
+    metrics.record_failure(&CrawlError::NotHtml);
+    metrics.record_failure(&CrawlError::NoProxy);
+    metrics.record_failure(&CrawlError::RateLimited(RateLimited { retry_after: None }));
+    metrics.record_failure(&CrawlError::SoftNotFound(SoftNotFound));
+
+    assert_eq!(metrics.failed.load(Ordering::Relaxed), 4);
+    assert_eq!(metrics.rate_limited.load(Ordering::Relaxed), 1);
+    assert_eq!(
+        metrics.failures(),
+        FailureBreakdown {
+            forbidden: 2,
+            not_html: 1,
+            network: 1,
+            ..Default::default()
+        }
+    );
+    assert!(render_prometheus(&metrics).contains("genesis_forbidden_total 2\n"));
+    assert!(metrics
+        .summary()
+        .to_string()
+        .contains("Failures: 0 timeout, 2 forbidden, 0 blocked"));
+}
+
+#[test]
+fn only_struggling_or_blocking_hosts_count_against_their_health() {
+    assert!(CrawlError::RateLimited(RateLimited { retry_after: None }).is_host_failure());
+    assert!(CrawlError::Forbidden.is_host_failure());
+    assert!(CrawlError::Blocked(Blocked(BlockKind::CloudflareChallenge)).is_host_failure());
+    assert!(!CrawlError::NotHtml.is_host_failure());
+    assert!(!CrawlError::TooLarge(PageTooLarge { limit: 1024 }).is_host_failure());
+    assert!(!CrawlError::Parse("not a url".into()).is_host_failure());
+    assert!(!CrawlError::SoftNotFound(SoftNotFound).is_host_failure());
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use common::{fixture, html, metrics, site_url, MockSite};
+use genesis::crawler::process_page;
+use genesis::readability::extract_main_content;
+
+#[test]
+fn keeps_the_article_and_drops_the_chrome() {
+    let html = String::from_utf8(fixture("blog_post.html")).unwrap();
+    let content = extract_main_content(&html);
+
+    assert!(
+        content.starts_with("Tuning a Crawler for Throughput"),
+        "{}",
+        content
+    );
+    assert!(content.contains("Most crawlers spend their time waiting"));
+    assert!(content.contains("Throughput is worthless if every site bans you."));
+    assert!(content.contains("Cap concurrent requests per host"));
+    for boilerplate in ["About us and our team", "Related posts", "Copyright"] {
+        assert!(
+            !content.contains(boilerplate),
+            "{} in {}",
+            boilerplate,
+            content
+        );
+    }
+}
+
+#[test]
+fn pages_without_prose_have_no_main_content() {
+    let html = "<html><body><nav><a href=\"/\">Home</a></nav><p>Short.</p></body></html>";
+    assert_eq!(extract_main_content(html), "");
+}
+
+#[tokio::test]
+async fn main_content_is_only_extracted_when_enabled() {
+    let site = MockSite::start().await;
+    site.serve("/blog/tuning", html(fixture("blog_post.html")))
+        .await;
+    let mut config = site.config();
+
+    let (_, analysis) = process_page(
+        &site_url("/blog/tuning"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(analysis.main_content, "");
+    assert!(analysis.content_text.contains("Related posts"));
+
+    config.crawl.extract_main_content = true;
+    let (_, analysis) = process_page(
+        &site_url("/blog/tuning"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics(),
+    )
+    .await
+    .unwrap();
+    assert!(analysis
+        .main_content
+        .contains("Most crawlers spend their time waiting"));
+    assert!(!analysis.main_content.contains("Related posts"));
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{html, metrics, site_url, CollectingSink, MockSite};
+use futures::StreamExt;
+use genesis::crawler::process_page;
+use genesis::html_parser::{parse_base_url, parse_html, RobotsMeta};
+use genesis::Crawler;
+
+const NOINDEX_PAGE: &[u8] = br#"<html><head><title>Tag: rust</title>
+    <meta name="robots" content="noindex, follow"></head>
+    <body><a href="/about">About</a></body></html>"#;
+
+#[test]
+fn robots_meta_tags_are_parsed() {
+    let page = br#"<head><meta name="ROBOTS" content="NoIndex">
+        <meta name="googlebot" content="nofollow"></head>"#;
+    let parsed = parse_html(page, &parse_base_url("https://site.test/").unwrap());
+    assert_eq!(
+        parsed.robots_directives,
+        RobotsMeta {
+            noindex: true,
+            nofollow: false
+        }
+    );
+
+    let parsed = parse_html(
+        br#"<meta name="robots" content="none">"#,
+        &parse_base_url("https://site.test/").unwrap(),
+    );
+    assert!(parsed.robots_directives.noindex && parsed.robots_directives.nofollow);
+
+    let parsed = parse_html(
+        b"<p>No directives</p>",
+        &parse_base_url("https://site.test/").unwrap(),
+    );
+    assert_eq!(parsed.robots_directives, RobotsMeta::default());
+}
+
+#[test]
+fn robots_headers_scoped_to_other_crawlers_are_ignored() {
+    assert!(RobotsMeta::from_header("noindex, nofollow").nofollow);
+    assert_eq!(
+        RobotsMeta::from_header("noarchive, unavailable_after: 25 Jun 2030"),
+        RobotsMeta::default()
+    );
+    assert_eq!(
+        RobotsMeta::from_header("googlebot: noindex"),
+        RobotsMeta::default()
+    );
+}
+
+#[tokio::test]
+async fn x_robots_tag_headers_apply_to_the_page() {
+    let site = MockSite::start().await;
+    site.serve(
+        "/",
+        html(br#"<a href="/about">About</a>"#.to_vec()).insert_header("x-robots-tag", "nofollow"),
+    )
+    .await;
+    let config = site.config();
+
+    let (links, analysis) = process_page(
+        &site_url("/"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics(),
+    )
+    .await
+    .unwrap();
+
+    assert!(links.is_empty());
+    assert!(analysis.robots.nofollow);
+    assert!(!analysis.robots.noindex);
+}
+
+#[tokio::test]
+async fn noindex_pages_are_fetched_and_followed_but_not_stored() {
+    let site = MockSite::start().await;
+    site.serve("/", html(NOINDEX_PAGE.to_vec())).await;
+    site.serve(
+        "/about",
+        html(b"<html><head><title>About</title></head><body>About the team</body></html>".to_vec()),
+    )
+    .await;
+    let mut config = site.config();
+    config.crawl.visited_file = None;
+
+    let sink = Arc::new(CollectingSink::default());
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let crawler = Crawler::builder()
+        .config(config)
+        .seeds([site_url("/")])
+        .sink(sink.clone())
+        .shutdown(shutdown.clone())
+        .build();
+    let metrics = crawler.metrics();
+    let mut analyses = crawler.crawl();
+
+    let mut crawled = Vec::new();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while let Some(analysis) = analyses.next().await {
+            crawled.push(analysis.url);
+            shutdown.store(true, Ordering::Relaxed);
+        }
+    })
+    .await
+    .expect("the linked page should be crawled");
+
+    assert_eq!(crawled, vec![site_url("/about")]);
+    assert_eq!(metrics.success.load(Ordering::Relaxed), 2);
+    assert_eq!(*sink.saved.lock().unwrap(), vec![site_url("/about")]);
+}
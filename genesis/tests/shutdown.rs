@@ -0,0 +1,98 @@
+mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{fixture, html, site_url, CollectingSink, MockSite};
+use futures::StreamExt;
+use genesis::Crawler;
+
+#[tokio::test]
+async fn shutdown_flushes_pending_analyses_and_ends_the_stream() {
+    let site = MockSite::start().await;
+    site.serve("/", html(fixture("index.html"))).await;
+    let mut config = site.config();
+    let visited_file = std::env::temp_dir().join(format!("visited-{}.txt", std::process::id()));
+    config.crawl.visited_file = Some(visited_file.clone());
+    config.sink.archive_raw_html = true;
+
+    let sink = Arc::new(CollectingSink::default());
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let mut analyses = Crawler::builder()
+        .config(config)
+        .seeds([site_url("/")])
+        .sink(sink.clone())
+        .shutdown(shutdown.clone())
+        .build()
+        .crawl();
+
+    let first = analyses.next().await.expect("the seed should be crawled");
+    shutdown.store(true, Ordering::Relaxed);
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while analyses.next().await.is_some() {}
+    })
+    .await
+    .expect("the stream should end after shutdown");
+
+    assert!(sink.saved.lock().unwrap().contains(&first.url));
+    // the raw body goes to the sink only, the streamed page does not carry a copy
+    assert!(sink.archived.lock().unwrap().contains(&first.url));
+    assert_eq!(first.raw_html, None);
+    let visited = std::fs::read_to_string(&visited_file).unwrap();
+    assert!(visited.lines().any(|url| url == site_url("/")));
+    let _ = std::fs::remove_file(visited_file);
+}
+
+#[tokio::test]
+async fn urls_visited_by_a_previous_run_are_not_crawled_again() {
+    let site = MockSite::start().await;
+    site.serve(
+        "/",
+        html(br#"<a href="/old">Old</a><a href="/new">New</a>"#.to_vec()),
+    )
+    .await;
+    site.serve("/old", html(fixture("index.html"))).await;
+    site.serve("/new", html(fixture("index.html"))).await;
+
+    let visited_file =
+        std::env::temp_dir().join(format!("visited-resume-{}.txt", std::process::id()));
+    std::fs::write(&visited_file, format!("{}\n", site_url("/old"))).unwrap();
+    let mut config = site.config();
+    config.crawl.visited_file = Some(visited_file.clone());
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let crawler = Crawler::builder()
+        .config(config)
+        .seeds([site_url("/")])
+        .shutdown(shutdown.clone())
+        .build();
+    let metrics = crawler.metrics();
+    let mut analyses = crawler.crawl();
+
+    let mut crawled = Vec::new();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while crawled.len() < 2 {
+            crawled.push(
+                analyses
+                    .next()
+                    .await
+                    .expect("both pages should be crawled")
+                    .url,
+            );
+        }
+        shutdown.store(true, Ordering::Relaxed);
+        while let Some(analysis) = analyses.next().await {
+            crawled.push(analysis.url);
+        }
+    })
+    .await
+    .expect("the stream should end after shutdown");
+
+    crawled.sort();
+    assert_eq!(crawled, vec![site_url("/"), site_url("/new")]);
+    assert_eq!(metrics.success.load(Ordering::Relaxed), 2);
+    let visited = std::fs::read_to_string(&visited_file).unwrap();
+    let _ = std::fs::remove_file(&visited_file);
+    assert!(visited.lines().any(|url| url == site_url("/old")));
+}
@@ -0,0 +1,191 @@
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+
+use genesis::db::{
+    from_parquet, partitioned_runs, raw_html_key, to_parquet, url_partition, BatchFormat,
+};
+use genesis::db::{Heading, MetaTag};
+use genesis::sink::{JsonlSink, Sink};
+use genesis::SeoAnalysis;
+
+// 2025-03-04 00:00:00 UTC
+const MARCH_4: i64 = 1741046400;
+const DAY: i64 = 24 * 60 * 60;
+
+fn analysis(url: &str, title: &str, crawled_at: i64) -> SeoAnalysis {
+    serde_json::from_value(serde_json::json!({
+        "url": url,
+        "language": "en",
+        "title": title,
+        "meta_tags": [],
+        "canonical_url": null,
+        "content_text": "Some content",
+        "crawled_at": crawled_at,
+    }))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn compressed_batches_round_trip() {
+    let dir = std::env::temp_dir().join(format!("genesis-jsonl-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let batch = vec![
+        analysis("https://site.test/", "Home", MARCH_4),
+        analysis("https://site.test/about", "About", MARCH_4 + 60),
+    ];
+    JsonlSink::new(&dir)
+        .compressed(true)
+        .write_batch(&batch)
+        .await
+        .unwrap();
+
+    let files = batch_files(&dir);
+    assert_eq!(files.len(), 1);
+    let path = files[0].to_string_lossy().into_owned();
+    assert!(path.contains(&format!(
+        "date=2025-03-04/partition={}/",
+        url_partition("https://site.test/")
+    )));
+    assert!(path.ends_with(".jsonl.zst"));
+
+    let decoder = zstd::Decoder::new(std::fs::File::open(&files[0]).unwrap()).unwrap();
+    let read_back: Vec<SeoAnalysis> = std::io::BufReader::new(decoder)
+        .lines()
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .collect();
+    assert_eq!(read_back.len(), 2);
+    assert_eq!(read_back[0].url, "https://site.test/");
+    assert_eq!(read_back[1].title, "About");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn raw_html_is_archived_next_to_the_batches() {
+    let dir = std::env::temp_dir().join(format!("genesis-raw-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let mut archived = analysis("https://site.test/", "Home", MARCH_4);
+    archived.raw_html = Some(b"<html><title>Home</title></html>".to_vec());
+    let batch = vec![
+        archived,
+        analysis("https://site.test/about", "About", MARCH_4),
+    ];
+    JsonlSink::new(&dir).write_batch(&batch).await.unwrap();
+
+    let key = raw_html_key(&batch[0]);
+    assert!(key.starts_with(&format!(
+        "raw/date=2025-03-04/partition={}/",
+        url_partition("https://site.test/")
+    )));
+    let raw = zstd::decode_all(std::fs::File::open(dir.join(&key)).unwrap()).unwrap();
+    assert_eq!(raw, b"<html><title>Home</title></html>");
+
+    let files = batch_files(&dir);
+    assert_eq!(
+        files.len(),
+        2,
+        "one batch and one archived page: {:?}",
+        files
+    );
+    let batch_file = files.iter().find(|path| !path.starts_with(dir.join("raw")));
+    let records = std::fs::read_to_string(batch_file.unwrap()).unwrap();
+    assert!(!records.contains("raw_html"));
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn parquet_batches_round_trip() {
+    let mut full = analysis("https://site.test/", "Home", MARCH_4);
+    full.meta_tags = vec![MetaTag {
+        name: "description".to_string(),
+        content: "The home page".to_string(),
+    }];
+    full.headings = vec![Heading {
+        level: 1,
+        text: "Welcome".to_string(),
+    }];
+    full.structured_data = vec![serde_json::json!({"@type": "WebSite", "name": "Site"})];
+    full.alternates = vec![("fr".to_string(), "https://site.test/fr/".to_string())];
+    full.robots.nofollow = true;
+    full.canonical_url = Some("https://site.test/".to_string());
+    full.language_confidence = Some(0.5);
+    full.content_hash = u64::MAX;
+    let batch = vec![full, analysis("https://site.test/about", "About", MARCH_4)];
+
+    for compress in [false, true] {
+        let body = to_parquet(&batch, compress).unwrap();
+        let read_back = from_parquet(body.into()).unwrap();
+        assert_eq!(read_back.len(), 2);
+        assert_eq!(
+            serde_json::to_value(&read_back[0]).unwrap(),
+            serde_json::to_value(&batch[0]).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_value(&read_back[1]).unwrap(),
+            serde_json::to_value(&batch[1]).unwrap()
+        );
+    }
+}
+
+#[tokio::test]
+async fn the_jsonl_sink_can_write_parquet() {
+    let dir = std::env::temp_dir().join(format!("genesis-parquet-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let batch = vec![analysis("https://site.test/", "Home", MARCH_4)];
+    JsonlSink::new(&dir)
+        .compressed(true)
+        .format(BatchFormat::Parquet)
+        .write_batch(&batch)
+        .await
+        .unwrap();
+
+    let files = batch_files(&dir);
+    assert_eq!(files.len(), 1);
+    assert!(files[0].to_string_lossy().ends_with(".parquet"));
+    let read_back = from_parquet(std::fs::read(&files[0]).unwrap().into()).unwrap();
+    assert_eq!(read_back[0].title, "Home");
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn batches_are_split_by_crawl_date() {
+    let batch = vec![
+        analysis("https://site.test/a", "A", MARCH_4 - 1),
+        analysis("https://site.test/b", "B", MARCH_4),
+        analysis("https://site.test/c", "C", MARCH_4 + DAY - 1),
+        analysis("https://site.test/d", "D", MARCH_4 + DAY),
+    ];
+
+    let runs = partitioned_runs(&batch);
+    let dates: Vec<(&str, usize)> = runs
+        .iter()
+        .map(|(prefix, run)| (prefix.split('/').next().unwrap(), run.len()))
+        .collect();
+    assert_eq!(
+        dates,
+        vec![
+            ("date=2025-03-03", 1),
+            ("date=2025-03-04", 2),
+            ("date=2025-03-05", 1)
+        ]
+    );
+}
+
+// every file below `dir`, at any depth
+fn batch_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(batch_files(&path));
+        } else {
+            files.push(path);
+        }
+    }
+    files
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use common::{gzip, MockSite};
+use genesis::sitemap::{fetch_sitemap, parse_sitemap, Sitemap};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const INDEX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <sitemap><loc>https://site.test/posts.xml.gz</loc></sitemap>
+</sitemapindex>"#;
+
+const URLSET: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url><loc>https://site.test/a?x=1&amp;y=2</loc></url>
+  <url><loc><![CDATA[https://site.test/b]]></loc></url>
+</urlset>"#;
+
+#[test]
+fn parse_sitemap_reads_plain_and_gzipped_documents() {
+    assert_eq!(
+        parse_sitemap(INDEX.as_bytes()),
+        Some(Sitemap::Index(
+            vec!["https://site.test/posts.xml.gz".into()]
+        ))
+    );
+    assert_eq!(
+        parse_sitemap(&gzip(URLSET.as_bytes())),
+        Some(Sitemap::UrlSet(vec![
+            "https://site.test/a?x=1&y=2".into(),
+            "https://site.test/b".into(),
+        ]))
+    );
+    assert_eq!(parse_sitemap(b"<html></html>"), None);
+}
+
+#[tokio::test]
+async fn fetch_sitemap_follows_nested_sitemaps() {
+    let site = MockSite::start().await;
+    for (page, body) in [
+        ("/sitemap_index.xml", INDEX.as_bytes().to_vec()),
+        ("/posts.xml.gz", gzip(URLSET.as_bytes())),
+    ] {
+        Mock::given(method("GET"))
+            .and(path(format!("/https:/site.test{}", page)))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(body))
+            .mount(&site.server)
+            .await;
+    }
+    let config = site.config();
+
+    let urls = fetch_sitemap("site.test", &site.proxy_manager(&config)).await;
+
+    assert_eq!(
+        urls,
+        vec!["https://site.test/a?x=1&y=2", "https://site.test/b"]
+    );
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use std::sync::atomic::Ordering;
+
+use common::{fixture, html, metrics, site_url, MockSite};
+use genesis::config::Soft404Config;
+use genesis::crawler::process_page;
+use genesis::soft404::{is_soft_404, SoftNotFound};
+use genesis::CrawlError;
+
+#[tokio::test]
+async fn soft_404_pages_are_not_analysed() {
+    let site = MockSite::start().await;
+    site.serve("/old-post", html(fixture("soft_404.html")))
+        .await;
+    site.serve("/", html(fixture("index.html"))).await;
+    let config = site.config();
+    let metrics = metrics();
+
+    let err = process_page(
+        &site_url("/old-post"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics,
+    )
+    .await
+    .expect_err("soft 404 should not be analysed");
+    assert!(matches!(err, CrawlError::SoftNotFound(SoftNotFound)));
+    assert_eq!(metrics.soft_404s.load(Ordering::Relaxed), 1);
+
+    process_page(
+        &site_url("/"),
+        &config,
+        &site.proxy_manager(&config),
+        None,
+        None,
+        &metrics,
+    )
+    .await
+    .expect("a regular page is still analysed");
+    assert_eq!(metrics.soft_404s.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn patterns_only_count_on_short_pages() {
+    let config = Soft404Config::default();
+
+    assert!(is_soft_404("404 - Site Test", "", &config));
+    assert!(is_soft_404(
+        "Site Test",
+        "Error 404: Page Not Found",
+        &config
+    ));
+
+    let article = format!(
+        "How we fixed our page not found errors. {}",
+        "Broken links hurt crawlers and readers alike. ".repeat(40)
+    );
+    assert!(!is_soft_404("Fixing broken links", &article, &config));
+
+    let review = format!(
+        "The Peugeot 404 was built from 1960 to 1975. {}",
+        "Its Pininfarina body aged remarkably well. ".repeat(40)
+    );
+    assert!(!is_soft_404("Peugeot 404 review", &review, &config));
+    assert!(!is_soft_404("Lost and Not Found", &article, &config));
+
+    let config = Soft404Config {
+        title_patterns: vec!["oops".to_string()],
+        content_patterns: Vec::new(),
+        ..config
+    };
+    assert!(is_soft_404("Oops! | Site Test", "", &config));
+    assert!(!is_soft_404("Site Test", "Page not found", &config));
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use common::{fixture, html, site_url, CollectingSink, MockSite};
+use futures::StreamExt;
+use genesis::Crawler;
+
+#[tokio::test]
+async fn thin_pages_are_followed_but_not_saved() {
+    let site = MockSite::start().await;
+    site.serve(
+        "/",
+        html(
+            b"<html><body><p>Just three words</p><a href=\"/post\">Post</a></body></html>".to_vec(),
+        ),
+    )
+    .await;
+    site.serve("/post", html(fixture("blog_post.html"))).await;
+    let mut config = site.config();
+    config.crawl.visited_file = None;
+    config.crawl.min_content_words = 20;
+
+    let sink = Arc::new(CollectingSink::default());
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let crawler = Crawler::builder()
+        .config(config)
+        .seeds([site_url("/")])
+        .sink(sink.clone())
+        .shutdown(shutdown.clone())
+        .build();
+    let metrics = crawler.metrics();
+    let mut analyses = crawler.crawl();
+
+    let mut crawled = Vec::new();
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while let Some(analysis) = analyses.next().await {
+            crawled.push(analysis.url);
+            shutdown.store(true, Ordering::Relaxed);
+        }
+    })
+    .await
+    .expect("the linked page should be crawled");
+
+    assert_eq!(crawled, vec![site_url("/post")]);
+    assert_eq!(metrics.success.load(Ordering::Relaxed), 2);
+    assert_eq!(metrics.thin_pages.load(Ordering::Relaxed), 1);
+    assert_eq!(*sink.saved.lock().unwrap(), vec![site_url("/post")]);
+}
@@ -1,36 +1,205 @@
-use anyhow::Result;
-use std::{
-    io::{self, Write},
-    path::PathBuf,
-};
+use anyhow::{bail, Result};
+use pulse::indexer::latest_index_path;
+use serde::Serialize;
+use std::io::{self, Write};
 use tantivy::{
     collector::TopDocs,
     query::QueryParser,
-    schema::{OwnedValue, Schema, Value},
-    Index, TantivyDocument,
+    schema::{Schema, Value},
+    snippet::SnippetGenerator,
+    Index, Searcher, TantivyDocument,
 };
 use tracing::info;
 
 const MAX_RESULTS: usize = 10;
+const PREVIEW_CHARS: usize = 200;
+// keep in step with the boosts in server.rs
+const TITLE_BOOST: f32 = 3.0;
+const HEADINGS_BOOST: f32 = 2.0;
+const CONTENT_BOOST: f32 = 1.0;
+const META_BOOST: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    /// A JSON array of the results, shaped like the server's `/search` results
+    Json,
+}
+
+/// `search [--format text|json] [query...]`, interactive when no query is given.
+struct Args {
+    format: OutputFormat,
+    query: Option<String>,
+}
+
+impl Args {
+    fn parse() -> Result<Self> {
+        let mut format = OutputFormat::Text;
+        let mut words = Vec::new();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = match arg.strip_prefix("--format") {
+                Some("") => args.next().unwrap_or_default(),
+                Some(rest) if rest.starts_with('=') => rest[1..].to_string(),
+                _ => {
+                    words.push(arg);
+                    continue;
+                }
+            };
+            format = match value.as_str() {
+                "text" => OutputFormat::Text,
+                "json" => OutputFormat::Json,
+                other => bail!("Unknown format {:?}, expected text or json", other),
+            };
+        }
+
+        let query = words.join(" ");
+        Ok(Args {
+            format,
+            query: (!query.trim().is_empty()).then_some(query),
+        })
+    }
+}
+
+/// Same fields as the server's search results.
+#[derive(Debug, Serialize)]
+struct SearchResult {
+    score: f32,
+    title: String,
+    url: String,
+    preview: String,
+    language: String,
+    meta_description: String,
+    nsfw: bool,
+    word_count: u64,
+    reading_time_minutes: u64,
+    crawled_at: u64,
+    favicon_url: Option<String>,
+    image_url: Option<String>,
+}
+
+fn truncate_chars(text: &str, max_chars: usize) -> String {
+    let text = text.trim();
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
+}
 
-fn get_latest_index() -> Result<PathBuf> {
-    let index_dir = PathBuf::from("pulse_indexes");
+fn run_query(
+    searcher: &Searcher,
+    query_parser: &QueryParser,
+    schema: &Schema,
+    query_str: &str,
+) -> Result<Vec<SearchResult>> {
+    let query = query_parser.parse_query(query_str)?;
+    let top_docs = searcher.search(&query, &TopDocs::with_limit(MAX_RESULTS))?;
 
-    let latest = std::fs::read_dir(&index_dir)?
-        .filter_map(Result::ok)
-        .filter(|entry| entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false))
-        .max_by_key(|entry| entry.path());
+    let snippet_generator =
+        SnippetGenerator::create(searcher, &*query, schema.get_field("content").unwrap())
+            .map(|mut generator| {
+                generator.set_max_num_chars(PREVIEW_CHARS);
+                generator
+            })
+            .ok();
 
-    latest
-        .map(|e| e.path())
-        .ok_or_else(|| anyhow::anyhow!("No index found in {}", index_dir.display()))
+    let mut results = Vec::with_capacity(top_docs.len());
+    for (score, doc_address) in top_docs {
+        let doc: TantivyDocument = searcher.doc(doc_address)?;
+        // older indexes lack some of the fields
+        let text = |name: &str| {
+            schema
+                .get_field(name)
+                .ok()
+                .and_then(|field| doc.get_first(field))
+                .and_then(|v| v.as_str())
+                .map(str::to_string)
+        };
+        let number = |name: &str| {
+            schema
+                .get_field(name)
+                .ok()
+                .and_then(|field| doc.get_first(field))
+                .and_then(|v| v.as_u64())
+                .unwrap_or_default()
+        };
+
+        let meta_description = text("meta_tags").unwrap_or_default();
+        let snippet = snippet_generator
+            .as_ref()
+            .map(|generator| {
+                let mut snippet = generator.snippet_from_doc(&doc);
+                snippet.set_snippet_prefix_postfix("<mark>", "</mark>");
+                snippet
+            })
+            .filter(|snippet| !snippet.is_empty())
+            .map(|snippet| snippet.to_html());
+        let preview = snippet.unwrap_or_else(|| {
+            let fallback = if meta_description.trim().is_empty() {
+                text("preview").unwrap_or_default()
+            } else {
+                meta_description.clone()
+            };
+            truncate_chars(&fallback, PREVIEW_CHARS)
+        });
+
+        results.push(SearchResult {
+            score,
+            title: text("title").unwrap_or_default(),
+            url: text("url").unwrap_or_default(),
+            preview,
+            language: text("language").unwrap_or_else(|| "en".to_string()),
+            meta_description,
+            nsfw: schema
+                .get_field("nsfw")
+                .ok()
+                .and_then(|field| doc.get_first(field))
+                .and_then(|v| v.as_bool())
+                .unwrap_or_default(),
+            word_count: number("word_count"),
+            reading_time_minutes: number("reading_time_minutes"),
+            crawled_at: number("crawled_at"),
+            favicon_url: text("favicon_url"),
+            image_url: text("image_url"),
+        });
+    }
+    Ok(results)
+}
+
+fn print_results(format: OutputFormat, query_str: &str, results: &[SearchResult]) -> Result<()> {
+    match format {
+        OutputFormat::Json => println!("{}", serde_json::to_string(results)?),
+        OutputFormat::Text => {
+            println!("\nSearch results for: {}", query_str);
+            println!("{}", "─".repeat(50));
+
+            for result in results {
+                println!(
+                    "Score: {:.2}\nTitle: {}\nURL: {}\nDescription: {}\nNSFW: {}\n{}",
+                    result.score,
+                    result.title,
+                    result.url,
+                    result.meta_description,
+                    result.nsfw,
+                    "─".repeat(50)
+                );
+            }
+        }
+    }
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    tracing_subscriber::fmt().with_env_filter("info").init();
+    let args = Args::parse()?;
+
+    // logs go to stderr so `--format json` output can be piped
+    tracing_subscriber::fmt()
+        .with_env_filter("info")
+        .with_writer(io::stderr)
+        .init();
 
-    let index_path = get_latest_index()?;
+    let index_path = latest_index_path()?;
     info!("Using index at: {}", index_path.display());
 
     let index = Index::open_in_dir(&index_path)?;
@@ -39,11 +208,29 @@ async fn main() -> Result<()> {
 
     let schema: Schema = index.schema();
     let title_field = schema.get_field("title").unwrap();
-    let url_field = schema.get_field("url").unwrap();
     let content_field = schema.get_field("content").unwrap();
     let meta_field = schema.get_field("meta_tags").unwrap();
 
-    let query_parser = QueryParser::for_index(&index, vec![title_field, content_field, meta_field]);
+    let headings_field = schema.get_field("headings").ok();
+
+    let mut query_parser = QueryParser::for_index(
+        &index,
+        [title_field, content_field, meta_field]
+            .into_iter()
+            .chain(headings_field)
+            .collect(),
+    );
+    query_parser.set_field_boost(title_field, TITLE_BOOST);
+    query_parser.set_field_boost(content_field, CONTENT_BOOST);
+    query_parser.set_field_boost(meta_field, META_BOOST);
+    if let Some(headings_field) = headings_field {
+        query_parser.set_field_boost(headings_field, HEADINGS_BOOST);
+    }
+
+    if let Some(query_str) = &args.query {
+        let results = run_query(&searcher, &query_parser, &schema, query_str)?;
+        return print_results(args.format, query_str, &results);
+    }
 
     loop {
         print!("\nEnter search query (or 'quit' to exit): ");
@@ -61,52 +248,8 @@ async fn main() -> Result<()> {
             continue;
         }
 
-        let query = query_parser.parse_query(query_str)?;
-        let top_docs = searcher.search(&query, &TopDocs::with_limit(MAX_RESULTS))?;
-
-        println!("\nSearch results for: {}", query_str);
-        println!("{}", "─".repeat(50));
-
-        for (score, doc_address) in top_docs {
-            let retrieved_doc: TantivyDocument = searcher.doc(doc_address)?;
-            let owned_doc = retrieved_doc.to_owned();
-
-            // Use map_or as suggested by the compiler
-            let title_str = owned_doc
-                .get_first(title_field)
-                .and_then(|owned_val| match owned_val {
-                    OwnedValue::Str(s) => Some(s),
-                    _ => None,
-                })
-                .map_or("", |s| &s);
-
-            let url_str = owned_doc
-                .get_first(url_field)
-                .and_then(|owned_val| match owned_val {
-                    OwnedValue::Str(s) => Some(s),
-                    _ => None,
-                })
-                .map_or("", |s| &s);
-
-            println!(
-                "Score: {:.2}\nTitle: {}\nURL: {}\nDescription: {}\nNSFW: {}\n{}",
-                score,
-                title_str,
-                url_str,
-                owned_doc
-                    .get_first(meta_field)
-                    .and_then(|v| match v {
-                        OwnedValue::Str(s) => Some(s),
-                        _ => None,
-                    })
-                    .map_or("", |s| &s),
-                owned_doc
-                    .get_first(schema.get_field("nsfw").unwrap())
-                    .and_then(|v| v.as_bool())
-                    .unwrap_or_default(),
-                "─".repeat(50)
-            );
-        }
+        let results = run_query(&searcher, &query_parser, &schema, query_str)?;
+        print_results(args.format, query_str, &results)?;
     }
 
     Ok(())
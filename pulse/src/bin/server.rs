@@ -1,22 +1,81 @@
 use anyhow::Result;
 use axum::{extract::Query, http::StatusCode, routing::get, Json, Router};
+use levenshtein_automata::{Distance, LevenshteinAutomatonBuilder, DFA};
+use pulse::indexer::latest_index_path;
 use serde::{Deserialize, Serialize};
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+    time::Duration,
+};
 use tantivy::{
-    collector::TopDocs,
-    query::QueryParser,
-    schema::{OwnedValue, Schema, Value},
-    Index, IndexReader, TantivyDocument,
+    aggregation::{
+        agg_req::Aggregations,
+        agg_result::{AggregationResult, BucketResult},
+        AggregationCollector, Key,
+    },
+    collector::{Collector, Count, SegmentCollector, TopDocs},
+    query::{AllQuery, BooleanQuery, Occur, QueryParser, TermQuery},
+    schema::{Field, IndexRecordOption, OwnedValue, Schema, Value},
+    snippet::SnippetGenerator,
+    DocAddress, DocId, Index, IndexReader, Order, Score, Searcher, SegmentOrdinal, SegmentReader,
+    TantivyDocument, Term,
 };
+use tantivy_fst::Automaton;
 use tower_http::cors::CorsLayer;
 use tracing::info;
 
-const MAX_RESULTS: usize = 10;
+const DEFAULT_PER_PAGE: usize = 10;
+const MAX_PER_PAGE: usize = 50;
+const PREVIEW_CHARS: usize = 200;
+/// BM25 score multipliers per field, so a query matching a page's title outranks pages
+/// that only mention it in passing
+const TITLE_BOOST: f32 = 3.0;
+const HEADINGS_BOOST: f32 = 2.0;
+const CONTENT_BOOST: f32 = 1.0;
+const META_BOOST: f32 = 1.0;
+/// Domains listed with their hit counts in a search response
+const TOP_DOMAINS: usize = 10;
+/// Upper bound on the languages `/languages` lists, far more than an index holds
+const MAX_LANGUAGES: usize = 1000;
+/// Results kept per host with `group_by_domain`, unless `per_domain` says otherwise
+const DEFAULT_PER_DOMAIN: usize = 2;
+/// Best hits grouped with `group_by_domain`, lower ranked ones are never returned
+const MAX_GROUP_CANDIDATES: usize = 1000;
+/// Queries with fewer hits than this get a "did you mean" suggestion
+const SUGGEST_BELOW_TOTAL: usize = 5;
+/// Shorter tokens have too many neighbours within the edit distance to correct reliably
+const MIN_SUGGEST_TOKEN_CHARS: usize = 3;
+const MAX_SUGGEST_DISTANCE: u8 = 2;
+/// Unknown words looked up per query, the rest are left as typed
+const MAX_SUGGEST_WORDS: usize = 4;
+/// Dictionary terms considered per word, so a common prefix cannot stall a search
+const MAX_SUGGEST_CANDIDATES: usize = 1000;
+/// Lookups slower than this are dropped rather than holding up the results
+const LEXICON_TIMEOUT: Duration = Duration::from_millis(300);
 
 #[derive(Debug, Deserialize)]
 struct SearchParams {
     q: String,
     lang: Option<String>,
+    /// Zero-based page number
+    #[serde(default)]
+    page: usize,
+    per_page: Option<usize>,
+    /// Excludes documents flagged as NSFW, on unless `safe=false` is passed
+    safe: Option<bool>,
+    /// `relevance` (default) or `recent` for the most recently crawled pages first
+    sort: Option<String>,
+    /// Only results from this host, e.g. `site=example.com`
+    site: Option<String>,
+    /// Keeps at most `per_domain` results from any one host, backfilling from lower ranked hits
+    #[serde(default)]
+    group_by_domain: bool,
+    per_domain: Option<usize>,
+    /// Drops hits scoring below this, 0 (the default) keeps every match
+    #[serde(default)]
+    min_score: f32,
 }
 
 #[derive(Debug, Serialize)]
@@ -28,60 +87,296 @@ struct SearchResult {
     language: String,
     meta_description: String,
     nsfw: bool,
+    word_count: u64,
+    reading_time_minutes: u64,
+    crawled_at: u64,
+    favicon_url: Option<String>,
+    /// Thumbnail for the result card
+    image_url: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 struct SearchResponse {
     results: Vec<SearchResult>,
     query: String,
+    /// Matching documents. With `group_by_domain`, the results left after capping hosts
+    /// among the best [`MAX_GROUP_CANDIDATES`] hits, so every counted result can be paged to
     total: usize,
+    page: usize,
+    per_page: usize,
+    /// Hosts with the most hits, busiest first
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    domains: Vec<DomainCount>,
+    /// Corrected query, when this one found little
+    #[serde(skip_serializing_if = "Option::is_none")]
+    suggestion: Option<String>,
+    /// Dictionary entry for single word queries, when `LEXICON_URL` is set and the word is known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    definition: Option<WordEntry>,
+}
+
+#[derive(Debug, Serialize)]
+struct DomainCount {
+    domain: String,
+    count: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct LanguagesResponse {
+    languages: Vec<LanguageCount>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+struct LanguageCount {
+    language: String,
+    count: u64,
+}
+
+/// An entry of the lexicon service's `/lookup/{word}` response, see `lexicon/README.md`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+struct WordEntry {
+    id: String,
+    word: String,
+    part_of_speech: String,
+    pronunciations: Vec<String>,
+    definitions: Vec<Definition>,
+    examples: Vec<String>,
+    synonyms: Vec<String>,
+    antonyms: Vec<String>,
+    similar_words: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct Definition {
+    pos: String,
+    gloss: String,
+    source: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct LookupMatch {
+    entry: WordEntry,
+}
+
+struct Lexicon {
+    client: reqwest::Client,
+    base_url: String,
+}
+
+impl Lexicon {
+    fn from_env() -> Option<Self> {
+        let base_url = std::env::var("LEXICON_URL").ok()?;
+        let client = reqwest::Client::builder()
+            .timeout(LEXICON_TIMEOUT)
+            .build()
+            .ok()?;
+        Some(Lexicon {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// The entry for exactly `word`, ignoring fuzzy matches and any lookup failure.
+    async fn define(&self, word: &str) -> Option<WordEntry> {
+        let url = format!("{}/lookup/{}", self.base_url, word);
+        let response = self.client.get(url).send().await.ok()?;
+        let matches: Vec<LookupMatch> = response.error_for_status().ok()?.json().await.ok()?;
+        matches
+            .into_iter()
+            .map(|lookup| lookup.entry)
+            .find(|entry| entry.word.eq_ignore_ascii_case(word))
+    }
+}
+
+/// Queries made of one word, the only ones that get a dictionary card.
+fn dictionary_word(query: &str) -> Option<&str> {
+    let word = query.trim();
+    let is_word = word.chars().next().is_some_and(char::is_alphabetic)
+        && word
+            .chars()
+            .all(|c| c.is_alphabetic() || c == '-' || c == '\'');
+    is_word.then_some(word)
 }
 
 struct SearchState {
+    index_path: PathBuf,
     reader: IndexReader,
     query_parser: QueryParser,
     schema: Arc<Schema>,
 }
 
-fn get_latest_index() -> Result<PathBuf> {
-    let index_dir = PathBuf::from("pulse_indexes");
+/// The server starts listening before the index is opened, `search` is set once it is.
+#[derive(Default)]
+struct AppState {
+    search: OnceLock<SearchState>,
+    lexicon: Option<Arc<Lexicon>>,
+}
 
-    let latest = std::fs::read_dir(&index_dir)?
-        .filter_map(Result::ok)
-        .filter(|entry| entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false))
-        .max_by_key(|entry| entry.path());
+#[derive(Debug, Serialize)]
+struct HealthResponse {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    index_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_docs: Option<u64>,
+}
 
-    latest
-        .map(|e| e.path())
-        .ok_or_else(|| anyhow::anyhow!("No index found in {}", index_dir.display()))
+fn truncate_chars(text: &str, max_chars: usize) -> String {
+    let text = text.trim();
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => format!("{}...", &text[..end]),
+        None => text.to_string(),
+    }
 }
 
 async fn search_handler(
-    state: axum::extract::State<Arc<SearchState>>,
+    state: axum::extract::State<Arc<AppState>>,
     Query(params): Query<SearchParams>,
 ) -> Result<Json<SearchResponse>, (StatusCode, String)> {
-    let searcher = state.reader.searcher();
+    let Some(search) = state.search.get() else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The index is still loading".to_string(),
+        ));
+    };
+    let searcher = search.reader.searcher();
+
+    // looked up while the index is searched
+    let definition = match (&state.lexicon, dictionary_word(&params.q)) {
+        (Some(lexicon), Some(word)) if params.page == 0 => {
+            let lexicon = lexicon.clone();
+            let word = word.to_lowercase();
+            Some(tokio::spawn(async move { lexicon.define(&word).await }))
+        }
+        _ => None,
+    };
 
     let mut query_str = params.q.clone();
     if let Some(lang) = params.lang {
         query_str = format!("({}) AND language:{}", query_str, lang);
     }
 
-    let query = state
+    let query = search
         .query_parser
         .parse_query(&query_str)
         .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
 
-    let top_docs = searcher
-        .search(&query, &TopDocs::with_limit(MAX_RESULTS))
-        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let nsfw_field = search.schema.get_field("nsfw").unwrap();
+    let query: Box<dyn tantivy::query::Query> = if params.safe.unwrap_or(true) {
+        let nsfw = TermQuery::new(
+            Term::from_field_bool(nsfw_field, true),
+            IndexRecordOption::Basic,
+        );
+        Box::new(BooleanQuery::new(vec![
+            (Occur::Must, query),
+            (Occur::MustNot, Box::new(nsfw)),
+        ]))
+    } else {
+        query
+    };
+
+    let domain_field = search.schema.get_field("domain").ok();
+    let query: Box<dyn tantivy::query::Query> = match (&params.site, domain_field) {
+        (None, _) => query,
+        (Some(site), Some(domain_field)) => {
+            let site = TermQuery::new(
+                Term::from_field_text(domain_field, &site_domain(site)),
+                IndexRecordOption::Basic,
+            );
+            Box::new(BooleanQuery::new(vec![
+                (Occur::Must, query),
+                (Occur::Must, Box::new(site)),
+            ]))
+        }
+        (Some(_), None) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "site is not supported by this index".to_string(),
+            ));
+        }
+    };
+
+    let per_page = params
+        .per_page
+        .unwrap_or(DEFAULT_PER_PAGE)
+        .clamp(1, MAX_PER_PAGE);
+    let offset = params.page.saturating_mul(per_page);
+    let per_domain = params
+        .group_by_domain
+        .then(|| params.per_domain.unwrap_or(DEFAULT_PER_DOMAIN).max(1));
+
+    // grouping needs every hit before the requested page to know which hosts are capped,
+    // and every candidate to count the results
+    let top_docs = match per_domain {
+        Some(_) => TopDocs::with_limit(MAX_GROUP_CANDIDATES),
+        None => TopDocs::with_limit(per_page).and_offset(offset),
+    };
+    let min_score = (params.min_score > 0.0).then_some(params.min_score);
+    let (top_docs, total): (Vec<(f32, DocAddress)>, usize) = match params.sort.as_deref() {
+        None | Some("relevance") => relevant_hits(&searcher, &*query, top_docs, min_score),
+        Some("recent") => {
+            if min_score.is_some() {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "min_score needs sort=relevance".to_string(),
+                ));
+            }
+            if search.schema.get_field("crawled_at").is_err() {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    "sort=recent is not supported by this index".to_string(),
+                ));
+            }
+            // recency ordered results carry no relevance score
+            searcher
+                .search(
+                    &query,
+                    &(
+                        top_docs.order_by_fast_field::<u64>("crawled_at", Order::Desc),
+                        Count,
+                    ),
+                )
+                .map(|(docs, total)| {
+                    let docs = docs.into_iter().map(|(_, doc)| (0.0, doc)).collect();
+                    (docs, total)
+                })
+        }
+        Some(other) => {
+            return Err((StatusCode::BAD_REQUEST, format!("Unknown sort: {}", other)));
+        }
+    }
+    .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
 
-    let title_field = state.schema.get_field("title").unwrap();
-    let url_field = state.schema.get_field("url").unwrap();
-    let preview_field = state.schema.get_field("preview").unwrap();
-    let language_field = state.schema.get_field("language").unwrap();
-    let meta_field = state.schema.get_field("meta_tags").unwrap();
-    let nsfw_field = state.schema.get_field("nsfw").unwrap();
+    let title_field = search.schema.get_field("title").unwrap();
+    let url_field = search.schema.get_field("url").unwrap();
+
+    let (top_docs, total) = match per_domain {
+        Some(per_domain) => {
+            let grouped = cap_per_domain(&searcher, url_field, top_docs, per_domain);
+            let total = grouped.len();
+            let page = grouped.into_iter().skip(offset).take(per_page).collect();
+            (page, total)
+        }
+        None => (top_docs, total),
+    };
+    let preview_field = search.schema.get_field("preview").unwrap();
+    let language_field = search.schema.get_field("language").unwrap();
+    let meta_field = search.schema.get_field("meta_tags").unwrap();
+    let word_count_field = search.schema.get_field("word_count").ok();
+    let reading_time_field = search.schema.get_field("reading_time_minutes").ok();
+    let crawled_at_field = search.schema.get_field("crawled_at").ok();
+    let favicon_field = search.schema.get_field("favicon_url").ok();
+    let image_field = search.schema.get_field("image_url").ok();
+
+    let content_field = search.schema.get_field("content").unwrap();
+    let snippet_generator = SnippetGenerator::create(&searcher, &*query, content_field)
+        .map(|mut generator| {
+            generator.set_max_num_chars(PREVIEW_CHARS);
+            generator
+        })
+        .ok();
 
     let results: Vec<SearchResult> = top_docs
         .iter()
@@ -90,7 +385,41 @@ async fn search_handler(
                 .doc(*doc_address)
                 .ok()
                 .map(|retrieved_doc: TantivyDocument| {
+                    // content is only stored by newer indexes, so the snippet may be empty
+                    let snippet = snippet_generator
+                        .as_ref()
+                        .map(|generator| {
+                            let mut snippet = generator.snippet_from_doc(&retrieved_doc);
+                            snippet.set_snippet_prefix_postfix("<mark>", "</mark>");
+                            snippet
+                        })
+                        .filter(|snippet| !snippet.is_empty())
+                        .map(|snippet| snippet.to_html());
+
                     let doc = retrieved_doc.to_owned();
+                    let meta_description = doc
+                        .get_first(meta_field)
+                        .and_then(|v| match v {
+                            OwnedValue::Str(s) => Some(s.clone()),
+                            _ => None,
+                        })
+                        .unwrap_or_default();
+                    let stored_preview = doc
+                        .get_first(preview_field)
+                        .and_then(|v| match v {
+                            OwnedValue::Str(s) => Some(s.clone()),
+                            _ => None,
+                        })
+                        .unwrap_or_default();
+                    let preview = snippet.unwrap_or_else(|| {
+                        let fallback = if meta_description.trim().is_empty() {
+                            &stored_preview
+                        } else {
+                            &meta_description
+                        };
+                        truncate_chars(fallback, PREVIEW_CHARS)
+                    });
+
                     SearchResult {
                         score: *score,
                         title: doc
@@ -107,13 +436,7 @@ async fn search_handler(
                                 _ => None,
                             })
                             .unwrap_or_default(),
-                        preview: doc
-                            .get_first(preview_field)
-                            .and_then(|v| match v {
-                                OwnedValue::Str(s) => Some(s.clone()),
-                                _ => None,
-                            })
-                            .unwrap_or_default(),
+                        preview,
                         language: doc
                             .get_first(language_field)
                             .and_then(|v| match v {
@@ -121,39 +444,391 @@ async fn search_handler(
                                 _ => None,
                             })
                             .unwrap_or_else(|| "en".to_string()),
-                        meta_description: doc
-                            .get_first(meta_field)
-                            .and_then(|v| match v {
-                                OwnedValue::Str(s) => Some(s.clone()),
-                                _ => None,
-                            })
-                            .unwrap_or_default(),
+                        meta_description,
                         nsfw: doc
                             .get_first(nsfw_field)
                             .and_then(|v| v.as_bool())
                             .unwrap_or_default(),
+                        // older indexes predate these fields
+                        word_count: word_count_field
+                            .and_then(|field| doc.get_first(field))
+                            .and_then(|v| v.as_u64())
+                            .unwrap_or_default(),
+                        reading_time_minutes: reading_time_field
+                            .and_then(|field| doc.get_first(field))
+                            .and_then(|v| v.as_u64())
+                            .unwrap_or_default(),
+                        crawled_at: crawled_at_field
+                            .and_then(|field| doc.get_first(field))
+                            .and_then(|v| v.as_u64())
+                            .unwrap_or_default(),
+                        favicon_url: favicon_field
+                            .and_then(|field| doc.get_first(field))
+                            .and_then(|v| v.as_str())
+                            .map(str::to_string),
+                        image_url: image_field
+                            .and_then(|field| doc.get_first(field))
+                            .and_then(|v| v.as_str())
+                            .map(str::to_string),
                     }
                 })
         })
         .collect();
 
-    let total_results = results.len();
+    let domains = match domain_field {
+        Some(_) => top_domains(&searcher, &*query)
+            .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?,
+        None => Vec::new(),
+    };
+
+    let suggestion = if total < SUGGEST_BELOW_TOTAL {
+        suggest_query(&searcher, search, &params.q)
+    } else {
+        None
+    };
+
+    let definition = match definition {
+        Some(lookup) => lookup.await.ok().flatten(),
+        None => None,
+    };
 
     Ok(Json(SearchResponse {
         results,
         query: params.q,
-        total: total_results,
+        total,
+        page: params.page,
+        per_page,
+        domains,
+        suggestion,
+        definition,
     }))
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt().with_env_filter("info").init();
+/// Hits of `top_docs` scoring at least `min_score`, with the number of such matches.
+/// Hits come best first, so a page past the threshold is simply cut short.
+fn relevant_hits(
+    searcher: &Searcher,
+    query: &dyn tantivy::query::Query,
+    top_docs: TopDocs,
+    min_score: Option<f32>,
+) -> tantivy::Result<(Vec<(f32, DocAddress)>, usize)> {
+    let Some(min_score) = min_score else {
+        return searcher.search(query, &(top_docs, Count));
+    };
+    let (mut hits, total) = searcher.search(query, &(top_docs, MinScoreCount(min_score)))?;
+    hits.retain(|(score, _)| *score >= min_score);
+    Ok((hits, total))
+}
+
+/// Like [`Count`], but only counts matches scoring at least the given score.
+struct MinScoreCount(Score);
+
+impl Collector for MinScoreCount {
+    type Fruit = usize;
+    type Child = MinScoreSegmentCount;
+
+    fn for_segment(
+        &self,
+        _segment_ord: SegmentOrdinal,
+        _segment: &SegmentReader,
+    ) -> tantivy::Result<MinScoreSegmentCount> {
+        Ok(MinScoreSegmentCount {
+            min_score: self.0,
+            count: 0,
+        })
+    }
+
+    fn requires_scoring(&self) -> bool {
+        true
+    }
+
+    fn merge_fruits(&self, counts: Vec<usize>) -> tantivy::Result<usize> {
+        Ok(counts.into_iter().sum())
+    }
+}
+
+struct MinScoreSegmentCount {
+    min_score: Score,
+    count: usize,
+}
+
+impl SegmentCollector for MinScoreSegmentCount {
+    type Fruit = usize;
+
+    fn collect(&mut self, _doc: DocId, score: Score) {
+        if score >= self.min_score {
+            self.count += 1;
+        }
+    }
+
+    fn harvest(self) -> usize {
+        self.count
+    }
+}
+
+/// Normalizes a `site` parameter the way the indexer derives `domain` from URLs.
+fn site_domain(site: &str) -> String {
+    site.trim()
+        .trim_start_matches("http://")
+        .trim_start_matches("https://")
+        .trim_start_matches("www.")
+        .split('/')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase()
+}
+
+/// Drops hits from hosts that already have `per_domain` better ranked hits. Hosts are read
+/// from the `domain` fast field, or from the stored url on indexes that predate it.
+fn cap_per_domain(
+    searcher: &Searcher,
+    url_field: Field,
+    hits: Vec<(f32, DocAddress)>,
+    per_domain: usize,
+) -> Vec<(f32, DocAddress)> {
+    let domain_columns: Vec<_> = searcher
+        .segment_readers()
+        .iter()
+        .map(|segment| segment.fast_fields().str("domain").ok().flatten())
+        .collect();
+    let mut per_host: HashMap<String, usize> = HashMap::new();
+    hits.into_iter()
+        .filter(|(_, doc_address)| {
+            let mut domain = String::new();
+            match &domain_columns[doc_address.segment_ord as usize] {
+                Some(column) => {
+                    if let Some(ord) = column.ords().first(doc_address.doc_id) {
+                        let _ = column.ord_to_str(ord, &mut domain);
+                    }
+                }
+                None => {
+                    let Ok(doc) = searcher.doc::<TantivyDocument>(*doc_address) else {
+                        return false;
+                    };
+                    domain = doc
+                        .get_first(url_field)
+                        .and_then(|v| v.as_str())
+                        .map(site_domain)
+                        .unwrap_or_default();
+                }
+            }
+            let count = per_host.entry(domain).or_default();
+            *count += 1;
+            *count <= per_domain
+        })
+        .collect()
+}
+
+fn top_domains(
+    searcher: &Searcher,
+    query: &dyn tantivy::query::Query,
+) -> tantivy::Result<Vec<DomainCount>> {
+    Ok(term_counts(searcher, query, "domain", TOP_DOMAINS)?
+        .into_iter()
+        .map(|(domain, count)| DomainCount { domain, count })
+        .collect())
+}
+
+/// Every language in the index with its document count, most common first.
+fn language_counts(searcher: &Searcher) -> tantivy::Result<Vec<LanguageCount>> {
+    Ok(term_counts(searcher, &AllQuery, "language", MAX_LANGUAGES)?
+        .into_iter()
+        .map(|(language, count)| LanguageCount { language, count })
+        .collect())
+}
+
+/// The `size` most frequent values of the fast string field `field` among the matches of
+/// `query`, with their document counts.
+fn term_counts(
+    searcher: &Searcher,
+    query: &dyn tantivy::query::Query,
+    field: &str,
+    size: usize,
+) -> tantivy::Result<Vec<(String, u64)>> {
+    let aggregations: Aggregations = serde_json::from_value(serde_json::json!({
+        "terms": { "terms": { "field": field, "size": size } }
+    }))
+    .map_err(|e| tantivy::TantivyError::InvalidArgument(e.to_string()))?;
+    let collector = AggregationCollector::from_aggs(aggregations, Default::default());
+    let results = searcher.search(query, &collector)?;
+
+    let Some(AggregationResult::BucketResult(BucketResult::Terms { buckets, .. })) =
+        results.0.get("terms")
+    else {
+        return Ok(Vec::new());
+    };
+    Ok(buckets
+        .iter()
+        .filter_map(|bucket| match &bucket.key {
+            Key::Str(term) => Some((term.clone(), bucket.doc_count)),
+            Key::F64(_) => None,
+        })
+        .collect())
+}
+
+/// Replaces query words that appear in no title or content with the most frequent
+/// indexed term within [`MAX_SUGGEST_DISTANCE`] edits. Candidates are found by running a
+/// Levenshtein automaton over the term dictionary, which only visits terms that can still
+/// match.
+fn suggest_query(searcher: &Searcher, search: &SearchState, query: &str) -> Option<String> {
+    let fields: Vec<Field> = ["title", "content"]
+        .iter()
+        .filter_map(|name| search.schema.get_field(name).ok())
+        .collect();
 
-    let index_path = get_latest_index()?;
+    let mut corrected = false;
+    let mut lookups = 0;
+    let words: Vec<String> = query
+        .split_whitespace()
+        .map(|word| {
+            let is_plain_word =
+                word.chars().all(char::is_alphanumeric) && !matches!(word, "AND" | "OR" | "NOT");
+            let lowercase = word.to_lowercase();
+            if !is_plain_word
+                || lowercase.chars().count() < MIN_SUGGEST_TOKEN_CHARS
+                || lookups >= MAX_SUGGEST_WORDS
+                || is_indexed(searcher, &fields, &lowercase)
+            {
+                return word.to_string();
+            }
+            lookups += 1;
+            match closest_term(searcher, &fields, &lowercase) {
+                Some(term) => {
+                    corrected = true;
+                    term
+                }
+                None => word.to_string(),
+            }
+        })
+        .collect();
+
+    corrected.then(|| words.join(" "))
+}
+
+fn is_indexed(searcher: &Searcher, fields: &[Field], word: &str) -> bool {
+    fields.iter().any(|field| {
+        searcher
+            .doc_freq(&Term::from_field_text(*field, word))
+            .is_ok_and(|doc_freq| doc_freq > 0)
+    })
+}
+
+fn closest_term(searcher: &Searcher, fields: &[Field], word: &str) -> Option<String> {
+    static AUTOMATA: OnceLock<LevenshteinAutomatonBuilder> = OnceLock::new();
+    let dfa = LevenshteinDfa(
+        AUTOMATA
+            .get_or_init(|| LevenshteinAutomatonBuilder::new(MAX_SUGGEST_DISTANCE, true))
+            .build_dfa(word),
+    );
+
+    let mut doc_freqs: HashMap<String, u64> = HashMap::new();
+    let mut candidates = 0;
+    'segments: for segment in searcher.segment_readers() {
+        for field in fields {
+            let Ok(inverted_index) = segment.inverted_index(*field) else {
+                continue;
+            };
+            let Ok(mut terms) = inverted_index.terms().search(&dfa).into_stream() else {
+                continue;
+            };
+            while terms.advance() {
+                let Ok(term) = std::str::from_utf8(terms.key()) else {
+                    continue;
+                };
+                *doc_freqs.entry(term.to_string()).or_default() += terms.value().doc_freq as u64;
+                candidates += 1;
+                if candidates >= MAX_SUGGEST_CANDIDATES {
+                    break 'segments;
+                }
+            }
+        }
+    }
+
+    doc_freqs
+        .into_iter()
+        .filter_map(|(term, doc_freq)| match dfa.0.eval(&term) {
+            Distance::Exact(distance) => Some((distance, doc_freq, term)),
+            Distance::AtLeast(_) => None,
+        })
+        .min_by(|a, b| a.0.cmp(&b.0).then(b.1.cmp(&a.1)).then(a.2.cmp(&b.2)))
+        .map(|(_, _, term)| term)
+}
+
+/// Lets a Levenshtein DFA drive a term dictionary stream.
+struct LevenshteinDfa(DFA);
+
+impl Automaton for LevenshteinDfa {
+    type State = u32;
+
+    fn start(&self) -> u32 {
+        self.0.initial_state()
+    }
+
+    fn is_match(&self, state: &u32) -> bool {
+        matches!(self.0.distance(*state), Distance::Exact(_))
+    }
+
+    fn can_match(&self, state: &u32) -> bool {
+        *state != levenshtein_automata::SINK_STATE
+    }
+
+    fn accept(&self, state: &u32, byte: u8) -> u32 {
+        self.0.transition(*state, byte)
+    }
+}
+
+/// Languages a `lang` filter can pick from, with how many documents each has.
+async fn languages_handler(
+    state: axum::extract::State<Arc<AppState>>,
+) -> Result<Json<LanguagesResponse>, (StatusCode, String)> {
+    let Some(search) = state.search.get() else {
+        return Err((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "The index is still loading".to_string(),
+        ));
+    };
+    let languages = language_counts(&search.reader.searcher())
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(LanguagesResponse { languages }))
+}
+
+fn health(state: &AppState, status: &'static str) -> HealthResponse {
+    let search = state.search.get();
+    HealthResponse {
+        status,
+        index_path: search.map(|search| search.index_path.display().to_string()),
+        num_docs: search.map(|search| search.reader.searcher().num_docs()),
+    }
+}
+
+/// Liveness: the process is up and serving requests.
+async fn healthz_handler(state: axum::extract::State<Arc<AppState>>) -> Json<HealthResponse> {
+    Json(health(&state, "ok"))
+}
+
+/// Readiness: 503 until the index has been opened and a reader is available.
+async fn readyz_handler(
+    state: axum::extract::State<Arc<AppState>>,
+) -> (StatusCode, Json<HealthResponse>) {
+    if state.search.get().is_some() {
+        (StatusCode::OK, Json(health(&state, "ready")))
+    } else {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(health(&state, "loading")),
+        )
+    }
+}
+
+fn open_search_state() -> Result<SearchState> {
+    let index_path = latest_index_path()?;
     info!("Using index at: {}", index_path.display());
 
     let index = Index::open_in_dir(&index_path)?;
+    search_state(&index, index_path)
+}
+
+fn search_state(index: &Index, index_path: PathBuf) -> Result<SearchState> {
     let schema = Arc::new(index.schema());
 
     let reader = index.reader()?;
@@ -162,32 +837,443 @@ async fn main() -> Result<()> {
     let meta_field = schema.get_field("meta_tags").unwrap();
     let language_field = schema.get_field("language").unwrap();
 
-    let query_parser = QueryParser::for_index(
-        &index,
-        vec![
-            title_field,
-            content_field,
-            meta_field,
-            language_field,
-        ],
+    // indexes built before headings were indexed lack the field
+    let headings_field = schema.get_field("headings").ok();
+
+    let mut query_parser = QueryParser::for_index(
+        index,
+        [title_field, content_field, meta_field, language_field]
+            .into_iter()
+            .chain(headings_field)
+            .collect(),
     );
+    query_parser.set_field_boost(title_field, TITLE_BOOST);
+    query_parser.set_field_boost(content_field, CONTENT_BOOST);
+    query_parser.set_field_boost(meta_field, META_BOOST);
+    if let Some(headings_field) = headings_field {
+        query_parser.set_field_boost(headings_field, HEADINGS_BOOST);
+    }
 
-    let state = Arc::new(SearchState {
+    Ok(SearchState {
+        index_path,
         reader,
         query_parser,
-        schema: schema.clone(),
+        schema,
+    })
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    tracing_subscriber::fmt().with_env_filter("info").init();
+
+    let state = Arc::new(AppState {
+        lexicon: Lexicon::from_env().map(Arc::new),
+        ..AppState::default()
     });
 
     let app = Router::new()
         .route("/search", get(search_handler))
+        .route("/languages", get(languages_handler))
+        .route("/healthz", get(healthz_handler))
+        .route("/readyz", get(readyz_handler))
         .layer(CorsLayer::permissive())
-        .with_state(state);
+        .with_state(state.clone());
 
     let addr = "0.0.0.0:3000";
     info!("Starting server at http://{}", addr);
 
     let listener = tokio::net::TcpListener::bind(addr).await?;
+
+    // opening a large index takes a while, /readyz reports 503 until it is done
+    tokio::task::spawn_blocking(move || match open_search_state() {
+        Ok(search) => {
+            let _ = state.search.set(search);
+            info!("Index loaded, ready to serve searches");
+        }
+        Err(e) => {
+            tracing::error!("Failed to open the index: {}", e);
+            std::process::exit(1);
+        }
+    });
+
     axum::serve(listener, app).await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulse::indexer::{index_documents, index_schema, DocumentFilter};
+    use serde_json::json;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tantivy::doc;
+    use tantivy::schema::{FAST, STRING, TEXT};
+
+    static BATCHES: AtomicUsize = AtomicUsize::new(0);
+
+    /// Server state over an in-RAM index that the indexer built from `analyses`, given as
+    /// the JSON lines genesis writes. Pages on `nsfw_domains` are flagged as NSFW.
+    async fn indexed_state(
+        schema: Schema,
+        analyses: &[serde_json::Value],
+        nsfw_domains: &[&str],
+    ) -> Arc<AppState> {
+        let dir = std::env::temp_dir().join(format!(
+            "pulse-server-{}-{}",
+            std::process::id(),
+            BATCHES.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("batch.jsonl");
+        let lines: Vec<String> = analyses.iter().map(|line| line.to_string()).collect();
+        std::fs::write(&file, lines.join("\n")).unwrap();
+
+        let index = Index::create_in_ram(schema);
+        let nsfw_domains: HashSet<String> = nsfw_domains.iter().map(|d| d.to_string()).collect();
+        let filter = DocumentFilter {
+            skip_empty: false,
+            languages: None,
+        };
+        index_documents(&[file], &index, &dir, &nsfw_domains, &filter, false)
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let state = AppState::default();
+        let _ = state
+            .search
+            .set(search_state(&index, PathBuf::new()).unwrap());
+        Arc::new(state)
+    }
+
+    async fn app_state(analyses: &[serde_json::Value]) -> Arc<AppState> {
+        indexed_state(index_schema(), analyses, &[]).await
+    }
+
+    /// The indexer's schema with `content` searchable but not stored, as in older indexes.
+    fn without_stored_content() -> Schema {
+        let mut schema = Schema::builder();
+        for (_, entry) in index_schema().fields() {
+            if entry.name() == "content" {
+                schema.add_text_field("content", TEXT);
+            } else {
+                schema.add_field(entry.clone());
+            }
+        }
+        schema.build()
+    }
+
+    async fn search(state: &Arc<AppState>, query_string: &str) -> SearchResponse {
+        let uri = format!("/search?{}", query_string).parse().unwrap();
+        let Json(response) = search_handler(
+            axum::extract::State(state.clone()),
+            Query::try_from_uri(&uri).unwrap(),
+        )
+        .await
+        .unwrap();
+        response
+    }
+
+    fn urls(response: &SearchResponse) -> Vec<&str> {
+        response
+            .results
+            .iter()
+            .map(|result| result.url.as_str())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn analyses_indexed_by_the_indexer_are_served() {
+        let state = app_state(&[json!({
+            "url": "https://www.docs.test/guide",
+            "title": "Tantivy guide",
+            "content_text": "Building a search engine with tantivy takes an afternoon.",
+            "meta_content": "How to build a search engine",
+            "language": "en-GB",
+            "crawled_at": 1741046400,
+            "favicon_url": "https://www.docs.test/favicon.ico",
+            "image_url": "https://www.docs.test/cover.png",
+            "headings": [{"level": 1, "text": "Getting started"}],
+        })])
+        .await;
+
+        let response = search(&state, "q=tantivy").await;
+        assert_eq!(response.total, 1);
+        let result = &response.results[0];
+        assert_eq!(result.url, "https://www.docs.test/guide");
+        assert_eq!(result.title, "Tantivy guide");
+        assert_eq!(result.language, "en-GB");
+        assert_eq!(result.meta_description, "How to build a search engine");
+        assert!(!result.nsfw);
+        assert_eq!((result.word_count, result.reading_time_minutes), (9, 1));
+        assert_eq!(result.crawled_at, 1741046400);
+        assert_eq!(
+            result.favicon_url.as_deref(),
+            Some("https://www.docs.test/favicon.ico")
+        );
+        assert_eq!(
+            result.image_url.as_deref(),
+            Some("https://www.docs.test/cover.png")
+        );
+        assert_eq!(response.domains[0].domain, "docs.test");
+
+        assert_eq!(
+            urls(&search(&state, "q=started").await),
+            [result.url.as_str()]
+        );
+    }
+
+    #[tokio::test]
+    async fn nsfw_pages_are_only_returned_with_safe_search_off() {
+        let state = indexed_state(
+            index_schema(),
+            &[
+                json!({"url": "https://a.test/", "title": "rust guide"}),
+                json!({"url": "https://b.test/", "title": "rust after dark"}),
+            ],
+            &["b.test"],
+        )
+        .await;
+
+        assert_eq!(urls(&search(&state, "q=rust").await), ["https://a.test/"]);
+        assert_eq!(
+            urls(&search(&state, "q=rust&safe=true").await),
+            ["https://a.test/"]
+        );
+        let unsafe_search = search(&state, "q=rust&safe=false").await;
+        let mut found = urls(&unsafe_search);
+        found.sort();
+        assert_eq!(found, ["https://a.test/", "https://b.test/"]);
+        assert_eq!(unsafe_search.total, 2);
+    }
+
+    #[test]
+    fn hits_below_min_score_are_dropped_and_not_counted() {
+        let mut schema = Schema::builder();
+        let title = schema.add_text_field("title", TEXT);
+        let index = Index::create_in_ram(schema.build());
+        let mut writer = index.writer(15_000_000).unwrap();
+        writer
+            .add_document(doc!(title => "rust rust rust"))
+            .unwrap();
+        writer
+            .add_document(
+                doc!(title => "a long title that mentions rust only once among many other words"),
+            )
+            .unwrap();
+        writer.commit().unwrap();
+        let searcher = index.reader().unwrap().searcher();
+        let query = QueryParser::for_index(&index, vec![title])
+            .parse_query("rust")
+            .unwrap();
+
+        let (hits, total) =
+            relevant_hits(&searcher, &*query, TopDocs::with_limit(10), None).unwrap();
+        assert_eq!((hits.len(), total), (2, 2));
+        let threshold = (hits[0].0 + hits[1].0) / 2.0;
+
+        let (filtered, total) =
+            relevant_hits(&searcher, &*query, TopDocs::with_limit(10), Some(threshold)).unwrap();
+        assert_eq!(total, 1);
+        assert_eq!(filtered, hits[..1]);
+    }
+
+    #[test]
+    fn languages_are_counted_over_the_whole_index() {
+        let mut schema = Schema::builder();
+        let language = schema.add_text_field("language", STRING | FAST);
+        let index = Index::create_in_ram(schema.build());
+        let mut writer = index.writer(15_000_000).unwrap();
+        for code in ["en", "de", "en", "en", "de"] {
+            writer.add_document(doc!(language => code)).unwrap();
+        }
+        writer.commit().unwrap();
+
+        let counts = language_counts(&index.reader().unwrap().searcher()).unwrap();
+        assert_eq!(
+            counts,
+            vec![
+                LanguageCount {
+                    language: "en".to_string(),
+                    count: 3
+                },
+                LanguageCount {
+                    language: "de".to_string(),
+                    count: 2
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn previews_highlight_the_query_in_the_content() {
+        let state = app_state(&[json!({
+            "url": "https://a.test/",
+            "title": "Search engines",
+            "content_text": "Full text search in Rust is easy with tantivy and a good schema.",
+            "meta_content": "A page about search",
+        })])
+        .await;
+
+        let response = search(&state, "q=tantivy").await;
+        assert!(
+            response.results[0].preview.contains("<mark>tantivy</mark>"),
+            "{}",
+            response.results[0].preview
+        );
+    }
+
+    #[tokio::test]
+    async fn previews_fall_back_to_the_meta_description_without_stored_content() {
+        let state = indexed_state(
+            without_stored_content(),
+            &[json!({
+                "url": "https://a.test/",
+                "title": "Search engines",
+                "content_text": "Full text search in Rust is easy with tantivy.",
+                "meta_content": "A page about search",
+            })],
+            &[],
+        )
+        .await;
+
+        let response = search(&state, "q=tantivy").await;
+        assert_eq!(response.results[0].preview, "A page about search");
+    }
+
+    #[tokio::test]
+    async fn recent_sort_returns_the_newest_pages_first() {
+        let state = app_state(&[
+            json!({"url": "https://a.test/old", "title": "rust rust rust", "crawled_at": 100}),
+            json!({"url": "https://a.test/new", "title": "rust", "crawled_at": 300}),
+            json!({"url": "https://a.test/mid", "title": "rust rust", "crawled_at": 200}),
+        ])
+        .await;
+
+        let response = search(&state, "q=rust&sort=recent").await;
+        assert_eq!(
+            urls(&response),
+            [
+                "https://a.test/new",
+                "https://a.test/mid",
+                "https://a.test/old"
+            ]
+        );
+        let crawled_at: Vec<u64> = response.results.iter().map(|r| r.crawled_at).collect();
+        assert_eq!(crawled_at, [300, 200, 100]);
+        assert_eq!(response.total, 3);
+    }
+
+    #[tokio::test]
+    async fn misspelled_words_get_a_suggestion() {
+        let state = app_state(&[
+            json!({"url": "https://a.test/", "title": "rust programming guide"}),
+            json!({"url": "https://b.test/", "title": "programming in rust"}),
+            json!({"url": "https://c.test/", "title": "a progressive web app"}),
+        ])
+        .await;
+
+        let response = search(&state, "q=rust%20programing").await;
+        assert_eq!(response.suggestion.as_deref(), Some("rust programming"));
+        let response = search(&state, "q=rust%20porgramming").await;
+        assert_eq!(response.suggestion.as_deref(), Some("rust programming"));
+
+        // nothing close enough, and known words are left alone
+        assert_eq!(search(&state, "q=xylophone").await.suggestion, None);
+        assert_eq!(search(&state, "q=rust").await.suggestion, None);
+    }
+
+    #[tokio::test]
+    async fn title_matches_outrank_body_matches() {
+        let state = app_state(&[
+            json!({
+                "url": "https://body.test/",
+                "title": "weekend notes",
+                "content_text": "gardening gardening gardening and more gardening",
+            }),
+            json!({
+                "url": "https://title.test/",
+                "title": "gardening for beginners",
+                "content_text": "soil, seeds and a little patience",
+            }),
+        ])
+        .await;
+
+        let response = search(&state, "q=gardening").await;
+        assert_eq!(
+            urls(&response),
+            ["https://title.test/", "https://body.test/"]
+        );
+    }
+
+    #[tokio::test]
+    async fn site_filters_results_to_one_domain() {
+        let page = |url: &str| json!({"url": url, "title": "rust"});
+        let state = app_state(&[
+            page("https://a.test/1"),
+            page("https://www.a.test/2"),
+            page("https://b.test/1"),
+        ])
+        .await;
+
+        let all = search(&state, "q=rust").await;
+        assert_eq!(all.total, 3);
+        let domains: Vec<_> = all
+            .domains
+            .iter()
+            .map(|d| (d.domain.as_str(), d.count))
+            .collect();
+        assert_eq!(domains, [("a.test", 2), ("b.test", 1)]);
+
+        for site in ["a.test", "www.a.test", "https://A.test/blog"] {
+            let response = search(&state, &format!("q=rust&site={}", site)).await;
+            let mut found = urls(&response);
+            found.sort();
+            assert_eq!(
+                found,
+                ["https://a.test/1", "https://www.a.test/2"],
+                "{}",
+                site
+            );
+            assert_eq!(response.total, 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn grouping_keeps_at_most_per_domain_hits_per_host() {
+        // a.test's pages rank first, most matches first
+        let page = |url: &str, title: &str| json!({"url": url, "title": title});
+        let state = app_state(&[
+            page("https://a.test/1", "rust rust rust rust"),
+            page("https://a.test/2", "rust rust rust"),
+            page("https://a.test/3", "rust rust"),
+            page("https://b.test/1", "rust and more"),
+            page("https://c.test/1", "rust among many other words here"),
+        ])
+        .await;
+
+        let ungrouped = search(&state, "q=rust").await;
+        assert_eq!(ungrouped.total, 5);
+        assert_eq!(
+            urls(&ungrouped)[..3],
+            ["https://a.test/1", "https://a.test/2", "https://a.test/3"]
+        );
+        let grouped = search(&state, "q=rust&group_by_domain=true&per_domain=2").await;
+        let kept: Vec<_> = urls(&ungrouped)
+            .into_iter()
+            .filter(|url| *url != "https://a.test/3")
+            .collect();
+        assert_eq!(urls(&grouped), kept);
+        assert_eq!(grouped.total, 4);
+
+        let second_page = search(
+            &state,
+            "q=rust&group_by_domain=true&per_domain=1&per_page=2&page=1",
+        )
+        .await;
+        assert_eq!(second_page.total, 3);
+        assert_eq!(second_page.results.len(), 1);
+    }
+}
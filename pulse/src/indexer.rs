@@ -0,0 +1,601 @@
+//! Turns genesis analysis batches into the tantivy index the search server reads.
+
+use anyhow::{bail, Result};
+use glob::glob;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+use tantivy::schema::{Schema, FAST, INDEXED, STORED, STRING, TEXT};
+use tantivy::{doc, Index, Term};
+use tracing::info;
+
+const COMMIT_THRESHOLD: usize = 1000;
+pub const INDEXES_DIR: &str = "pulse_indexes";
+/// JSONL files already ingested into an index, one path per line, kept inside the index directory
+const MANIFEST_FILE: &str = "ingested_files.txt";
+/// Present in an index directory while a fresh build into it has not finished
+pub const BUILDING_MARKER: &str = "building";
+const WORDS_PER_MINUTE: u64 = 200;
+/// Length of the stored `preview`, shown when a search has no snippet to offer
+const PREVIEW_CHARS: usize = 500;
+
+#[derive(Debug, Deserialize)]
+struct JsonlEntry {
+    url: String,
+    title: Option<String>,
+    content_text: Option<String>,
+    meta_content: Option<String>,
+    language: Option<String>,
+    crawled_at: Option<i64>,
+    favicon_url: Option<String>,
+    image_url: Option<String>,
+    #[serde(default)]
+    headings: Vec<HeadingEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HeadingEntry {
+    text: String,
+}
+
+/// Which JSONL entries make it into the index.
+pub struct DocumentFilter {
+    pub skip_empty: bool,
+    /// Primary language subtags to keep, e.g. `en` also keeps `en-US`. `None` keeps every language.
+    pub languages: Option<HashSet<String>>,
+}
+
+impl DocumentFilter {
+    /// Empty pages are skipped unless `INDEX_EMPTY_CONTENT=true`, and `INDEX_LANGUAGES=en,de`
+    /// restricts the index to those languages.
+    pub fn from_env() -> Self {
+        let languages = std::env::var("INDEX_LANGUAGES").ok().map(|value| {
+            value
+                .split(',')
+                .map(|code| code.trim().to_lowercase())
+                .filter(|code| !code.is_empty())
+                .collect()
+        });
+        DocumentFilter {
+            skip_empty: !std::env::var("INDEX_EMPTY_CONTENT").is_ok_and(|value| value == "true"),
+            languages,
+        }
+    }
+
+    fn accepts(&self, entry: &JsonlEntry) -> bool {
+        let content = entry.content_text.as_deref().unwrap_or_default();
+        if self.skip_empty && content.trim().is_empty() {
+            return false;
+        }
+        let Some(languages) = &self.languages else {
+            return true;
+        };
+        // entries without a language are indexed as english
+        let language = entry.language.as_deref().unwrap_or("en");
+        let primary = language.split(['-', '_']).next().unwrap_or_default();
+        languages.contains(&primary.trim().to_lowercase())
+    }
+}
+
+/// The first `max_len` characters of `text`, with an ellipsis when it is longer.
+fn generate_preview(text: &str, max_len: usize) -> String {
+    let text = text.trim();
+    if text.chars().count() <= max_len {
+        text.to_string()
+    } else {
+        let mut preview = text.chars().take(max_len).collect::<String>();
+        preview.push_str("...");
+        preview
+    }
+}
+
+/// Counts whitespace separated tokens, ignoring ones made only of punctuation
+/// such as dashes or ellipses.
+fn count_words(text: &str) -> u64 {
+    text.split_whitespace()
+        .filter(|token| token.chars().any(char::is_alphanumeric))
+        .count() as u64
+}
+
+fn reading_time_minutes(word_count: u64) -> u64 {
+    word_count.div_ceil(WORDS_PER_MINUTE)
+}
+
+pub async fn create_search_index() -> Result<(Index, PathBuf)> {
+    let timestamp = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)?
+        .as_secs();
+
+    let index_path = PathBuf::from(INDEXES_DIR).join(format!("index_{}", timestamp));
+
+    std::fs::create_dir_all(&index_path)?;
+    info!("Creating index at: {}", index_path.display());
+
+    let index = Index::create_in_dir(&index_path, index_schema())?;
+    Ok((index, index_path))
+}
+
+pub fn index_schema() -> Schema {
+    let mut schema_builder = Schema::builder();
+
+    schema_builder.add_text_field("url", TEXT | STORED);
+    // untokenized copy of the url, used to replace documents on incremental updates
+    schema_builder.add_text_field("url_key", STRING);
+    // host without `www.`, for `site` filters and per-domain counts
+    schema_builder.add_text_field("domain", STRING | STORED | FAST);
+    schema_builder.add_text_field("title", TEXT | STORED);
+    schema_builder.add_text_field("content", TEXT | STORED);
+    // searchable only, so matches in a page's outline can be boosted over body text
+    schema_builder.add_text_field("headings", TEXT);
+    schema_builder.add_text_field("preview", STRING | STORED);
+    schema_builder.add_text_field("language", STRING | STORED | FAST);
+    schema_builder.add_text_field("meta_tags", TEXT | STORED);
+    schema_builder.add_bool_field("nsfw", INDEXED | STORED | FAST);
+    schema_builder.add_u64_field("word_count", INDEXED | STORED | FAST);
+    schema_builder.add_u64_field("reading_time_minutes", STORED | FAST);
+    schema_builder.add_u64_field("crawled_at", INDEXED | STORED | FAST);
+    schema_builder.add_text_field("favicon_url", STORED);
+    schema_builder.add_text_field("image_url", STORED);
+
+    schema_builder.build()
+}
+
+/// Opens a batch file, transparently decompressing `.zst` files. Parquet batches written by
+/// genesis with `format = "parquet"` are converted to the same JSON lines.
+fn open_jsonl(path: &Path) -> Result<Box<dyn BufRead>> {
+    let file = File::open(path)?;
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("zst") => Ok(Box::new(BufReader::new(zstd::Decoder::new(file)?))),
+        Some("parquet") => Ok(Box::new(Cursor::new(parquet_to_jsonl(file)?))),
+        _ => Ok(Box::new(BufReader::new(file))),
+    }
+}
+
+fn parquet_to_jsonl(file: File) -> Result<Vec<u8>> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(file)?.build()?;
+    let mut writer = arrow_json::LineDelimitedWriter::new(Vec::new());
+    for batch in reader {
+        writer.write(&batch?)?;
+    }
+    writer.finish()?;
+    Ok(writer.into_inner())
+}
+
+pub fn latest_index_path() -> Result<PathBuf> {
+    let index_dir = PathBuf::from(INDEXES_DIR);
+
+    let latest = std::fs::read_dir(&index_dir)?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().map(|ft| ft.is_dir()).unwrap_or(false))
+        .max_by_key(|entry| entry.path());
+
+    latest
+        .map(|e| e.path())
+        .ok_or_else(|| anyhow::anyhow!("No index found in {}", index_dir.display()))
+}
+
+pub fn load_manifest(index_path: &Path) -> HashSet<String> {
+    std::fs::read_to_string(index_path.join(MANIFEST_FILE))
+        .map(|content| content.lines().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+fn append_manifest(index_path: &Path, files: &[String]) -> Result<()> {
+    use std::io::Write;
+
+    let mut manifest = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(index_path.join(MANIFEST_FILE))?;
+    for file in files {
+        writeln!(manifest, "{}", file)?;
+    }
+    Ok(())
+}
+
+pub async fn check_files_exist(pattern: &str) -> Result<usize> {
+    let mut count = 0;
+    for entry in glob(pattern)? {
+        match entry {
+            Ok(_) => count += 1,
+            Err(e) => tracing::warn!("Error matching pattern: {}", e),
+        }
+    }
+
+    if count == 0 {
+        bail!("No files found matching pattern: {}", pattern);
+    }
+
+    info!("Found {} files to process", count);
+    Ok(count)
+}
+
+/// Files matching `analyses_pattern`, minus the ones listed in `ingested`.
+pub fn pending_files(analyses_pattern: &str, ingested: &HashSet<String>) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in glob(analyses_pattern)? {
+        match entry {
+            Ok(path) if ingested.contains(&path.display().to_string()) => {}
+            Ok(path) => files.push(path),
+            Err(e) => tracing::error!("Error matching glob pattern: {}", e),
+        }
+    }
+    Ok(files)
+}
+
+/// Indexes `files` into the index at `index_path`, committing after each file and
+/// recording it in the manifest so an interrupted run can pick up where it stopped.
+/// With `replace_existing`, documents already indexed under the same url are deleted first.
+pub async fn index_documents(
+    files: &[PathBuf],
+    index: &Index,
+    index_path: &Path,
+    nsfw_domains: &HashSet<String>,
+    filter: &DocumentFilter,
+    replace_existing: bool,
+) -> Result<()> {
+    let start_time = Instant::now();
+    let schema = index.schema();
+    let url_key_field = schema.get_field("url_key").ok();
+    if replace_existing && url_key_field.is_none() {
+        bail!("The latest index predates incremental updates, run without --update to rebuild it");
+    }
+    let mut total_processed = 0;
+    let mut total_skipped = 0;
+
+    let mut index_writer = index.writer_with_num_threads(4, 4 * 1024 * 1024 * 1024)?;
+
+    info!("Starting to process files...");
+    let mut file_count = 0;
+
+    for path in files {
+        file_count += 1;
+        info!("Processing file [{}]: {}", file_count, path.display());
+        let file_start_time = Instant::now();
+        let mut line_count = 0;
+
+        for line in open_jsonl(path)?.lines() {
+            let line = line?;
+            line_count += 1;
+            match serde_json::from_str::<JsonlEntry>(&line) {
+                Ok(entry_data) if !filter.accepts(&entry_data) => total_skipped += 1,
+                Ok(entry_data) => {
+                    let content = entry_data.content_text.as_deref().unwrap_or_default();
+                    let preview = generate_preview(content, PREVIEW_CHARS);
+                    let word_count = count_words(content);
+                    let language = entry_data.language.unwrap_or_else(|| "en".to_string());
+
+                    let title = entry_data.title.as_deref().unwrap_or_default();
+                    let meta = entry_data.meta_content.as_deref().unwrap_or_default();
+
+                    let is_nsfw_content = is_nsfw(content, nsfw_domains)
+                        || is_nsfw(title, nsfw_domains)
+                        || is_nsfw(meta, nsfw_domains)
+                        || is_nsfw(&entry_data.url, nsfw_domains)
+                        || is_nsfw_domain(&entry_data.url, nsfw_domains);
+
+                    if let (true, Some(url_key_field)) = (replace_existing, url_key_field) {
+                        index_writer
+                            .delete_term(Term::from_field_text(url_key_field, &entry_data.url));
+                    }
+
+                    let mut document = doc!(
+                        schema.get_field("url").unwrap() => entry_data.url.clone(),
+                        schema.get_field("title").unwrap() => entry_data.title.unwrap_or_default(),
+                        schema.get_field("content").unwrap() => content,
+                        schema.get_field("preview").unwrap() => preview,
+                        schema.get_field("language").unwrap() => language,
+                        schema.get_field("meta_tags").unwrap() => entry_data.meta_content.unwrap_or_default(),
+                        schema.get_field("nsfw").unwrap() => is_nsfw_content,
+                        schema.get_field("word_count").unwrap() => word_count,
+                        schema.get_field("reading_time_minutes").unwrap() => reading_time_minutes(word_count),
+                        schema.get_field("crawled_at").unwrap() => entry_data.crawled_at.unwrap_or_default().max(0) as u64
+                    );
+                    if let Some(url_key_field) = url_key_field {
+                        document.add_text(url_key_field, &entry_data.url);
+                    }
+                    if let (Ok(domain_field), Some(domain)) =
+                        (schema.get_field("domain"), extract_domain(&entry_data.url))
+                    {
+                        document.add_text(domain_field, domain);
+                    }
+                    if let Ok(headings_field) = schema.get_field("headings") {
+                        for heading in &entry_data.headings {
+                            document.add_text(headings_field, &heading.text);
+                        }
+                    }
+                    for (name, value) in [
+                        ("favicon_url", &entry_data.favicon_url),
+                        ("image_url", &entry_data.image_url),
+                    ] {
+                        if let (Ok(field), Some(value)) = (schema.get_field(name), value) {
+                            document.add_text(field, value);
+                        }
+                    }
+                    index_writer.add_document(document)?;
+
+                    total_processed += 1;
+
+                    if total_processed % COMMIT_THRESHOLD == 0 && index_writer.commit().is_ok() {
+                        let elapsed = start_time.elapsed().as_secs_f64();
+                        let rate = total_processed as f64 / elapsed;
+                        info!(
+                            total_processed,
+                            rate = rate,
+                            "Processing at {:.2} docs/second",
+                            rate
+                        );
+                    }
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to parse JSON line {} in file {}: {}",
+                        line_count,
+                        path.display(),
+                        e
+                    );
+                }
+            }
+        }
+
+        // the manifest only lists files whose documents are all committed
+        index_writer.commit()?;
+        append_manifest(index_path, &[path.display().to_string()])?;
+        info!(
+            "Finished file {} ({} lines) in {:.2}s",
+            path.display(),
+            line_count,
+            file_start_time.elapsed().as_secs_f64()
+        );
+    }
+
+    let total_duration = start_time.elapsed();
+    info!(
+        total_processed,
+        total_skipped,
+        total_files = file_count,
+        duration = format!("{:?}", total_duration),
+        "Indexing completed"
+    );
+    Ok(())
+}
+
+// THE CONTENT BELOW CONTAINS NSFW KEYWORDS
+// DISCRETION ADVISED
+// ====================================================
+// ====================================================
+// ====================================================
+// ====================================================
+// ====================================================
+// ====================================================
+// ====================================================
+// ====================================================
+// ====================================================
+// ====================================================
+// ====================================================
+pub fn load_nsfw_domains() -> Result<HashSet<String>> {
+    let domains = std::fs::read_to_string("top_1m_nsfw_sites.txt")?
+        .lines()
+        .map(|line| line.trim().to_lowercase())
+        .collect();
+    Ok(domains)
+}
+
+fn extract_domain(url: &str) -> Option<String> {
+    url.trim_start_matches("http://")
+        .trim_start_matches("https://")
+        .trim_start_matches("www.")
+        .split('/')
+        .next()
+        .map(|s| s.to_lowercase())
+}
+
+fn is_nsfw_domain(url: &str, nsfw_domains: &HashSet<String>) -> bool {
+    if let Some(domain) = extract_domain(url) {
+        nsfw_domains.contains(&domain)
+    } else {
+        false
+    }
+}
+
+fn is_nsfw(text: &str, nsfw_domains: &HashSet<String>) -> bool {
+    is_nsfw_domain(text, nsfw_domains)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parquet::arrow::ArrowWriter;
+    use std::sync::Arc;
+    use tantivy::collector::TopDocs;
+    use tantivy::query::TermQuery;
+    use tantivy::schema::{IndexRecordOption, Value};
+    use tantivy::TantivyDocument;
+
+    fn entry(content: &str, language: Option<&str>) -> JsonlEntry {
+        serde_json::from_value(serde_json::json!({
+            "url": "https://example.com/",
+            "content_text": content,
+            "language": language,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn empty_content_is_dropped() {
+        let filter = DocumentFilter {
+            skip_empty: true,
+            languages: None,
+        };
+        assert!(!filter.accepts(&entry("", None)));
+        assert!(!filter.accepts(&entry("  \n ", Some("en"))));
+        assert!(filter.accepts(&entry("some words", Some("fr"))));
+    }
+
+    #[test]
+    fn languages_match_on_their_primary_subtag() {
+        let filter = DocumentFilter {
+            skip_empty: true,
+            languages: Some(HashSet::from(["en".to_string(), "de".to_string()])),
+        };
+        assert!(filter.accepts(&entry("hello", Some("en-US"))));
+        assert!(filter.accepts(&entry("hallo", Some("DE"))));
+        assert!(filter.accepts(&entry("hello", None)));
+        assert!(!filter.accepts(&entry("bonjour", Some("fr"))));
+    }
+
+    #[test]
+    fn words_are_counted_across_whitespace_and_punctuation() {
+        assert_eq!(count_words(""), 0);
+        assert_eq!(count_words("  \n\t "), 0);
+        assert_eq!(count_words("one  two\n\nthree\tfour"), 4);
+        // punctuation on its own is not a word, attached to one it is part of it
+        assert_eq!(count_words("Hello, world! -- it's a test ... ok?"), 6);
+        assert_eq!(count_words("— • | 2024 re-run"), 2);
+
+        assert_eq!(reading_time_minutes(0), 0);
+        assert_eq!(reading_time_minutes(1), 1);
+        assert_eq!(reading_time_minutes(WORDS_PER_MINUTE), 1);
+        assert_eq!(reading_time_minutes(WORDS_PER_MINUTE + 1), 2);
+    }
+
+    /// Runs `lines` through [`index_documents`] into a fresh in-memory index.
+    async fn index_lines(name: &str, lines: &[serde_json::Value]) -> Index {
+        let dir = std::env::temp_dir().join(format!("pulse-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("batch.jsonl");
+        let jsonl: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+        std::fs::write(&file, jsonl.join("\n")).unwrap();
+
+        let index = Index::create_in_ram(index_schema());
+        let filter = DocumentFilter {
+            skip_empty: true,
+            languages: None,
+        };
+        index_documents(&[file], &index, &dir, &HashSet::new(), &filter, false)
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        index
+    }
+
+    fn stored_text(index: &Index, url: &str, field: &str) -> String {
+        let searcher = index.reader().unwrap().searcher();
+        let schema = index.schema();
+        let query = TermQuery::new(
+            Term::from_field_text(schema.get_field("url_key").unwrap(), url),
+            IndexRecordOption::Basic,
+        );
+        let (_, address) = searcher.search(&query, &TopDocs::with_limit(1)).unwrap()[0];
+        let doc: TantivyDocument = searcher.doc(address).unwrap();
+        doc.get_first(schema.get_field(field).unwrap())
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn languages_are_stored_and_default_to_english() {
+        let index = index_lines(
+            "language",
+            &[
+                serde_json::json!({"url": "https://example.de/", "content_text": "Hallo Welt", "language": "de"}),
+                serde_json::json!({"url": "https://example.com/", "content_text": "hello world"}),
+            ],
+        )
+        .await;
+
+        assert_eq!(stored_text(&index, "https://example.de/", "language"), "de");
+        assert_eq!(
+            stored_text(&index, "https://example.com/", "language"),
+            "en"
+        );
+    }
+
+    #[tokio::test]
+    async fn previews_are_cut_at_preview_chars() {
+        let content = "é".repeat(PREVIEW_CHARS + 10);
+        let index = index_lines(
+            "preview",
+            &[serde_json::json!({"url": "https://example.com/", "title": "Example", "content_text": content})],
+        )
+        .await;
+
+        let preview = stored_text(&index, "https://example.com/", "preview");
+        assert_eq!(preview.chars().count(), PREVIEW_CHARS + 3);
+        assert!(preview.ends_with("..."));
+        assert_eq!(generate_preview("café", 4), "café");
+    }
+
+    #[tokio::test]
+    async fn parquet_batches_are_indexed_like_jsonl() {
+        let rows = vec![serde_json::json!({
+            "url": "https://example.com/",
+            "title": "Example",
+            "content_text": "hello parquet",
+            "language": "de",
+            "crawled_at": 1741046400,
+            "headings": [{"level": 1, "text": "Welcome"}],
+        })];
+        let schema = Arc::new(
+            arrow_json::reader::infer_json_schema_from_iterator(rows.iter().map(Ok)).unwrap(),
+        );
+        let mut decoder = arrow_json::ReaderBuilder::new(schema.clone())
+            .build_decoder()
+            .unwrap();
+        decoder.serialize(&rows).unwrap();
+        let dir = std::env::temp_dir().join(format!("pulse-parquet-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("batch.parquet");
+        let mut writer = ArrowWriter::try_new(File::create(&file).unwrap(), schema, None).unwrap();
+        writer.write(&decoder.flush().unwrap().unwrap()).unwrap();
+        writer.close().unwrap();
+
+        let index = Index::create_in_ram(index_schema());
+        let filter = DocumentFilter {
+            skip_empty: true,
+            languages: None,
+        };
+        index_documents(&[file], &index, &dir, &HashSet::new(), &filter, false)
+            .await
+            .unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            stored_text(&index, "https://example.com/", "title"),
+            "Example"
+        );
+        assert_eq!(
+            stored_text(&index, "https://example.com/", "language"),
+            "de"
+        );
+    }
+
+    #[test]
+    fn files_listed_in_the_manifest_are_skipped() {
+        let dir = std::env::temp_dir().join(format!("pulse-manifest-{}", std::process::id()));
+        let batches = dir.join("partition=00");
+        std::fs::create_dir_all(&batches).unwrap();
+        for name in ["a.jsonl", "b.jsonl", "c.jsonl"] {
+            std::fs::write(batches.join(name), "").unwrap();
+        }
+        let pattern = format!("{}/partition=*/*.jsonl*", dir.display());
+
+        append_manifest(
+            &dir,
+            &[
+                batches.join("a.jsonl").display().to_string(),
+                batches.join("c.jsonl").display().to_string(),
+            ],
+        )
+        .unwrap();
+        let pending = pending_files(&pattern, &load_manifest(&dir)).unwrap();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(pending, vec![batches.join("b.jsonl")]);
+    }
+}
@@ -0,0 +1,4 @@
+//! Pulse builds and serves Vyntr's search index. The indexer binary and the search
+//! server share the index layout defined here.
+
+pub mod indexer;